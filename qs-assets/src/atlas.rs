@@ -0,0 +1,393 @@
+use crate::{hash_files, input_hasher, BuildCache, BuildReport};
+use anyhow::*;
+use glob::glob;
+use std::collections::HashMap;
+use std::fs::{read_to_string, write, File};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use texture_atlas::*;
+use texture_packer::{
+    exporter::ImageExporter, importer::ImageImporter, texture::Texture, TexturePacker,
+    TexturePackerConfig,
+};
+
+fn render_filename(path: &Path) -> String {
+    path.components()
+        .map(|component| match component {
+            std::path::Component::Prefix(_) => {
+                panic!("prefix not supported");
+            }
+            std::path::Component::RootDir => {
+                panic!("root dir not supported");
+            }
+            std::path::Component::CurDir => {
+                panic!("current dir not supported");
+            }
+            std::path::Component::ParentDir => {
+                panic!("parent dir not supported");
+            }
+            std::path::Component::Normal(name) => name.to_str().unwrap().to_string(),
+        })
+        .fold(
+            String::new(),
+            |l, r| {
+                if l.is_empty() {
+                    r
+                } else {
+                    l + "/" + &r
+                }
+            },
+        )
+}
+
+/// Copies the pixels along each edge of the frame outwards, `amount` pixels deep, into the padding around it.
+/// Pixels outside the frame's corners take the colour of the nearest corner.
+fn extrude_edges(image: &mut image::RgbaImage, frame: texture_packer::Rect, amount: u32) {
+    if frame.w == 0 || frame.h == 0 {
+        return;
+    }
+    let amount = amount as i64;
+    let (x, y, w, h) = (
+        frame.x as i64,
+        frame.y as i64,
+        frame.w as i64,
+        frame.h as i64,
+    );
+    for dy in -amount..h + amount {
+        for dx in -amount..w + amount {
+            let inside = (0..w).contains(&dx) && (0..h).contains(&dy);
+            let (target_x, target_y) = (x + dx, y + dy);
+            if inside
+                || target_x < 0
+                || target_y < 0
+                || target_x >= image.width() as i64
+                || target_y >= image.height() as i64
+            {
+                continue;
+            }
+            let source = *image.get_pixel(
+                (x + dx.max(0).min(w - 1)) as u32,
+                (y + dy.max(0).min(h - 1)) as u32,
+            );
+            image.put_pixel(target_x as u32, target_y as u32, source);
+        }
+    }
+}
+
+/// Packs each directory in `raw_directory` into its own atlas, written to the directory with the same name in `output_directory`.
+/// Directories whose contents haven't changed since they were last packed are skipped.
+pub fn pack_textures(
+    raw_directory: &Path,
+    output_directory: &Path,
+    cache: &mut BuildCache,
+) -> Result<BuildReport> {
+    // Directories may be added to the raw directory.
+    let mut report = BuildReport {
+        inputs: vec![raw_directory.to_path_buf()],
+        ..Default::default()
+    };
+
+    for entry in std::fs::read_dir(raw_directory)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let name = entry.file_name();
+        let name = name
+            .to_str()
+            .with_context(|| format!("Invalid directory name {:?}", name))?;
+
+        // Every file in the directory is an input, including the config and sidecar files.
+        let pattern = entry.path().join("**").join("*");
+        let mut inputs = Vec::new();
+        for path in glob(pattern.to_str().context("Invalid path")?)? {
+            let path = path?;
+            if path.is_file() {
+                inputs.push(path);
+            }
+        }
+        inputs.sort();
+
+        let mut hasher = input_hasher();
+        hash_files(&inputs)?.hash(&mut hasher);
+        let input_hash = hasher.finish();
+        report.inputs.extend(inputs);
+        report.inputs.push(entry.path());
+
+        let step = format!("atlas {}", entry.path().display());
+        if !cache.is_fresh(&step, input_hash) {
+            let outputs = pack_directory(&entry.path(), &output_directory.join(name))?;
+            report.outputs.extend(outputs.iter().cloned());
+            cache.record(step, input_hash, outputs)?;
+        }
+    }
+    Ok(report)
+}
+
+/// Packs every image in `raw_directory` and its subdirectories into an atlas in `output_directory`.
+/// Images in subdirectories are named by their path relative to the directory, such as `icons/sword.png`.
+/// Returns the paths of the files that were written.
+pub fn pack_directory(raw_directory: &Path, output_directory: &Path) -> Result<Vec<PathBuf>> {
+    let config_path = raw_directory.join("pack.json");
+    let config: PackConfig = if config_path.exists() {
+        serde_json::from_str(&read_to_string(&config_path)?)
+            .with_context(|| format!("Invalid packing config {}", config_path.display()))?
+    } else {
+        PackConfig::default()
+    };
+    let packer_config = TexturePackerConfig {
+        max_width: config.max_page_width,
+        max_height: config.max_page_height,
+        allow_rotation: config.allow_rotation,
+        // Extruded pixels from neighbouring images must not overlap.
+        border_padding: config.padding.max(config.extrude),
+        texture_padding: config.padding.max(2 * config.extrude),
+        trim: config.trim,
+        ..Default::default()
+    };
+
+    // If the textures don't fit on a single page, start a new page.
+    let mut packers = vec![TexturePacker::new_skyline(packer_config)];
+
+    // The nine-patch margins of each texture, if it has any.
+    let mut nine_patches = HashMap::new();
+
+    let pattern = raw_directory.join("**").join("*.png");
+    let pattern = pattern
+        .to_str()
+        .with_context(|| format!("Invalid path {}", raw_directory.display()))?;
+    let raw_directory_canonical = raw_directory.canonicalize()?;
+    for path in glob(pattern)? {
+        let path = path?;
+        let mut texture = ImageImporter::import_from_file(&path).unwrap();
+        let canonical_path = path.canonicalize()?;
+        let mut name = render_filename(canonical_path.strip_prefix(&raw_directory_canonical)?);
+
+        if name.ends_with(".9.png") {
+            // This is an Android-style nine-patch, so read the margins from the markers in its border, then remove the border.
+            let rgba = image::RgbaImage::from_fn(texture.width(), texture.height(), |x, y| {
+                image::GenericImageView::get_pixel(&texture, x, y)
+            });
+            let margins =
+                NinePatchMargins::from_border_markers(rgba.width(), rgba.height(), |x, y| {
+                    rgba.get_pixel(x, y).0 == [0, 0, 0, 255]
+                })
+                .with_context(|| format!("Nine-patch {} has no border markers", path.display()))?;
+            texture = image::DynamicImage::ImageRgba8(
+                image::imageops::crop_imm(&rgba, 1, 1, rgba.width() - 2, rgba.height() - 2)
+                    .to_image(),
+            );
+            name = name.replace(".9.png", ".png");
+            nine_patches.insert(name.clone(), margins);
+        } else {
+            // The margins may instead be given in a sidecar file `<name>.9.json`.
+            let sidecar_path = path.with_extension("9.json");
+            if sidecar_path.exists() {
+                let margins: NinePatchMargins =
+                    serde_json::from_str(&read_to_string(&sidecar_path)?).with_context(|| {
+                        format!("Invalid nine-patch file {}", sidecar_path.display())
+                    })?;
+                nine_patches.insert(name.clone(), margins);
+            }
+        }
+
+        if !packers.last().unwrap().can_pack(&texture) {
+            packers.push(TexturePacker::new_skyline(packer_config));
+        }
+        packers
+            .last_mut()
+            .unwrap()
+            .pack_own(name, texture)
+            .map_err(|_| anyhow!("{} is too large to fit in a page", path.display()))?;
+    }
+
+    if packers.iter().all(|packer| packer.get_frames().is_empty()) {
+        // There are no images in this directory, so there's nothing to pack.
+        return Ok(Vec::new());
+    }
+    std::fs::create_dir_all(output_directory)?;
+
+    let mut outputs = Vec::new();
+    let mut pages = Vec::new();
+    for (index, packer) in packers.iter().enumerate() {
+        // Save the packed image.
+        let image = format!("atlas_{}.png", index);
+        let mut exporter = ImageExporter::export(packer).unwrap();
+        if config.extrude > 0 {
+            let pixels = exporter
+                .as_mut_rgba8()
+                .context("Packed image is not RGBA")?;
+            for frame in packer.get_frames().values() {
+                extrude_edges(pixels, frame.frame, config.extrude);
+            }
+        }
+        let image_path = output_directory.join(&image);
+        let mut file = File::create(&image_path).unwrap();
+        outputs.push(image_path);
+        exporter
+            .write_to(&mut file, image::ImageFormat::Png)
+            .unwrap();
+
+        // Save the page information.
+        let mut frames = HashMap::new();
+        for (name, frame) in packer.get_frames() {
+            frames.insert(
+                name.clone(),
+                TextureRegionInformation {
+                    frame: Rect {
+                        x: frame.frame.x,
+                        y: frame.frame.y,
+                        w: frame.frame.w,
+                        h: frame.frame.h,
+                    },
+                    rotated: frame.rotated,
+                    trimmed: frame.trimmed,
+                    source: Rect {
+                        x: frame.source.x,
+                        y: frame.source.y,
+                        w: frame.source.w,
+                        h: frame.source.h,
+                    },
+                    nine_patch: nine_patches.get(name).copied(),
+                    pivot: None,
+                },
+            );
+        }
+        pages.push(TextureAtlasPage {
+            image,
+            width: packer.width(),
+            height: packer.height(),
+            frames,
+        });
+    }
+
+    // Frames named like `walk_0.png`, `walk_1.png` make up animations.
+    // These can be configured with an optional sidecar file `walk.anim.json`.
+    let mut animations = HashMap::new();
+    let frame_names = pages
+        .iter()
+        .flat_map(|page| page.frames.keys().map(String::as_str));
+    for (name, frames) in group_animation_frames(frame_names) {
+        let sidecar_path = raw_directory.join(format!("{}.anim.json", name));
+        let sidecar = if sidecar_path.exists() {
+            let sidecar: AnimationSidecar = serde_json::from_str(&read_to_string(&sidecar_path)?)
+                .with_context(|| {
+                format!("Invalid animation file {}", sidecar_path.display())
+            })?;
+            Some(sidecar)
+        } else {
+            None
+        };
+        animations.insert(name, Animation::new(frames, sidecar.as_ref()));
+    }
+
+    let atlas = TextureAtlas {
+        pages,
+        animations,
+        texture_options: config.texture_options,
+    };
+    if let Err(errors) = atlas.validate() {
+        let errors = errors.iter().map(ToString::to_string).collect::<Vec<_>>();
+        bail!(
+            "The atlas for {} is invalid:\n{}",
+            raw_directory.display(),
+            errors.join("\n")
+        );
+    }
+    // The game loads the compact binary atlas, but we also write the JSON version to make debugging easier.
+    let binary_path = output_directory.join("atlas.bin");
+    write(&binary_path, atlas.to_binary())?;
+    let json_path = output_directory.join("atlas.json");
+    let atlas_file = File::create(&json_path).unwrap();
+    serde_json::to_writer(&atlas_file, &atlas)?;
+    outputs.push(binary_path);
+    outputs.push(json_path);
+
+    Ok(outputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edges_are_extruded_into_padding() {
+        let directory = std::env::temp_dir().join("qs-assets-extrude-test");
+        let raw_directory = directory.join("raw");
+        let output_directory = directory.join("packed");
+        std::fs::create_dir_all(&raw_directory).unwrap();
+        write(
+            raw_directory.join("pack.json"),
+            r#"{ "extrude": 1, "padding": 1, "trim": false, "allow_rotation": false }"#,
+        )
+        .unwrap();
+
+        // A two by two image with a different colour in each corner.
+        let colours = [
+            [255, 0, 0, 255],
+            [0, 255, 0, 255],
+            [0, 0, 255, 255],
+            [255, 255, 255, 255],
+        ];
+        let square =
+            image::RgbaImage::from_fn(2, 2, |x, y| image::Rgba(colours[(y * 2 + x) as usize]));
+        square.save(raw_directory.join("square.png")).unwrap();
+
+        pack_directory(&raw_directory, &output_directory).unwrap();
+        let atlas: TextureAtlas =
+            serde_json::from_str(&read_to_string(output_directory.join("atlas.json")).unwrap())
+                .unwrap();
+        let frame = atlas.pages[0].frames["square.png"].frame;
+        let page = image::open(output_directory.join("atlas_0.png")).unwrap();
+        let page = page.as_rgba8().unwrap();
+        let pixel = |x: u32, y: u32| page.get_pixel(x, y).0;
+
+        // Each pixel around the edge has the colour of the nearest pixel of the image.
+        assert_eq!(pixel(frame.x - 1, frame.y - 1), colours[0]);
+        assert_eq!(pixel(frame.x, frame.y - 1), colours[0]);
+        assert_eq!(pixel(frame.x + 2, frame.y), colours[1]);
+        assert_eq!(pixel(frame.x - 1, frame.y + 1), colours[2]);
+        assert_eq!(pixel(frame.x + 2, frame.y + 2), colours[3]);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn padding_separates_images() {
+        // Packs two images with the given padding, and returns the gap between them.
+        let gap = |padding: u32| {
+            let directory =
+                std::env::temp_dir().join(format!("qs-assets-padding-test-{}", padding));
+            let raw_directory = directory.join("raw");
+            let output_directory = directory.join("packed");
+            std::fs::create_dir_all(&raw_directory).unwrap();
+            write(
+                raw_directory.join("pack.json"),
+                format!(
+                    r#"{{ "padding": {}, "trim": false, "allow_rotation": false }}"#,
+                    padding
+                ),
+            )
+            .unwrap();
+            let square = image::RgbaImage::from_pixel(4, 4, image::Rgba([255, 255, 255, 255]));
+            square.save(raw_directory.join("a.png")).unwrap();
+            square.save(raw_directory.join("b.png")).unwrap();
+
+            pack_directory(&raw_directory, &output_directory).unwrap();
+            let atlas: TextureAtlas =
+                serde_json::from_str(&read_to_string(output_directory.join("atlas.json")).unwrap())
+                    .unwrap();
+            let mut frames = atlas.pages[0]
+                .frames
+                .values()
+                .map(|info| info.frame)
+                .collect::<Vec<_>>();
+            frames.sort_by_key(|frame| (frame.x, frame.y));
+            let (a, b) = (frames[0], frames[1]);
+            std::fs::remove_dir_all(directory).unwrap();
+            (b.x as i64 - (a.x + a.w) as i64).max(b.y as i64 - (a.y + a.h) as i64)
+        };
+
+        assert_eq!(gap(0), 0);
+        assert_eq!(gap(5), 5);
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::*;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::{read, read_to_string, write};
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use texture_atlas::BINARY_ATLAS_VERSION;
+
+/// Remembers the hashes of the inputs and outputs of each build step from the last time it ran,
+/// so that steps whose inputs haven't changed and whose outputs are still intact can be skipped.
+///
+/// The hashes come from `DefaultHasher`, which gives the same results every time the pipeline runs.
+/// If a different compiler version hashes differently, every step will simply run again.
+#[derive(Default)]
+pub struct BuildCache {
+    /// Where the cache is saved, if anywhere.
+    path: Option<PathBuf>,
+    /// Maps the name of each step to the hash of its inputs, the files it wrote, and the hash of those files.
+    entries: HashMap<String, (u64, Vec<PathBuf>, u64)>,
+}
+
+impl BuildCache {
+    /// Creates an empty cache that is only kept in memory, so every step runs the first time it is seen.
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Loads the cache from the given file. If the file is missing or invalid, the cache starts empty.
+    pub fn load(path: PathBuf) -> Self {
+        let entries = read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Self {
+            path: Some(path),
+            entries,
+        }
+    }
+
+    /// Saves the cache to the file it was loaded from. Does nothing if the cache is only kept in memory.
+    pub fn save(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            write(path, serde_json::to_string(&self.entries)?)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the step ran with the same inputs last time, and the files it wrote haven't changed since.
+    pub fn is_fresh(&self, step: &str, input_hash: u64) -> bool {
+        match self.entries.get(step) {
+            Some((last_input_hash, outputs, output_hash)) => {
+                *last_input_hash == input_hash
+                    && hash_files(outputs).ok().as_ref() == Some(output_hash)
+            }
+            None => false,
+        }
+    }
+
+    /// Records that the step ran with the given inputs, and wrote the given files.
+    pub fn record(&mut self, step: String, input_hash: u64, outputs: Vec<PathBuf>) -> Result<()> {
+        let output_hash = hash_files(&outputs)?;
+        self.entries
+            .insert(step, (input_hash, outputs, output_hash));
+        Ok(())
+    }
+}
+
+/// What a build step did.
+#[derive(Debug, Default)]
+pub struct BuildReport {
+    /// Every file and directory the step read. If any of these change, the step should be run again.
+    pub inputs: Vec<PathBuf>,
+    /// The files the step wrote. Outputs of parts of the step that were skipped are not included.
+    pub outputs: Vec<PathBuf>,
+}
+
+impl BuildReport {
+    /// Adds the inputs and outputs of another step to this report.
+    pub fn extend(&mut self, other: BuildReport) {
+        self.inputs.extend(other.inputs);
+        self.outputs.extend(other.outputs);
+    }
+}
+
+/// Creates a hasher for the inputs of a build step. Changing the code that processes the inputs changes every hash,
+/// since the way the inputs are processed may have changed.
+pub(crate) fn input_hasher() -> DefaultHasher {
+    let mut hasher = DefaultHasher::new();
+    include_str!("atlas.rs").hash(&mut hasher);
+    include_str!("distance_field.rs").hash(&mut hasher);
+    include_str!("shaders.rs").hash(&mut hasher);
+    BINARY_ATLAS_VERSION.hash(&mut hasher);
+    hasher
+}
+
+/// Hashes the names and contents of the given files.
+pub(crate) fn hash_files(paths: &[PathBuf]) -> Result<u64> {
+    let mut hasher = DefaultHasher::new();
+    for path in paths {
+        path.hash(&mut hasher);
+        read(path)?.hash(&mut hasher);
+    }
+    Ok(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_are_stale_when_outputs_change() {
+        let output = std::env::temp_dir().join("qs-assets-cache-test.txt");
+        write(&output, "compiled").unwrap();
+
+        let mut cache = BuildCache::in_memory();
+        assert!(!cache.is_fresh("step", 1));
+        cache
+            .record("step".to_string(), 1, vec![output.clone()])
+            .unwrap();
+        assert!(cache.is_fresh("step", 1));
+        assert!(!cache.is_fresh("step", 2));
+
+        write(&output, "edited").unwrap();
+        assert!(!cache.is_fresh("step", 1));
+        std::fs::remove_file(output).unwrap();
+    }
+}
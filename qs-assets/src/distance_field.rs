@@ -0,0 +1,433 @@
+use crate::{hash_files, input_hasher, BuildCache, BuildReport};
+use anyhow::*;
+use glob::glob;
+use image::RgbaImage;
+use rusttype::{point, Font, Scale};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::{read, read_to_string, write};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use texture_atlas::{BitmapFont, BitmapGlyph, Rect};
+use texture_packer::{texture::Texture, TexturePacker, TexturePackerConfig};
+
+/// Each glyph is rasterised at this many times the size it's baked at, so that the distance to its outline
+/// can be measured more precisely than to the nearest pixel.
+const OVERSAMPLING: i32 = 4;
+
+/// How to bake a signed distance field font from a vector font, read from a `<name>.sdf.json` file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DistanceFieldFontConfig {
+    /// The `.ttf` file to bake, relative to the config file.
+    pub font: PathBuf,
+    /// The size of the em square in pixels. Larger sizes keep more detail, such as sharp corners.
+    #[serde(default = "default_size")]
+    pub size: u32,
+    /// How far in pixels the field extends either side of each glyph's outline.
+    /// This must be large enough that the field doesn't run out when the text is drawn much smaller than `size`.
+    #[serde(default = "default_spread")]
+    pub spread: u32,
+    /// The characters to bake. Missing characters fall back to the next font in a font family.
+    #[serde(default = "default_characters")]
+    pub characters: String,
+    /// The largest width and height of a page. If the glyphs don't fit on one page, more pages are added.
+    #[serde(default = "default_max_page_size")]
+    pub max_page_size: u32,
+}
+
+fn default_size() -> u32 {
+    48
+}
+
+fn default_spread() -> u32 {
+    6
+}
+
+/// Printable ASCII and the replacement character.
+fn default_characters() -> String {
+    (' '..='~').chain(std::iter::once('\u{FFFD}')).collect()
+}
+
+fn default_max_page_size() -> u32 {
+    1024
+}
+
+/// Bakes a distance field font for each `<name>.sdf.json` file in `raw_directory`, written to `<name>.sdf.fnt`
+/// and its pages in `output_directory`. Fonts whose config and `.ttf` file haven't changed are skipped.
+pub fn bake_distance_field_fonts(
+    raw_directory: &Path,
+    output_directory: &Path,
+    cache: &mut BuildCache,
+) -> Result<BuildReport> {
+    let mut report = BuildReport {
+        inputs: vec![raw_directory.to_path_buf()],
+        ..Default::default()
+    };
+
+    let pattern = raw_directory.join("*.sdf.json");
+    for config_path in glob(pattern.to_str().context("Invalid path")?)? {
+        let config_path = config_path?;
+        let config: DistanceFieldFontConfig = serde_json::from_str(&read_to_string(&config_path)?)
+            .with_context(|| format!("Invalid distance field font {}", config_path.display()))?;
+        let font_path = config_path.with_file_name(&config.font);
+        let inputs = vec![config_path.clone(), font_path];
+
+        let mut hasher = input_hasher();
+        hash_files(&inputs)?.hash(&mut hasher);
+        let input_hash = hasher.finish();
+        report.inputs.extend(inputs);
+
+        let step = format!("distance field font {}", config_path.display());
+        if !cache.is_fresh(&step, input_hash) {
+            let outputs = bake_distance_field_font(&config_path, output_directory)?;
+            report.outputs.extend(outputs.iter().cloned());
+            cache.record(step, input_hash, outputs)?;
+        }
+    }
+    Ok(report)
+}
+
+/// Bakes the distance field font described by the config file at `config_path`, which is named like `<name>.sdf.json`.
+/// The font is written to `<name>.sdf.fnt` in `output_directory`, and its pages to `<name>_sdf_0.png`, `<name>_sdf_1.png` and so on.
+/// Returns the paths of the files that were written.
+pub fn bake_distance_field_font(
+    config_path: &Path,
+    output_directory: &Path,
+) -> Result<Vec<PathBuf>> {
+    let config: DistanceFieldFontConfig = serde_json::from_str(&read_to_string(config_path)?)
+        .with_context(|| format!("Invalid distance field font {}", config_path.display()))?;
+    let name = config_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.strip_suffix(".sdf.json"))
+        .with_context(|| {
+            format!(
+                "{} is not named like <name>.sdf.json",
+                config_path.display()
+            )
+        })?;
+    let font_path = config_path.with_file_name(&config.font);
+    let font = Font::try_from_vec(read(&font_path)?)
+        .with_context(|| format!("Invalid font {}", font_path.display()))?;
+
+    let scale = Scale::uniform(config.size as f32);
+    let v_metrics = font.v_metrics(scale);
+    let base = v_metrics.ascent.round() as i32;
+    let line_height = (v_metrics.ascent - v_metrics.descent + v_metrics.line_gap).round() as u32;
+
+    let mut images = HashMap::new();
+    let mut glyphs = HashMap::new();
+    for c in config.characters.chars() {
+        let glyph = font.glyph(c);
+        // Don't bake the font's `.notdef` glyph in place of every missing character.
+        if glyph.id().0 == 0 {
+            continue;
+        }
+        let x_advance = glyph.scaled(scale).h_metrics().advance_width.round() as i32;
+        let (image, x_offset, y_offset) =
+            match glyph_distance_field(&font, c, config.size as f32, config.spread as i32) {
+                Some(field) => field,
+                None => (RgbaImage::new(0, 0), 0, 0),
+            };
+        glyphs.insert(
+            c,
+            BitmapGlyph {
+                frame: Rect {
+                    x: 0,
+                    y: 0,
+                    w: image.width(),
+                    h: image.height(),
+                },
+                page: 0,
+                x_offset,
+                y_offset: y_offset + base,
+                x_advance,
+            },
+        );
+        images.insert(c, image);
+    }
+
+    let mut kernings = HashMap::new();
+    for first in glyphs.keys() {
+        for second in glyphs.keys() {
+            let amount = font.pair_kerning(scale, *first, *second).round() as i32;
+            if amount != 0 {
+                kernings.insert((*first, *second), amount);
+            }
+        }
+    }
+
+    // Pack the glyphs in a consistent order, so the same font always produces the same pages.
+    let mut characters = images
+        .iter()
+        .filter(|(_, image)| image.width() > 0 && image.height() > 0)
+        .map(|(c, _)| *c)
+        .collect::<Vec<_>>();
+    characters.sort_unstable();
+    let packer_config = TexturePackerConfig {
+        max_width: config.max_page_size,
+        max_height: config.max_page_size,
+        allow_rotation: false,
+        border_padding: 1,
+        texture_padding: 1,
+        trim: false,
+        ..Default::default()
+    };
+    let mut packers = vec![TexturePacker::new_skyline(packer_config)];
+    for c in &characters {
+        let image = image::DynamicImage::ImageRgba8(images[c].clone());
+        if !packers.last().unwrap().can_pack(&image) {
+            packers.push(TexturePacker::new_skyline(packer_config));
+        }
+        packers
+            .last_mut()
+            .unwrap()
+            .pack_own(BitmapFont::glyph_region_name(*c), image)
+            .map_err(|_| anyhow!("Glyph {:?} is too large to fit in a page", c))?;
+    }
+
+    // Every page of a bitmap font has the same size.
+    let page_size = packers.iter().fold((1, 1), |(width, height), packer| {
+        (width.max(packer.width()), height.max(packer.height()))
+    });
+    let mut pages = Vec::new();
+    let mut outputs = Vec::new();
+    std::fs::create_dir_all(output_directory)?;
+    for (index, packer) in packers.iter().enumerate() {
+        let mut page =
+            RgbaImage::from_pixel(page_size.0, page_size.1, image::Rgba([255, 255, 255, 0]));
+        for c in &characters {
+            if let Some(frame) = packer.get_frame(&BitmapFont::glyph_region_name(*c)) {
+                image::imageops::replace(&mut page, &images[c], frame.frame.x, frame.frame.y);
+                let glyph = glyphs.get_mut(c).unwrap();
+                glyph.frame.x = frame.frame.x;
+                glyph.frame.y = frame.frame.y;
+                glyph.page = index;
+            }
+        }
+        let file = format!("{}_sdf_{}.png", name, index);
+        let page_path = output_directory.join(&file);
+        page.save(&page_path)
+            .with_context(|| format!("Could not write {}", page_path.display()))?;
+        outputs.push(page_path);
+        pages.push(file);
+    }
+
+    let bitmap_font = BitmapFont {
+        face: font_path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(name)
+            .to_string(),
+        size: config.size,
+        line_height,
+        base: base as u32,
+        page_size,
+        pages,
+        glyphs,
+        kernings,
+        distance_range: Some(2 * config.spread),
+    };
+    if let Err(errors) = bitmap_font.to_atlas().validate() {
+        let errors = errors.iter().map(ToString::to_string).collect::<Vec<_>>();
+        bail!(
+            "The distance field font {} is invalid:\n{}",
+            config_path.display(),
+            errors.join("\n")
+        );
+    }
+    let font_file = output_directory.join(format!("{}.sdf.fnt", name));
+    write(&font_file, bitmap_font.to_text())?;
+    outputs.push(font_file);
+    Ok(outputs)
+}
+
+/// Computes the signed distance field of a character's glyph at the given size, or returns `None` if the glyph has no outline.
+/// Returns the field, whose alpha is 0.5 on the outline and increases inwards, along with where its top left corner is
+/// relative to the glyph's origin on the baseline. The field extends `spread` pixels past the outline on every side.
+fn glyph_distance_field(
+    font: &Font,
+    c: char,
+    size: f32,
+    spread: i32,
+) -> Option<(RgbaImage, i32, i32)> {
+    let glyph = font
+        .glyph(c)
+        .scaled(Scale::uniform(size * OVERSAMPLING as f32))
+        .positioned(point(0.0, 0.0));
+    let bounds = glyph.pixel_bounding_box()?;
+
+    // The field's edges in baked pixels, rounded outwards so that the oversampled glyph fits inside them.
+    let left = bounds.min.x.div_euclid(OVERSAMPLING) - spread;
+    let top = bounds.min.y.div_euclid(OVERSAMPLING) - spread;
+    let right = -(-bounds.max.x).div_euclid(OVERSAMPLING) + spread;
+    let bottom = -(-bounds.max.y).div_euclid(OVERSAMPLING) + spread;
+    let (width, height) = ((right - left) as usize, (bottom - top) as usize);
+
+    let oversampled_width = width * OVERSAMPLING as usize;
+    let oversampled_height = height * OVERSAMPLING as usize;
+    let mut inside = vec![false; oversampled_width * oversampled_height];
+    let (offset_x, offset_y) = (
+        (bounds.min.x - left * OVERSAMPLING) as usize,
+        (bounds.min.y - top * OVERSAMPLING) as usize,
+    );
+    glyph.draw(|x, y, coverage| {
+        let index = (y as usize + offset_y) * oversampled_width + x as usize + offset_x;
+        inside[index] = coverage >= 0.5;
+    });
+
+    // The distance from each oversampled pixel's centre to the outline, which lies halfway between
+    // neighbouring pixels on either side of it. This is positive inside the glyph.
+    let to_inside = squared_distances(oversampled_width, oversampled_height, |i| inside[i]);
+    let to_outside = squared_distances(oversampled_width, oversampled_height, |i| !inside[i]);
+    let signed_distance = |i: usize| {
+        if inside[i] {
+            to_outside[i].sqrt() - 0.5
+        } else {
+            0.5 - to_inside[i].sqrt()
+        }
+    };
+
+    let image = RgbaImage::from_fn(width as u32, height as u32, |x, y| {
+        let mut sum = 0.0;
+        for dy in 0..OVERSAMPLING as usize {
+            for dx in 0..OVERSAMPLING as usize {
+                let oversampled_x = x as usize * OVERSAMPLING as usize + dx;
+                let oversampled_y = y as usize * OVERSAMPLING as usize + dy;
+                sum += signed_distance(oversampled_y * oversampled_width + oversampled_x);
+            }
+        }
+        let distance = sum / (OVERSAMPLING * OVERSAMPLING * OVERSAMPLING) as f64;
+        let alpha = (0.5 + distance / (2 * spread) as f64).clamp(0.0, 1.0);
+        image::Rgba([255, 255, 255, (alpha * 255.0).round() as u8])
+    });
+    Some((image, left, top))
+}
+
+/// A distance larger than any in a glyph, which stands in for infinity without producing `NaN`s when subtracted.
+const FAR: f64 = 1e20;
+
+/// Finds the squared distance from each pixel of an image to the nearest pixel for which `is_target` is true,
+/// using the linear-time algorithm from Felzenszwalb and Huttenlocher's "Distance Transforms of Sampled Functions".
+fn squared_distances(width: usize, height: usize, is_target: impl Fn(usize) -> bool) -> Vec<f64> {
+    let mut distances = (0..width * height)
+        .map(|i| if is_target(i) { 0.0 } else { FAR })
+        .collect::<Vec<_>>();
+
+    // The distance transform of an image is the transform of its columns, followed by the transform of its rows.
+    let mut line = vec![0.0; width.max(height)];
+    for x in 0..width {
+        for y in 0..height {
+            line[y] = distances[y * width + x];
+        }
+        let transformed = squared_distances_1d(&line[..height]);
+        for y in 0..height {
+            distances[y * width + x] = transformed[y];
+        }
+    }
+    for y in 0..height {
+        let transformed = squared_distances_1d(&distances[y * width..(y + 1) * width]);
+        distances[y * width..(y + 1) * width].copy_from_slice(&transformed);
+    }
+    distances
+}
+
+/// The one-dimensional distance transform: the minimum over `q` of `(p - q)^2 + f[q]` for each `p`.
+/// This finds the lower envelope of the parabolas rooted at each `q`, then reads off the envelope.
+fn squared_distances_1d(f: &[f64]) -> Vec<f64> {
+    let n = f.len();
+    if n == 0 {
+        return Vec::new();
+    }
+    // The roots of the parabolas in the envelope, and the boundaries between them.
+    let mut roots = vec![0; n];
+    let mut boundaries = vec![0.0; n + 1];
+    let mut k = 0;
+    boundaries[0] = -FAR;
+    boundaries[1] = FAR;
+    for q in 1..n {
+        let intersection = |p: usize| {
+            ((f[q] + (q * q) as f64) - (f[p] + (p * p) as f64)) / (2.0 * q as f64 - 2.0 * p as f64)
+        };
+        // Remove the parabolas that are hidden by the new one.
+        let mut s = intersection(roots[k]);
+        while s <= boundaries[k] {
+            k -= 1;
+            s = intersection(roots[k]);
+        }
+        k += 1;
+        roots[k] = q;
+        boundaries[k] = s;
+        boundaries[k + 1] = FAR;
+    }
+
+    let mut k = 0;
+    (0..n)
+        .map(|p| {
+            while boundaries[k + 1] < p as f64 {
+                k += 1;
+            }
+            let q = roots[k];
+            let offset = p as f64 - q as f64;
+            offset * offset + f[q]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances_are_found_in_both_directions() {
+        // A single target pixel in the middle of a five by three image.
+        let distances = squared_distances(5, 3, |i| i == 7);
+        assert_eq!(
+            distances,
+            vec![5.0, 2.0, 1.0, 2.0, 5.0, 4.0, 1.0, 0.0, 1.0, 4.0, 5.0, 2.0, 1.0, 2.0, 5.0]
+        );
+    }
+
+    #[test]
+    fn fonts_are_baked() {
+        let directory = std::env::temp_dir().join("qs-assets-distance-field-test");
+        std::fs::create_dir_all(&directory).unwrap();
+        let font = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../qs-client/assets/NotoSans-Regular.ttf")
+            .canonicalize()
+            .unwrap();
+        let config_path = directory.join("noto.sdf.json");
+        write(
+            &config_path,
+            format!(
+                r#"{{ "font": {:?}, "size": 32, "spread": 4, "characters": "I " }}"#,
+                font
+            ),
+        )
+        .unwrap();
+
+        let outputs = bake_distance_field_font(&config_path, &directory).unwrap();
+        assert_eq!(
+            outputs,
+            vec![
+                directory.join("noto_sdf_0.png"),
+                directory.join("noto.sdf.fnt")
+            ]
+        );
+        let font =
+            BitmapFont::parse(&read_to_string(directory.join("noto.sdf.fnt")).unwrap()).unwrap();
+        assert_eq!(font.distance_range, Some(8));
+        assert_eq!(font.glyphs[&' '].frame.w, 0);
+
+        // The middle of the stem of the `I` is inside the outline, and the corners of its field are well outside it.
+        let glyph = font.glyphs[&'I'];
+        let page = image::open(directory.join("noto_sdf_0.png")).unwrap();
+        let page = page.as_rgba8().unwrap();
+        let alpha = |x: u32, y: u32| page.get_pixel(glyph.frame.x + x, glyph.frame.y + y).0[3];
+        assert!(alpha(glyph.frame.w / 2, glyph.frame.h / 2) > 128);
+        assert_eq!(alpha(0, 0), 0);
+        assert_eq!(alpha(glyph.frame.w - 1, glyph.frame.h - 1), 0);
+        // The field extends `spread` pixels beyond the outline on each side.
+        assert_eq!(glyph.y_offset + glyph.frame.h as i32, font.base as i32 + 4);
+    }
+}
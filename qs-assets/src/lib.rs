@@ -0,0 +1,15 @@
+//! The asset pipeline, which compiles shaders, packs images into texture atlases and bakes distance field fonts.
+//! This is run by the client's build script, and by the `qs-assets` tool so that assets can be rebuilt without a full build.
+
+mod atlas;
+pub use atlas::*;
+mod cache;
+pub use cache::*;
+mod distance_field;
+pub use distance_field::*;
+mod reflect;
+pub use reflect::*;
+mod shaders;
+pub use shaders::*;
+mod validate;
+pub use validate::*;
@@ -0,0 +1,149 @@
+//! Runs the asset pipeline without building the client, so that artists can see their changes quickly.
+//!
+//! Paths default to the layout of the `qs-client` directory, so the tool is normally run from there.
+
+use anyhow::*;
+use glob::glob;
+use qs_assets::*;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const USAGE: &str = "Usage:
+    qs-assets pack [<raw directory>] [<output directory>]
+        Packs each directory of images into its own texture atlas.
+    qs-assets fonts [<raw directory>] [<output directory>]
+        Bakes a distance field font for each <name>.sdf.json file.
+    qs-assets shaders [<shader directory>]
+        Compiles GLSL shaders into SPIR-V.
+    qs-assets validate [<atlas file>...]
+        Checks packed atlases and their page images for problems.
+    qs-assets watch [<raw directory>] [<output directory>] [<shader directory>]
+        Rebuilds shaders, atlases and fonts whenever they change, until interrupted.";
+
+const DEFAULT_RAW_DIRECTORY: &str = "assets_raw";
+const DEFAULT_OUTPUT_DIRECTORY: &str = "assets";
+const DEFAULT_SHADER_DIRECTORY: &str = "src/graphics";
+
+/// How long to wait between checking for changes in watch mode.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+fn main() -> Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (command.as_str(), args),
+        None => bail!("No command given\n\n{}", USAGE),
+    };
+    // Missing positional arguments take their default values.
+    let arg = |index: usize, default: &str| {
+        PathBuf::from(args.get(index).map(String::as_str).unwrap_or(default))
+    };
+
+    match command {
+        "pack" => {
+            let report = pack_textures(
+                &arg(0, DEFAULT_RAW_DIRECTORY),
+                &arg(1, DEFAULT_OUTPUT_DIRECTORY),
+                &mut BuildCache::in_memory(),
+            )?;
+            print_outputs(&report);
+        }
+        "fonts" => {
+            let report = bake_distance_field_fonts(
+                &arg(0, DEFAULT_RAW_DIRECTORY),
+                &arg(1, DEFAULT_OUTPUT_DIRECTORY),
+                &mut BuildCache::in_memory(),
+            )?;
+            print_outputs(&report);
+        }
+        "shaders" => {
+            let report = compile_shaders(
+                &arg(0, DEFAULT_SHADER_DIRECTORY),
+                &mut BuildCache::in_memory(),
+                jobs(),
+            )?;
+            print_outputs(&report);
+        }
+        "validate" => validate(args)?,
+        "watch" => watch(
+            &arg(0, DEFAULT_RAW_DIRECTORY),
+            &arg(1, DEFAULT_OUTPUT_DIRECTORY),
+            &arg(2, DEFAULT_SHADER_DIRECTORY),
+        ),
+        "help" | "--help" | "-h" => println!("{}", USAGE),
+        _ => bail!("Unknown command {}\n\n{}", command, USAGE),
+    }
+    Ok(())
+}
+
+/// The number of shaders to compile at once.
+fn jobs() -> usize {
+    std::thread::available_parallelism()
+        .map(|jobs| jobs.get())
+        .unwrap_or(1)
+}
+
+fn print_outputs(report: &BuildReport) {
+    for output in &report.outputs {
+        println!("wrote {}", output.display());
+    }
+}
+
+/// Validates the given atlas files, or every binary atlas in the default output directory if none were given.
+fn validate(args: &[String]) -> Result<()> {
+    let paths = if args.is_empty() {
+        let pattern = Path::new(DEFAULT_OUTPUT_DIRECTORY)
+            .join("**")
+            .join("atlas.bin");
+        glob(pattern.to_str().context("Invalid path")?)?.collect::<Result<Vec<_>, _>>()?
+    } else {
+        args.iter().map(PathBuf::from).collect()
+    };
+
+    let mut problem_count = 0;
+    for path in &paths {
+        let problems = validate_atlas_file(path)?;
+        for problem in &problems {
+            println!("{}: {}", path.display(), problem);
+        }
+        problem_count += problems.len();
+    }
+    ensure!(
+        problem_count == 0,
+        "Found {} problems in {} atlases",
+        problem_count,
+        paths.len()
+    );
+    println!("{} atlases are valid", paths.len());
+    Ok(())
+}
+
+/// Repeatedly rebuilds the shaders, atlases and fonts. The cache is kept between builds, so only the assets that changed are rebuilt.
+/// Errors are reported rather than stopping the watch, since they are usually fixed by the next change.
+/// Each kind of asset is rebuilt on its own, so that an error in one doesn't hold up the others.
+fn watch(raw_directory: &Path, output_directory: &Path, shader_directory: &Path) -> ! {
+    let mut cache = BuildCache::in_memory();
+    // The last error reported by each step.
+    let mut last_errors: [Option<String>; 3] = Default::default();
+    println!("Watching for changes...");
+    loop {
+        let results = vec![
+            compile_shaders(shader_directory, &mut cache, jobs()),
+            pack_textures(raw_directory, output_directory, &mut cache),
+            bake_distance_field_fonts(raw_directory, output_directory, &mut cache),
+        ];
+        for (result, last_error) in results.into_iter().zip(&mut last_errors) {
+            match result.map(|report| print_outputs(&report)) {
+                Err(error) => {
+                    // The same error would otherwise be printed every time we check for changes.
+                    let message = format!("{:?}", error);
+                    if last_error.as_ref() != Some(&message) {
+                        eprintln!("error: {}", message);
+                    }
+                    *last_error = Some(message);
+                }
+                _ => *last_error = None,
+            }
+        }
+        std::thread::sleep(WATCH_INTERVAL);
+    }
+}
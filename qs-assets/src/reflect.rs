@@ -0,0 +1,660 @@
+use anyhow::*;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs::read;
+use std::path::Path;
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+// The few SPIR-V opcodes, decorations and enumerants that we need to understand a shader's interface.
+const OP_NAME: u32 = 5;
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+const DECORATION_BLOCK: u32 = 2;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_INPUT: u32 = 1;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+/// The kind of number that makes up each component of a value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScalarKind {
+    Float,
+    Int,
+    UInt,
+    Bool,
+}
+
+/// The type of an input to a shader.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InputType {
+    /// A scalar, or a vector with the given number of components.
+    Vector { kind: ScalarKind, components: u32 },
+    /// Matrices, arrays and structs, which the renderer never provides as inputs.
+    Other,
+}
+
+impl Display for InputType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InputType::Vector { kind, components } => {
+                let (scalar, prefix) = match kind {
+                    ScalarKind::Float => ("float", ""),
+                    ScalarKind::Int => ("int", "i"),
+                    ScalarKind::UInt => ("uint", "u"),
+                    ScalarKind::Bool => ("bool", "b"),
+                };
+                if *components == 1 {
+                    write!(f, "{}", scalar)
+                } else {
+                    write!(f, "{}vec{}", prefix, components)
+                }
+            }
+            InputType::Other => write!(f, "an unsupported type"),
+        }
+    }
+}
+
+/// The type of a resource bound to a shader in a bind group.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BindingType {
+    /// A uniform block, whose members take up the given number of bytes.
+    UniformBuffer {
+        size: u32,
+    },
+    StorageBuffer,
+    Texture,
+    Sampler,
+    /// A texture combined with its sampler, such as a `sampler2D`.
+    SampledTexture,
+    Other,
+}
+
+impl Display for BindingType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BindingType::UniformBuffer { size } => {
+                write!(f, "a uniform buffer of {} bytes", size)
+            }
+            BindingType::StorageBuffer => write!(f, "a storage buffer"),
+            BindingType::Texture => write!(f, "a texture"),
+            BindingType::Sampler => write!(f, "a sampler"),
+            BindingType::SampledTexture => write!(f, "a combined texture and sampler"),
+            BindingType::Other => write!(f, "an unsupported resource"),
+        }
+    }
+}
+
+/// The stage of the pipeline that a shader runs in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+    Compute,
+    Other,
+}
+
+/// An input to a shader with an explicit location. For vertex shaders, these are the vertex attributes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShaderInput {
+    pub location: u32,
+    pub name: String,
+    pub ty: InputType,
+}
+
+/// A resource that a shader reads from a bind group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShaderBinding {
+    pub set: u32,
+    pub binding: u32,
+    /// The name of the variable, or of the block if the variable has no name.
+    pub name: String,
+    pub ty: BindingType,
+}
+
+/// The inputs and resources used by a compiled shader, read from its SPIR-V.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShaderInterface {
+    pub stage: ShaderStage,
+    pub inputs: Vec<ShaderInput>,
+    pub bindings: Vec<ShaderBinding>,
+}
+
+/// What the renderer provides to a shader, to be checked against the shader's `ShaderInterface`.
+/// Shaders don't have to use everything that is provided.
+#[derive(Debug, Clone, Default)]
+pub struct ExpectedInterface {
+    /// The location and type of each vertex attribute in the vertex buffer. These are only checked against vertex shaders.
+    pub vertex_attributes: Vec<(u32, InputType)>,
+    /// The set, binding and type of each resource in the bind groups.
+    pub bindings: Vec<(u32, u32, BindingType)>,
+}
+
+/// A SPIR-V type, with just enough information to describe inputs and resources.
+enum Type {
+    Scalar(ScalarKind, u32),
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { length: u32 },
+    Struct(Vec<u32>),
+    Pointer { pointee: u32 },
+}
+
+/// Everything we read from a SPIR-V module, keyed by ID.
+#[derive(Default)]
+struct Module {
+    stage: Option<ShaderStage>,
+    names: HashMap<u32, String>,
+    /// Maps a target and decoration to the decoration's first operand, or zero if it has none.
+    decorations: HashMap<(u32, u32), u32>,
+    /// Maps a struct, member and decoration to the decoration's first operand, or zero if it has none.
+    member_decorations: HashMap<(u32, u32, u32), u32>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    /// The ID, type and storage class of each global variable.
+    variables: Vec<(u32, u32, u32)>,
+}
+
+/// Reads a nul-terminated string packed into SPIR-V words.
+fn read_string(words: &[u32]) -> String {
+    let bytes = words
+        .iter()
+        .flat_map(|word| word.to_le_bytes().to_vec())
+        .take_while(|&byte| byte != 0)
+        .collect::<Vec<_>>();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+impl Module {
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        let chunks = bytes.chunks_exact(4);
+        ensure!(
+            chunks.remainder().is_empty(),
+            "SPIR-V must be a whole number of words"
+        );
+        let mut words = chunks
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect::<Vec<_>>();
+        ensure!(words.len() >= 5, "SPIR-V is missing its header");
+        if words[0] == SPIRV_MAGIC.swap_bytes() {
+            for word in &mut words {
+                *word = word.swap_bytes();
+            }
+        }
+        ensure!(words[0] == SPIRV_MAGIC, "not a SPIR-V module");
+
+        let mut module = Module::default();
+        let mut index = 5;
+        while index < words.len() {
+            let word_count = (words[index] >> 16) as usize;
+            let opcode = words[index] & 0xffff;
+            ensure!(
+                word_count > 0 && index + word_count <= words.len(),
+                "invalid SPIR-V instruction at word {}",
+                index
+            );
+            let operands = &words[index + 1..index + word_count];
+            index += word_count;
+
+            // Every instruction we read has at least this many operands.
+            let required = match opcode {
+                OP_NAME | OP_TYPE_BOOL | OP_TYPE_SAMPLER | OP_TYPE_SAMPLED_IMAGE => 1,
+                OP_ENTRY_POINT | OP_TYPE_INT | OP_TYPE_FLOAT | OP_DECORATE => 2,
+                OP_TYPE_VECTOR | OP_TYPE_MATRIX | OP_TYPE_ARRAY | OP_TYPE_POINTER | OP_CONSTANT
+                | OP_VARIABLE | OP_MEMBER_DECORATE => 3,
+                OP_TYPE_IMAGE => 7,
+                _ => 0,
+            };
+            ensure!(
+                operands.len() >= required,
+                "SPIR-V instruction with opcode {} has too few operands",
+                opcode
+            );
+
+            match opcode {
+                OP_NAME => {
+                    module
+                        .names
+                        .insert(operands[0], read_string(&operands[1..]));
+                }
+                OP_ENTRY_POINT => {
+                    let stage = match operands[0] {
+                        0 => ShaderStage::Vertex,
+                        4 => ShaderStage::Fragment,
+                        5 => ShaderStage::Compute,
+                        _ => ShaderStage::Other,
+                    };
+                    module.stage.get_or_insert(stage);
+                }
+                OP_TYPE_BOOL => {
+                    module
+                        .types
+                        .insert(operands[0], Type::Scalar(ScalarKind::Bool, 4));
+                }
+                OP_TYPE_INT => {
+                    let kind = if operands.get(2) == Some(&1) {
+                        ScalarKind::Int
+                    } else {
+                        ScalarKind::UInt
+                    };
+                    module
+                        .types
+                        .insert(operands[0], Type::Scalar(kind, operands[1] / 8));
+                }
+                OP_TYPE_FLOAT => {
+                    module.types.insert(
+                        operands[0],
+                        Type::Scalar(ScalarKind::Float, operands[1] / 8),
+                    );
+                }
+                OP_TYPE_VECTOR => {
+                    module.types.insert(
+                        operands[0],
+                        Type::Vector {
+                            component: operands[1],
+                            count: operands[2],
+                        },
+                    );
+                }
+                OP_TYPE_MATRIX => {
+                    module.types.insert(
+                        operands[0],
+                        Type::Matrix {
+                            column: operands[1],
+                            count: operands[2],
+                        },
+                    );
+                }
+                OP_TYPE_IMAGE => {
+                    module.types.insert(
+                        operands[0],
+                        Type::Image {
+                            sampled: operands[6],
+                        },
+                    );
+                }
+                OP_TYPE_SAMPLER => {
+                    module.types.insert(operands[0], Type::Sampler);
+                }
+                OP_TYPE_SAMPLED_IMAGE => {
+                    module.types.insert(operands[0], Type::SampledImage);
+                }
+                OP_TYPE_ARRAY => {
+                    // The length is an ID of a constant, which always comes before the array type.
+                    let length = *module
+                        .constants
+                        .get(&operands[2])
+                        .context("array length is not a constant")?;
+                    module.types.insert(operands[0], Type::Array { length });
+                }
+                OP_TYPE_STRUCT => {
+                    module
+                        .types
+                        .insert(operands[0], Type::Struct(operands[1..].to_vec()));
+                }
+                OP_TYPE_POINTER => {
+                    module.types.insert(
+                        operands[0],
+                        Type::Pointer {
+                            pointee: operands[2],
+                        },
+                    );
+                }
+                OP_CONSTANT => {
+                    module.constants.insert(operands[1], operands[2]);
+                }
+                OP_VARIABLE => {
+                    module
+                        .variables
+                        .push((operands[1], operands[0], operands[2]));
+                }
+                OP_DECORATE => {
+                    module.decorations.insert(
+                        (operands[0], operands[1]),
+                        operands.get(2).copied().unwrap_or(0),
+                    );
+                }
+                OP_MEMBER_DECORATE => {
+                    module.member_decorations.insert(
+                        (operands[0], operands[1], operands[2]),
+                        operands.get(3).copied().unwrap_or(0),
+                    );
+                }
+                _ => {}
+            }
+        }
+        Ok(module)
+    }
+
+    fn ty(&self, id: u32) -> Result<&Type> {
+        self.types
+            .get(&id)
+            .with_context(|| format!("unknown SPIR-V type %{}", id))
+    }
+
+    fn input_type(&self, id: u32) -> Result<InputType> {
+        Ok(match self.ty(id)? {
+            Type::Scalar(kind, _) => InputType::Vector {
+                kind: *kind,
+                components: 1,
+            },
+            Type::Vector { component, count } => match self.ty(*component)? {
+                Type::Scalar(kind, _) => InputType::Vector {
+                    kind: *kind,
+                    components: *count,
+                },
+                _ => InputType::Other,
+            },
+            _ => InputType::Other,
+        })
+    }
+
+    /// The number of bytes taken up by a value of the given type.
+    /// Matrices inside structs are laid out using the matrix stride of the member, if there is one.
+    fn size(&self, id: u32, matrix_stride: Option<u32>) -> Result<u32> {
+        Ok(match self.ty(id)? {
+            Type::Scalar(_, size) => *size,
+            Type::Vector { component, count } => self.size(*component, None)? * count,
+            Type::Matrix { column, count } => match matrix_stride {
+                Some(stride) => stride * count,
+                None => self.size(*column, None)? * count,
+            },
+            Type::Array { length } => {
+                let stride = self
+                    .decorations
+                    .get(&(id, DECORATION_ARRAY_STRIDE))
+                    .context("array has no stride")?;
+                stride * length
+            }
+            Type::Struct(members) => {
+                let mut size = 0;
+                for (index, &member) in members.iter().enumerate() {
+                    let index = index as u32;
+                    let offset = self
+                        .member_decorations
+                        .get(&(id, index, DECORATION_OFFSET))
+                        .copied()
+                        .unwrap_or(0);
+                    let stride = self
+                        .member_decorations
+                        .get(&(id, index, DECORATION_MATRIX_STRIDE))
+                        .copied();
+                    size = size.max(offset + self.size(member, stride)?);
+                }
+                size
+            }
+            _ => bail!("SPIR-V type %{} has no size", id),
+        })
+    }
+
+    fn binding_type(&self, id: u32, storage_class: u32) -> Result<BindingType> {
+        Ok(match self.ty(id)? {
+            Type::Struct(_) if storage_class == STORAGE_STORAGE_BUFFER => {
+                BindingType::StorageBuffer
+            }
+            Type::Struct(_)
+                if self
+                    .decorations
+                    .contains_key(&(id, DECORATION_BUFFER_BLOCK)) =>
+            {
+                BindingType::StorageBuffer
+            }
+            Type::Struct(_) if self.decorations.contains_key(&(id, DECORATION_BLOCK)) => {
+                BindingType::UniformBuffer {
+                    size: self.size(id, None)?,
+                }
+            }
+            // Images with `sampled` set to 2 are storage images.
+            Type::Image { sampled } if *sampled != 2 => BindingType::Texture,
+            Type::Sampler => BindingType::Sampler,
+            Type::SampledImage => BindingType::SampledTexture,
+            _ => BindingType::Other,
+        })
+    }
+
+    fn name(&self, id: u32) -> String {
+        self.names.get(&id).cloned().unwrap_or_default()
+    }
+}
+
+impl ShaderInterface {
+    /// Reads the interface of a compiled SPIR-V shader.
+    pub fn reflect(spirv: &[u8]) -> Result<Self> {
+        let module = Module::parse(spirv)?;
+
+        let mut inputs = Vec::new();
+        let mut bindings = Vec::new();
+        for &(id, type_id, storage_class) in &module.variables {
+            let pointee = match module.ty(type_id)? {
+                Type::Pointer { pointee } => *pointee,
+                _ => bail!("variable %{} is not a pointer", id),
+            };
+            match storage_class {
+                STORAGE_INPUT => {
+                    // Built-in inputs such as `gl_VertexIndex` have no location.
+                    if let Some(&location) = module.decorations.get(&(id, DECORATION_LOCATION)) {
+                        inputs.push(ShaderInput {
+                            location,
+                            name: module.name(id),
+                            ty: module.input_type(pointee)?,
+                        });
+                    }
+                }
+                STORAGE_UNIFORM_CONSTANT | STORAGE_UNIFORM | STORAGE_STORAGE_BUFFER => {
+                    let binding = match module.decorations.get(&(id, DECORATION_BINDING)) {
+                        Some(&binding) => binding,
+                        None => continue,
+                    };
+                    let set = module
+                        .decorations
+                        .get(&(id, DECORATION_DESCRIPTOR_SET))
+                        .copied()
+                        .unwrap_or(0);
+                    // Uniform blocks are often declared without an instance name, so fall back to the block's name.
+                    let mut name = module.name(id);
+                    if name.is_empty() {
+                        name = module.name(pointee);
+                    }
+                    bindings.push(ShaderBinding {
+                        set,
+                        binding,
+                        name,
+                        ty: module.binding_type(pointee, storage_class)?,
+                    });
+                }
+                _ => {}
+            }
+        }
+        inputs.sort_by_key(|input| input.location);
+        bindings.sort_by_key(|binding| (binding.set, binding.binding));
+
+        Ok(Self {
+            stage: module.stage.context("SPIR-V module has no entry point")?,
+            inputs,
+            bindings,
+        })
+    }
+
+    /// Checks that everything this shader uses is provided by the renderer with the same type, returning a description
+    /// of each mismatch.
+    pub fn mismatches(&self, expected: &ExpectedInterface) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        if self.stage == ShaderStage::Vertex {
+            for input in &self.inputs {
+                let attribute = expected
+                    .vertex_attributes
+                    .iter()
+                    .find(|(location, _)| *location == input.location);
+                match attribute {
+                    Some((_, ty)) if *ty == input.ty => {}
+                    Some((_, ty)) => mismatches.push(format!(
+                        "vertex input {} at location {} is {}, but the vertex buffer provides {}",
+                        input.name, input.location, input.ty, ty
+                    )),
+                    None => mismatches.push(format!(
+                        "vertex input {} at location {} is not provided by the vertex buffer",
+                        input.name, input.location
+                    )),
+                }
+            }
+        }
+        for binding in &self.bindings {
+            let resource = expected
+                .bindings
+                .iter()
+                .find(|(set, index, _)| *set == binding.set && *index == binding.binding);
+            match resource {
+                Some((_, _, ty)) if *ty == binding.ty => {}
+                Some((_, _, ty)) => mismatches.push(format!(
+                    "{} at set {} binding {} is {}, but the renderer binds {}",
+                    binding.name, binding.set, binding.binding, binding.ty, ty
+                )),
+                None => mismatches.push(format!(
+                    "{} at set {} binding {} is not bound by the renderer",
+                    binding.name, binding.set, binding.binding
+                )),
+            }
+        }
+        mismatches
+    }
+}
+
+/// Checks the compiled shader at the given path against what the renderer provides, failing with a list of every mismatch.
+pub fn check_shader_interface(spv_path: &Path, expected: &ExpectedInterface) -> Result<()> {
+    let interface = ShaderInterface::reflect(&read(spv_path)?)
+        .with_context(|| format!("Could not reflect shader {}", spv_path.display()))?;
+    let mismatches = interface.mismatches(expected);
+    ensure!(
+        mismatches.is_empty(),
+        "Shader {} does not match the renderer:\n{}",
+        spv_path.display(),
+        mismatches.join("\n")
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op(opcode: u32, operands: &[u32]) -> Vec<u32> {
+        let mut words = vec![((operands.len() as u32 + 1) << 16) | opcode];
+        words.extend_from_slice(operands);
+        words
+    }
+
+    fn string(s: &str) -> Vec<u32> {
+        let mut bytes = s.as_bytes().to_vec();
+        bytes.resize(s.len() / 4 * 4 + 4, 0);
+        bytes
+            .chunks(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect()
+    }
+
+    /// A vertex shader with `layout(location=0) in vec3 a_position` and `layout(set=1, binding=0) uniform Uniforms { mat4 combined; }`.
+    fn vertex_shader() -> Vec<u8> {
+        let words = [
+            vec![SPIRV_MAGIC, 0x0001_0000, 0, 20, 0],
+            op(
+                OP_ENTRY_POINT,
+                &[&[0, 1][..], &string("main"), &[10]].concat(),
+            ),
+            op(OP_NAME, &[&[10][..], &string("a_position")].concat()),
+            op(OP_NAME, &[&[7][..], &string("Uniforms")].concat()),
+            op(OP_DECORATE, &[10, DECORATION_LOCATION, 0]),
+            op(OP_DECORATE, &[7, DECORATION_BLOCK]),
+            op(OP_MEMBER_DECORATE, &[7, 0, DECORATION_OFFSET, 0]),
+            op(OP_MEMBER_DECORATE, &[7, 0, DECORATION_MATRIX_STRIDE, 16]),
+            op(OP_DECORATE, &[11, DECORATION_DESCRIPTOR_SET, 1]),
+            op(OP_DECORATE, &[11, DECORATION_BINDING, 0]),
+            op(OP_TYPE_FLOAT, &[2, 32]),
+            op(OP_TYPE_VECTOR, &[3, 2, 3]),
+            op(OP_TYPE_POINTER, &[4, STORAGE_INPUT, 3]),
+            op(OP_VARIABLE, &[4, 10, STORAGE_INPUT]),
+            op(OP_TYPE_VECTOR, &[5, 2, 4]),
+            op(OP_TYPE_MATRIX, &[6, 5, 4]),
+            op(OP_TYPE_STRUCT, &[7, 6]),
+            op(OP_TYPE_POINTER, &[8, STORAGE_UNIFORM, 7]),
+            op(OP_VARIABLE, &[8, 11, STORAGE_UNIFORM]),
+        ]
+        .concat();
+        words
+            .iter()
+            .flat_map(|word| word.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn reflect_vertex_shader() {
+        let interface = ShaderInterface::reflect(&vertex_shader()).unwrap();
+        assert_eq!(
+            interface,
+            ShaderInterface {
+                stage: ShaderStage::Vertex,
+                inputs: vec![ShaderInput {
+                    location: 0,
+                    name: "a_position".to_string(),
+                    ty: InputType::Vector {
+                        kind: ScalarKind::Float,
+                        components: 3
+                    },
+                }],
+                bindings: vec![ShaderBinding {
+                    set: 1,
+                    binding: 0,
+                    name: "Uniforms".to_string(),
+                    ty: BindingType::UniformBuffer { size: 64 },
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn mismatched_interface() {
+        let interface = ShaderInterface::reflect(&vertex_shader()).unwrap();
+        let expected = ExpectedInterface {
+            vertex_attributes: vec![(
+                0,
+                InputType::Vector {
+                    kind: ScalarKind::Float,
+                    components: 4,
+                },
+            )],
+            bindings: vec![(1, 0, BindingType::UniformBuffer { size: 64 })],
+        };
+        assert_eq!(
+            interface.mismatches(&expected),
+            vec![
+                "vertex input a_position at location 0 is vec3, but the vertex buffer provides vec4"
+                    .to_string()
+            ]
+        );
+    }
+}
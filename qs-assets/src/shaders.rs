@@ -0,0 +1,339 @@
+use crate::{hash_files, input_hasher, BuildCache, BuildReport};
+use anyhow::*;
+use glob::glob;
+use std::collections::BTreeMap;
+use std::fs::{read_to_string, write};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// How deeply `#include` directives may be nested, so that an include cycle fails instead of recursing forever.
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+/// The macros to define for each variant of a shader, read from the optional sidecar file `<shader>.variants.json`.
+/// A macro with no value is defined as if by `#define NAME`.
+type ShaderVariants = BTreeMap<String, BTreeMap<String, Option<String>>>;
+
+struct ShaderData {
+    src: String,
+    src_path: PathBuf,
+    spv_path: PathBuf,
+    kind: shaderc::ShaderKind,
+    /// The macros to define when compiling this variant of the shader.
+    defines: BTreeMap<String, Option<String>>,
+}
+
+impl ShaderData {
+    /// Loads the shader at the given path, followed by each of its variants.
+    /// Each variant of `name.frag` is compiled to `name.<variant>.frag.spv`.
+    pub fn load(src_path: PathBuf) -> Result<Vec<Self>> {
+        let extension = src_path
+            .extension()
+            .context("File has no extension")?
+            .to_str()
+            .context("Extension cannot be converted to &str")?;
+        let kind = match extension {
+            "vert" => shaderc::ShaderKind::Vertex,
+            "frag" => shaderc::ShaderKind::Fragment,
+            "comp" => shaderc::ShaderKind::Compute,
+            _ => bail!("Unsupported shader: {}", src_path.display()),
+        };
+
+        let src = read_to_string(src_path.clone())?;
+        let spv_path = src_path.with_extension(format!("{}.spv", extension));
+
+        let variants_path = src_path.with_extension(format!("{}.variants.json", extension));
+        let variants: ShaderVariants = if variants_path.exists() {
+            serde_json::from_str(&read_to_string(&variants_path)?).with_context(|| {
+                format!("Invalid shader variants file {}", variants_path.display())
+            })?
+        } else {
+            ShaderVariants::new()
+        };
+
+        let stem = src_path
+            .file_stem()
+            .context("File has no name")?
+            .to_str()
+            .context("File name cannot be converted to &str")?;
+        let mut shaders = vec![Self {
+            src: src.clone(),
+            src_path: src_path.clone(),
+            spv_path,
+            kind,
+            defines: BTreeMap::new(),
+        }];
+        for (variant, defines) in variants {
+            shaders.push(Self {
+                src: src.clone(),
+                src_path: src_path.clone(),
+                spv_path: src_path
+                    .with_file_name(format!("{}.{}.{}.spv", stem, variant, extension)),
+                kind,
+                defines,
+            });
+        }
+        Ok(shaders)
+    }
+}
+
+/// Resolves an `#include` directive. Paths in quotes are relative to the file containing the directive,
+/// and paths in angle brackets are relative to the shader directory.
+fn resolve_include(
+    directory: &Path,
+    requested: &str,
+    include_type: shaderc::IncludeType,
+    requesting: &str,
+    depth: usize,
+) -> shaderc::IncludeCallbackResult {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(format!(
+            "#include is nested more than {} levels deep",
+            MAX_INCLUDE_DEPTH
+        ));
+    }
+    let path = match include_type {
+        shaderc::IncludeType::Relative => Path::new(requesting)
+            .parent()
+            .unwrap_or(directory)
+            .join(requested),
+        shaderc::IncludeType::Standard => directory.join(requested),
+    };
+    read_to_string(&path)
+        .map(|content| shaderc::ResolvedInclude {
+            resolved_name: path.to_string_lossy().into_owned(),
+            content,
+        })
+        .map_err(|error| format!("could not include {}: {}", path.display(), error))
+}
+
+/// Compiles every GLSL shader in the given directory and its subdirectories into SPIR-V, written next to the source file
+/// with `.spv` appended to its name. Shaders that haven't changed since they were last compiled are skipped.
+///
+/// Shaders may `#include` shared snippets, which are files ending in `.glsl` that are not compiled on their own.
+/// Since it's hard to tell which shaders include which snippets before compiling them, changing any snippet
+/// recompiles every shader.
+///
+/// Up to `jobs` shaders are compiled at once.
+pub fn compile_shaders(
+    directory: &Path,
+    cache: &mut BuildCache,
+    jobs: usize,
+) -> Result<BuildReport> {
+    // Shaders may be added to the directory.
+    let mut report = BuildReport {
+        inputs: vec![directory.to_path_buf()],
+        ..Default::default()
+    };
+
+    // Collect all shaders recursively within the directory.
+    let mut shader_paths = Vec::new();
+    for extension in &["vert", "frag", "comp"] {
+        let pattern = directory.join("**").join(format!("*.{}", extension));
+        shader_paths.push(glob(pattern.to_str().context("Invalid path")?)?);
+    }
+
+    // Every shader may include any of the snippets, so they are part of the inputs of every shader.
+    let pattern = directory.join("**").join("*.glsl");
+    let mut snippets =
+        glob(pattern.to_str().context("Invalid path")?)?.collect::<Result<Vec<_>, _>>()?;
+    snippets.sort();
+    let snippets_hash = hash_files(&snippets)?;
+    report.inputs.extend(snippets);
+
+    // Only compile the shaders that have changed since they were last compiled.
+    let mut shaders = Vec::new();
+    for path in shader_paths.iter_mut().flatten() {
+        let path = path?;
+        report.inputs.push(path.clone());
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+        // A sidecar that doesn't exist can't be an input, since the build would then always be out of date.
+        // Adding one later is noticed because the directory is an input.
+        let variants_path = path.with_extension(format!("{}.variants.json", extension));
+        if variants_path.exists() {
+            report.inputs.push(variants_path);
+        }
+        for shader in ShaderData::load(path)? {
+            let mut hasher = input_hasher();
+            shader.src.hash(&mut hasher);
+            shader.defines.hash(&mut hasher);
+            snippets_hash.hash(&mut hasher);
+            let input_hash = hasher.finish();
+            if !cache.is_fresh(&shader_step(&shader), input_hash) {
+                shaders.push((shader, input_hash));
+            }
+        }
+    }
+
+    // The [shaderc::Compiler] is not thread safe, so each worker thread creates its own compiler,
+    // then takes shaders from the queue until there are none left.
+    // A shader that fails to compile doesn't stop the worker, so every other shader is still compiled.
+    let worker_count = jobs.max(1).min(shaders.len());
+    let queue = Arc::new(Mutex::new(shaders));
+    let workers = (0..worker_count)
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let directory = directory.to_path_buf();
+            std::thread::spawn(move || -> Result<Vec<(ShaderData, u64, Result<()>)>> {
+                let mut compiler =
+                    shaderc::Compiler::new().context("Unable to create shader compiler")?;
+                let mut compiled_shaders = Vec::new();
+                loop {
+                    let next = queue.lock().unwrap().pop();
+                    let (shader, input_hash) = match next {
+                        Some(next) => next,
+                        None => break,
+                    };
+                    let result = compile_shader(&mut compiler, &directory, &shader);
+                    compiled_shaders.push((shader, input_hash, result));
+                }
+                Ok(compiled_shaders)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // Record every shader that compiled, even if another failed, so that we don't compile it again next time.
+    // If any failed, the first error is returned.
+    let mut first_error = None;
+    for worker in workers {
+        let compiled_shaders = worker
+            .join()
+            .expect("shader compiler thread panicked")
+            .unwrap_or_else(|error| {
+                first_error.get_or_insert(error);
+                Vec::new()
+            });
+        for (shader, input_hash, compiled) in compiled_shaders {
+            if let Err(error) = compiled {
+                first_error.get_or_insert(error);
+                continue;
+            }
+            cache.record(
+                shader_step(&shader),
+                input_hash,
+                vec![shader.spv_path.clone()],
+            )?;
+            report.outputs.push(shader.spv_path);
+        }
+    }
+    match first_error {
+        Some(error) => Err(error),
+        None => Ok(report),
+    }
+}
+
+/// Compiles a single shader, writing the SPIR-V to its `spv_path`.
+fn compile_shader(
+    compiler: &mut shaderc::Compiler,
+    directory: &Path,
+    shader: &ShaderData,
+) -> Result<()> {
+    let mut options =
+        shaderc::CompileOptions::new().context("Unable to create shader compiler options")?;
+    options.set_include_callback(|requested, include_type, requesting, depth| {
+        resolve_include(directory, requested, include_type, requesting, depth)
+    });
+    for (name, value) in &shader.defines {
+        options.add_macro_definition(name, value.as_deref());
+    }
+    let compiled = compiler.compile_into_spirv(
+        &shader.src,
+        shader.kind,
+        shader.src_path.to_str().unwrap(),
+        "main",
+        Some(&options),
+    )?;
+    write(&shader.spv_path, compiled.as_binary_u8())?;
+    Ok(())
+}
+
+fn shader_step(shader: &ShaderData) -> String {
+    format!("shader {}", shader.spv_path.display())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variants_are_compiled_to_separate_files() {
+        let directory = std::env::temp_dir().join("qs-assets-variants-test");
+        std::fs::create_dir_all(&directory).unwrap();
+        write(
+            directory.join("sprite.frag"),
+            "#version 450\nvoid main() {}",
+        )
+        .unwrap();
+        write(
+            directory.join("sprite.frag.variants.json"),
+            r#"{ "outlined": { "OUTLINE": null, "OUTLINE_WIDTH": "2.0" } }"#,
+        )
+        .unwrap();
+
+        let shaders = ShaderData::load(directory.join("sprite.frag")).unwrap();
+        let outputs = shaders
+            .iter()
+            .map(|shader| (shader.spv_path.clone(), shader.defines.clone()))
+            .collect::<Vec<_>>();
+        let mut defines = BTreeMap::new();
+        defines.insert("OUTLINE".to_string(), None);
+        defines.insert("OUTLINE_WIDTH".to_string(), Some("2.0".to_string()));
+        assert_eq!(
+            outputs,
+            vec![
+                (directory.join("sprite.frag.spv"), BTreeMap::new()),
+                (directory.join("sprite.outlined.frag.spv"), defines),
+            ]
+        );
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn includes_are_resolved_relative_to_their_file_or_the_shader_directory() {
+        let directory = std::env::temp_dir().join("qs-assets-include-test");
+        std::fs::create_dir_all(directory.join("effects")).unwrap();
+        write(directory.join("colour.glsl"), "// shared").unwrap();
+        write(directory.join("effects").join("colour.glsl"), "// local").unwrap();
+        let requesting = directory.join("effects").join("blur.frag");
+        let requesting = requesting.to_str().unwrap();
+
+        let resolve = |include_type, depth| {
+            resolve_include(&directory, "colour.glsl", include_type, requesting, depth)
+                .map(|include| (include.resolved_name, include.content))
+        };
+        assert_eq!(
+            resolve(shaderc::IncludeType::Relative, 1).ok(),
+            Some((
+                directory
+                    .join("effects")
+                    .join("colour.glsl")
+                    .to_string_lossy()
+                    .into_owned(),
+                "// local".to_string()
+            ))
+        );
+        assert_eq!(
+            resolve(shaderc::IncludeType::Standard, 1).ok(),
+            Some((
+                directory.join("colour.glsl").to_string_lossy().into_owned(),
+                "// shared".to_string()
+            ))
+        );
+
+        // An include cycle stops once it is nested too deeply.
+        let error = resolve(shaderc::IncludeType::Standard, MAX_INCLUDE_DEPTH + 1).unwrap_err();
+        assert!(error.contains("nested"), "{}", error);
+        let missing = resolve_include(
+            &directory,
+            "missing.glsl",
+            shaderc::IncludeType::Standard,
+            requesting,
+            1,
+        );
+        assert!(missing.is_err());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
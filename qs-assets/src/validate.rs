@@ -0,0 +1,43 @@
+use anyhow::{Context, Result};
+use std::fs::read;
+use std::path::Path;
+use texture_atlas::TextureAtlas;
+
+/// Checks the atlas file at the given path, as well as the page images it refers to, which must be in the same directory.
+/// Returns a description of every problem that was found, or an error if the atlas couldn't be read at all.
+pub fn validate_atlas_file(path: &Path) -> Result<Vec<String>> {
+    let atlas = TextureAtlas::decode(&read(path)?)
+        .with_context(|| format!("Could not read atlas {}", path.display()))?;
+
+    let mut problems = match atlas.validate() {
+        Ok(()) => Vec::new(),
+        Err(errors) => errors.iter().map(ToString::to_string).collect(),
+    };
+
+    let directory = path.parent().unwrap_or_else(|| Path::new("."));
+    for (index, page) in atlas.pages.iter().enumerate() {
+        let image_path = directory.join(&page.image);
+        match image::image_dimensions(&image_path) {
+            Ok((width, height)) => {
+                if (width, height) != (page.width, page.height) {
+                    problems.push(format!(
+                        "page {} is {}x{}, but its image {} is {}x{}",
+                        index,
+                        page.width,
+                        page.height,
+                        image_path.display(),
+                        width,
+                        height
+                    ));
+                }
+            }
+            Err(error) => problems.push(format!(
+                "could not read image {} of page {}: {}",
+                image_path.display(),
+                index,
+                error
+            )),
+        }
+    }
+    Ok(problems)
+}
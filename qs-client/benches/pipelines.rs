@@ -0,0 +1,382 @@
+//! Benchmarks for the CPU-side parts of the client's rendering pipeline.
+//! These don't need a GPU or a window, so they can run on CI machines.
+//!
+//! Run them with `cargo bench -p qs-client --bench pipelines`, passing any options after `--`.
+//! Each result is compared against the baseline committed in `benches/pipelines-baseline.json`, and the benchmark fails
+//! if any result is slower than the baseline by more than the threshold.
+//! Timings depend on the machine, so the baseline should be saved again on the machine that checks for regressions.
+//! - `--save-baseline` stores the results as the new baseline instead of comparing against it.
+//! - `--baseline <path>` reads and writes the baseline at the given path.
+//! - `--require-baseline` fails if there is no baseline for a result, instead of only warning.
+//! - `--threshold <ratio>` sets the largest allowed ratio between a result and its baseline. Defaults to 1.25.
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::Instant;
+
+use qs_client::{assets::FontAssetLoader, graphics::*, ui::*};
+use qs_common::{
+    assets::{AssetManager, AssetPath, LoadError, Loader},
+    profile::display_time,
+};
+use stretch::{
+    geometry::Size,
+    number::Number,
+    style::{Dimension, FlexDirection, FlexWrap, Style},
+};
+use texture_atlas::{Rect, TextureRegionInformation};
+
+/// How many samples to take of each benchmark. The median sample is reported.
+const SAMPLES: usize = 21;
+/// Each sample runs the routine enough times to take at least this many seconds.
+const MIN_SAMPLE_SECONDS: f64 = 0.01;
+
+const LOREM_IPSUM: &str = "Lorem ipsum dolor sit amet, consectetur adipiscing elit. Ut facilisis elit at massa placerat, in placerat est pretium. Curabitur consequat porta ante vel pharetra. Vestibulum sit amet mauris rhoncus, facilisis felis et, elementum arcu.";
+
+struct Options {
+    baseline_path: String,
+    save_baseline: bool,
+    require_baseline: bool,
+    threshold: f64,
+}
+
+impl Options {
+    fn parse() -> Self {
+        let mut options = Self {
+            baseline_path: concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/benches/pipelines-baseline.json"
+            )
+            .to_string(),
+            save_baseline: false,
+            require_baseline: false,
+            threshold: 1.25,
+        };
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--save-baseline" => options.save_baseline = true,
+                "--require-baseline" => options.require_baseline = true,
+                "--baseline" => {
+                    options.baseline_path = args.next().expect("expected a path after --baseline")
+                }
+                "--threshold" => {
+                    options.threshold = args
+                        .next()
+                        .and_then(|ratio| ratio.parse().ok())
+                        .expect("expected a number after --threshold")
+                }
+                // Cargo passes `--bench` to every benchmark binary.
+                _ => {}
+            }
+        }
+        options
+    }
+}
+
+/// Runs benchmarks and records the median time per iteration of each.
+struct Harness {
+    results: Vec<(&'static str, f64)>,
+}
+
+impl Harness {
+    /// Benchmarks `routine`. The inputs to the routine are created by `setup`, which is not timed.
+    fn bench<I, O>(
+        &mut self,
+        name: &'static str,
+        mut setup: impl FnMut() -> I,
+        mut routine: impl FnMut(I) -> O,
+    ) {
+        let mut run = |iterations: usize| {
+            let inputs = (0..iterations).map(|_| setup()).collect::<Vec<_>>();
+            let start = Instant::now();
+            for input in inputs {
+                black_box(routine(input));
+            }
+            start.elapsed().as_secs_f64()
+        };
+
+        // Work out how many iterations make a sample long enough to time accurately.
+        let mut iterations = 1;
+        while run(iterations) < MIN_SAMPLE_SECONDS {
+            iterations *= 2;
+        }
+
+        let mut samples = (0..SAMPLES)
+            .map(|_| run(iterations) / iterations as f64)
+            .collect::<Vec<_>>();
+        samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        self.results.push((name, samples[SAMPLES / 2]));
+    }
+}
+
+/// Stands in for textures in benchmarks. Loading always fails, since we don't have a GPU to load textures onto.
+struct NoTextureLoader;
+
+#[async_trait::async_trait]
+impl<T: 'static> Loader<(), T> for NoTextureLoader {
+    async fn load(&self, _key: ()) -> Result<T, LoadError> {
+        Err(LoadError::FileNotFound)
+    }
+}
+
+fn quad(x: f32, y: f32) -> Renderable {
+    let vertex = |x, y| Vertex {
+        position: [x, y, 0.0],
+        color: [1.0, 1.0, 1.0, 1.0],
+        tex_coords: [0.0, 0.0],
+    };
+    Renderable::Quadrilateral(
+        vertex(x, y),
+        vertex(x + 1.0, y),
+        vertex(x + 1.0, y + 1.0),
+        vertex(x, y + 1.0),
+    )
+}
+
+fn main() {
+    let options = Options::parse();
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let _guard = rt.enter();
+
+    let mut harness = Harness {
+        results: Vec::new(),
+    };
+
+    {
+        // A wrapping grid of fixed-size widgets, similar to a large inventory screen.
+        let rows = (0..20)
+            .map(|_| {
+                let cells = (0..20)
+                    .map(|_| {
+                        Widget::new(
+                            (),
+                            Vec::new(),
+                            Vec::new(),
+                            Style {
+                                min_size: Size {
+                                    width: Dimension::Points(20.0),
+                                    height: Dimension::Points(20.0),
+                                },
+                                ..Default::default()
+                            },
+                        )
+                    })
+                    .collect();
+                Widget::new(
+                    (),
+                    cells,
+                    Vec::new(),
+                    Style {
+                        flex_wrap: FlexWrap::Wrap,
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect();
+        let root = Widget::new(
+            (),
+            rows,
+            Vec::new(),
+            Style {
+                flex_direction: FlexDirection::Column,
+                ..Default::default()
+            },
+        );
+        let size = Size {
+            width: Number::Defined(300.0),
+            height: Number::Defined(800.0),
+        };
+        let ui = UI::new(root, size);
+        harness.bench("ui_layout", || (), |()| ui.layout(size));
+    }
+
+    {
+        let mut font_am = AssetManager::new(FontAssetLoader::default());
+        let regular = font_am.get(AssetPath::new(vec!["NotoSans-Regular.ttf".to_string()]));
+        let bold = font_am.get(AssetPath::new(vec!["NotoSans-Bold.ttf".to_string()]));
+        rt.block_on(async {
+            regular.wait_until_loaded().await;
+            bold.wait_until_loaded().await;
+        });
+        let font_family = std::sync::Arc::new(FontFamily::new(vec![FontFace::new(
+            "Noto Sans".to_string(),
+            regular,
+            Some(bold),
+            None,
+            None,
+        )]));
+        let mut rich_text = RichText::new(Default::default());
+        harness.bench(
+            "typeset_rich_text",
+            || {
+                rich_text
+                    .set_text(std::sync::Arc::clone(&font_family))
+                    .h1(|b| b.write("Header"))
+                    .end_paragraph()
+                    .write(LOREM_IPSUM)
+                    .bold(|b| b.write("bold text"))
+            },
+            |builder| rt.block_on(builder.typeset()),
+        );
+    }
+
+    let mut texture_am = AssetManager::new(NoTextureLoader);
+    let mut partitioned_texture_am = AssetManager::new(NoTextureLoader);
+    let texture = texture_am.get(());
+    let nine_patch = NinePatch {
+        texture_region: TextureRegion::with_info(
+            partitioned_texture_am.get(()),
+            0,
+            TextureRegionInformation {
+                frame: Rect {
+                    x: 16,
+                    y: 16,
+                    w: 16,
+                    h: 16,
+                },
+                rotated: false,
+                trimmed: false,
+                source: Rect {
+                    x: 0,
+                    y: 0,
+                    w: 16,
+                    h: 16,
+                },
+                nine_patch: None,
+                pivot: None,
+            },
+            (512, 512),
+        ),
+        left_margin: 2,
+        right_margin: 2,
+        top_margin: 2,
+        bottom_margin: 2,
+    };
+
+    harness.bench(
+        "nine_patch_render_info",
+        || (),
+        |()| nine_patch.generate_render_info(Colour::WHITE, 0.0, 0.0, 100.0, 40.0),
+    );
+
+    harness.bench(
+        "multi_renderable_flatten",
+        || {
+            // Layers of buttons drawn over images, switching texture often enough to split the batch.
+            MultiRenderable::Layered(
+                (0..10)
+                    .map(|layer| {
+                        MultiRenderable::Adjacent(
+                            (0..50)
+                                .map(|i| {
+                                    if (layer + i) % 5 == 0 {
+                                        MultiRenderable::Image {
+                                            texture: texture.clone(),
+                                            renderables: vec![quad(i as f32, layer as f32)],
+                                        }
+                                    } else {
+                                        nine_patch.generate_render_info(
+                                            Colour::WHITE,
+                                            i as f32,
+                                            layer as f32,
+                                            20.0,
+                                            20.0,
+                                        )
+                                    }
+                                })
+                                .collect(),
+                        )
+                    })
+                    .collect(),
+            )
+        },
+        |renderable| renderable.flatten(),
+    );
+
+    let quads = (0..10_000)
+        .map(|i| quad((i % 100) as f32, (i / 100) as f32))
+        .collect::<Vec<_>>();
+    harness.bench(
+        "batch_generate_geometry",
+        || (),
+        |()| {
+            generate_geometry(quads.iter().copied(), |verts, inds| {
+                black_box((verts, inds));
+            })
+        },
+    );
+
+    let baseline: HashMap<String, f64> = if options.save_baseline {
+        HashMap::new()
+    } else {
+        match std::fs::read(&options.baseline_path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).expect("baseline is not valid JSON"),
+            Err(error) => {
+                eprintln!(
+                    "WARNING: could not read baseline {}: {}",
+                    options.baseline_path, error
+                );
+                eprintln!("WARNING: regressions cannot be detected without a baseline, save one with --save-baseline");
+                HashMap::new()
+            }
+        }
+    };
+
+    let mut regressions = Vec::new();
+    let mut missing = Vec::new();
+    for (name, seconds) in &harness.results {
+        match baseline.get(*name) {
+            Some(baseline_seconds) if !options.save_baseline => {
+                let ratio = seconds / baseline_seconds;
+                println!(
+                    "{:28} {} (baseline {}, {:+.1}%)",
+                    name,
+                    display_time(*seconds),
+                    display_time(*baseline_seconds),
+                    100.0 * (ratio - 1.0)
+                );
+                if ratio > options.threshold {
+                    regressions.push(*name);
+                }
+            }
+            _ => {
+                println!("{:28} {}", name, display_time(*seconds));
+                if !options.save_baseline {
+                    missing.push(*name);
+                }
+            }
+        }
+    }
+
+    if options.save_baseline {
+        let results = harness
+            .results
+            .iter()
+            .map(|(name, seconds)| (name.to_string(), *seconds))
+            .collect::<HashMap<_, _>>();
+        std::fs::write(
+            &options.baseline_path,
+            serde_json::to_vec_pretty(&results).unwrap(),
+        )
+        .expect("could not write baseline");
+        println!("saved baseline to {}", options.baseline_path);
+    } else {
+        if !missing.is_empty() {
+            eprintln!("WARNING: no baseline for: {}", missing.join(", "));
+        }
+        if !regressions.is_empty() {
+            eprintln!(
+                "regressed by more than {:.0}%: {}",
+                100.0 * (options.threshold - 1.0),
+                regressions.join(", ")
+            );
+        }
+        if !regressions.is_empty() || (options.require_baseline && !missing.is_empty()) {
+            std::process::exit(1);
+        }
+    }
+}
@@ -1,189 +1,87 @@
 use anyhow::*;
-use glob::glob;
-use std::{collections::HashMap, path::PathBuf};
-use std::{
-    fs::{read_to_string, write, File},
-    path::Path,
-};
-use texture_atlas::*;
-use texture_packer::{
-    exporter::ImageExporter, importer::ImageImporter, texture::Texture, TexturePacker,
-    TexturePackerConfig,
-};
-
-struct ShaderData {
-    src: String,
-    src_path: PathBuf,
-    spv_path: PathBuf,
-    kind: shaderc::ShaderKind,
-}
-
-impl ShaderData {
-    pub fn load(src_path: PathBuf) -> Result<Self> {
-        let extension = src_path
-            .extension()
-            .context("File has no extension")?
-            .to_str()
-            .context("Extension cannot be converted to &str")?;
-        let kind = match extension {
-            "vert" => shaderc::ShaderKind::Vertex,
-            "frag" => shaderc::ShaderKind::Fragment,
-            "comp" => shaderc::ShaderKind::Compute,
-            _ => bail!("Unsupported shader: {}", src_path.display()),
-        };
-
-        let src = read_to_string(src_path.clone())?;
-        let spv_path = src_path.with_extension(format!("{}.spv", extension));
-
-        Ok(Self {
-            src,
-            src_path,
-            spv_path,
-            kind,
-        })
+use qs_assets::*;
+use std::path::{Path, PathBuf};
+
+#[path = "src/graphics/shader_interface.rs"]
+mod shader_interface;
+
+/// The shaders drawn by `Batch`, which must match `shader_interface.rs`.
+const BATCH_SHADERS: &[&str] = &[
+    "shader.vert",
+    "shader.frag",
+    "shader.text.frag",
+    "shader.premultiplied.frag",
+    "shader.distance_field.frag",
+];
+
+/// What the renderer provides to the batch shaders, as described by `shader_interface.rs`.
+fn batch_interface() -> ExpectedInterface {
+    use shader_interface::*;
+    ExpectedInterface {
+        vertex_attributes: VERTEX_ATTRIBUTES
+            .iter()
+            .map(|&(location, components)| {
+                let ty = InputType::Vector {
+                    kind: ScalarKind::Float,
+                    components,
+                };
+                (location, ty)
+            })
+            .collect(),
+        bindings: vec![
+            (TEXTURE_SET, TEXTURE_BINDING, BindingType::Texture),
+            (TEXTURE_SET, SAMPLER_BINDING, BindingType::Sampler),
+            (
+                UNIFORMS_SET,
+                UNIFORMS_BINDING,
+                BindingType::UniformBuffer {
+                    size: UNIFORMS_SIZE,
+                },
+            ),
+        ],
     }
 }
 
-fn compile_shaders() -> Result<()> {
-    // This tells cargo to rerun this script if something in /src/graphics changes.
-    println!("cargo:rerun-if-changed=src/graphics/*");
-
-    // Collect all shaders recursively within /src/
-    let mut shader_paths = [
-        glob("./src/graphics/**/*.vert")?,
-        glob("./src/graphics/**/*.frag")?,
-        glob("./src/graphics/**/*.comp")?,
-    ];
-
-    // This could be parallelized
-    let shaders = shader_paths
-        .iter_mut()
-        .flatten()
-        .map(|glob_result| ShaderData::load(glob_result?))
-        .collect::<Vec<Result<_>>>()
-        .into_iter()
-        .collect::<Result<Vec<_>>>();
-
-    let mut compiler = shaderc::Compiler::new().context("Unable to create shader compiler")?;
-
-    // This can't be parallelized. The [shaderc::Compiler] is not
-    // thread safe. Also, it creates a lot of resources. You could
-    // spawn multiple processes to handle this, but it would probably
-    // be better just to only compile shaders that have been changed
-    // recently.
-    for shader in shaders? {
-        let compiled = compiler.compile_into_spirv(
-            &shader.src,
-            shader.kind,
-            &shader.src_path.to_str().unwrap(),
-            "main",
-            None,
+fn main() -> Result<()> {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/graphics/shader_interface.rs");
+
+    let mut cache =
+        BuildCache::load(PathBuf::from(std::env::var("OUT_DIR")?).join("asset_cache.json"));
+    let jobs = std::env::var("NUM_JOBS")
+        .ok()
+        .and_then(|jobs| jobs.parse().ok())
+        .unwrap_or(1);
+    // Save the cache even if a step failed, so that the steps that succeeded aren't repeated.
+    let result =
+        compile_shaders(Path::new("src/graphics"), &mut cache, jobs).and_then(|mut report| {
+            report.extend(pack_textures(
+                Path::new("assets_raw"),
+                Path::new("assets"),
+                &mut cache,
+            )?);
+            report.extend(bake_distance_field_fonts(
+                Path::new("assets_raw"),
+                Path::new("assets"),
+                &mut cache,
+            )?);
+            Ok(report)
+        });
+    cache.save()?;
+    let report = result?;
+
+    // A shader that doesn't match the renderer would otherwise only fail when the pipeline is created at runtime.
+    let expected = batch_interface();
+    for shader in BATCH_SHADERS {
+        check_shader_interface(
+            &Path::new("src/graphics").join(format!("{}.spv", shader)),
+            &expected,
         )?;
-        write(shader.spv_path, compiled.as_binary_u8())?;
-    }
-
-    Ok(())
-}
-
-fn render_filename(path: &Path) -> String {
-    path.components()
-        .map(|component| match component {
-            std::path::Component::Prefix(_) => {
-                panic!("prefix not supported");
-            }
-            std::path::Component::RootDir => {
-                panic!("root dir not supported");
-            }
-            std::path::Component::CurDir => {
-                panic!("current dir not supported");
-            }
-            std::path::Component::ParentDir => {
-                panic!("parent dir not supported");
-            }
-            std::path::Component::Normal(name) => name.to_str().unwrap().to_string(),
-        })
-        .fold(
-            String::new(),
-            |l, r| {
-                if l.is_empty() {
-                    r
-                } else {
-                    l + "/" + &r
-                }
-            },
-        )
-}
-
-fn pack_textures() -> Result<()> {
-    let config = TexturePackerConfig {
-        max_width: 512,
-        max_height: 512,
-        allow_rotation: false,
-        border_padding: 2,
-        ..Default::default()
-    };
-
-    let mut packer = TexturePacker::new_skyline(config);
-
-    for path in glob("./assets_raw/ui/*.png")?.into_iter() {
-        let path = path?;
-        let texture = ImageImporter::import_from_file(&path).unwrap();
-        let canonical_path = path.canonicalize()?;
-        let name = canonical_path.strip_prefix(Path::new("./assets_raw/ui/").canonicalize()?)?;
-        packer.pack_own(render_filename(name), texture).unwrap();
     }
 
-    // Print the information
-    // println!("Dimensions : {}x{}", packer.width(), packer.height());
-    // for (name, frame) in packer.get_frames() {
-    //     println!("  {:7} : {:?}", name, frame.frame);
-    // }
-
-    // Save the packed image.
-    let exporter = ImageExporter::export(&packer).unwrap();
-    let _ = std::fs::create_dir("./assets/ui"); // ignore whether the directory already existed
-    let mut file = File::create("./assets/ui/atlas.png").unwrap();
-    exporter
-        .write_to(&mut file, image::ImageFormat::Png)
-        .unwrap();
-
-    // Save the atlas information.
-    let mut frames = HashMap::new();
-    for (name, frame) in packer.get_frames() {
-        frames.insert(
-            name.clone(),
-            TextureRegionInformation {
-                frame: Rect {
-                    x: frame.frame.x,
-                    y: frame.frame.y,
-                    w: frame.frame.w,
-                    h: frame.frame.h,
-                },
-                rotated: frame.rotated,
-                trimmed: frame.trimmed,
-                source: Rect {
-                    x: frame.source.x,
-                    y: frame.source.y,
-                    w: frame.source.w,
-                    h: frame.source.h,
-                },
-            },
-        );
+    // Rerun this script if any of the assets, or the directories containing them, change.
+    for input in report.inputs {
+        println!("cargo:rerun-if-changed={}", input.display());
     }
-    let atlas = TextureAtlas {
-        width: packer.width(),
-        height: packer.height(),
-        frames,
-    };
-    let atlas_file = File::create("./assets/ui/atlas.json").unwrap();
-    serde_json::to_writer(&atlas_file, &atlas)?;
-
-    Ok(())
-}
-
-fn main() -> Result<()> {
-    compile_shaders()?;
-    pack_textures()?;
-
     Ok(())
 }
@@ -1,9 +1,10 @@
 //! This module contains implementations of common asset managers used by clients.
 
-use crate::graphics::{PartitionedTexture, Texture};
+use crate::graphics::{BitmapFontTexture, PartitionedTexture, Texture, TextureEncoding};
 use qs_common::assets::*;
 use rusttype::Font;
 use std::sync::Arc;
+use texture_atlas::{BitmapFont, TextureAtlas, TextureOptions};
 use tokio::io::AsyncReadExt;
 use wgpu::{Device, Queue};
 
@@ -27,7 +28,13 @@ impl Loader<AssetPath, Texture> for TextureAssetLoader {
                 let mut result = Vec::new();
                 match reader.read_to_end(&mut result).await {
                     Ok(_) => {
-                        match Texture::from_bytes(&self.device, &self.queue, &result, "texture") {
+                        match Texture::from_bytes(
+                            &self.device,
+                            &self.queue,
+                            &result,
+                            "texture",
+                            TextureOptions::default(),
+                        ) {
                             Ok(texture) => Ok(texture),
                             Err(_) => Err(LoadError::InvalidData),
                         }
@@ -40,7 +47,8 @@ impl Loader<AssetPath, Texture> for TextureAssetLoader {
     }
 }
 
-/// Loads textures and texture atlas data from two files.
+/// Loads texture atlas data from a file, along with the texture for each page of the atlas.
+/// The page textures are found in the same directory as the atlas file.
 pub struct PartitionedTextureAssetLoader {
     device: Arc<Device>,
     queue: Arc<Queue>,
@@ -52,45 +60,60 @@ impl PartitionedTextureAssetLoader {
     }
 }
 
-/// A pair of atlas paths to specify both the texture image and the atlas file.
-#[derive(Debug, Eq, PartialEq, Clone, Hash)]
-pub struct PartitionedTextureAtlasPaths {
-    pub texture: AssetPath,
-    pub atlas: AssetPath,
-}
-
 #[async_trait::async_trait]
-impl Loader<PartitionedTextureAtlasPaths, PartitionedTexture> for PartitionedTextureAssetLoader {
-    async fn load(
-        &self,
-        key: PartitionedTextureAtlasPaths,
-    ) -> Result<PartitionedTexture, LoadError> {
-        let PartitionedTextureAtlasPaths { texture, atlas } = key;
-
-        // Read the texture information, then the texture atlas.
-        let base_texture = match texture.read_file().await {
+impl Loader<AssetPath, PartitionedTexture> for PartitionedTextureAssetLoader {
+    async fn load(&self, key: AssetPath) -> Result<PartitionedTexture, LoadError> {
+        // Read the texture atlas and deserialise it. It may be either JSON or binary.
+        let atlas = match key.read_file().await {
             Ok(mut reader) => {
                 let mut result = Vec::new();
                 match reader.read_to_end(&mut result).await {
-                    Ok(_) => {
-                        match Texture::from_bytes(&self.device, &self.queue, &result, "texture") {
-                            Ok(texture) => Ok(texture),
-                            Err(_) => Err(LoadError::InvalidData),
+                    Ok(_) => match TextureAtlas::decode(&result) {
+                        Ok(atlas) => Ok(atlas),
+                        Err(error) => {
+                            tracing::error!("could not read atlas {:?}: {}", key, error);
+                            Err(LoadError::InvalidData)
                         }
-                    }
+                    },
                     Err(_) => Err(LoadError::FileNotReadable),
                 }
             }
             Err(_) => Err(LoadError::FileNotFound),
         }?;
+        if let Err(errors) = atlas.validate() {
+            for error in errors {
+                tracing::error!("atlas {:?} is invalid: {}", key, error);
+            }
+            return Err(LoadError::InvalidData);
+        }
 
-        // Read the texture atlas and deserialise it from JSON.
-        let atlas = match atlas.read_file().await {
+        // Then read the texture information for each page.
+        let pages = load_pages(&self.device, &self.queue, &key, &atlas).await?;
+        Ok(PartitionedTexture { pages, atlas })
+    }
+}
+
+/// Loads the texture for each page of an atlas. The page textures are found in the same directory as the file at `key`.
+async fn load_pages(
+    device: &Device,
+    queue: &Queue,
+    key: &AssetPath,
+    atlas: &TextureAtlas,
+) -> Result<Vec<Texture>, LoadError> {
+    let mut pages = Vec::new();
+    for page in &atlas.pages {
+        let texture = match key.with_file_name(&page.image).read_file().await {
             Ok(mut reader) => {
                 let mut result = Vec::new();
                 match reader.read_to_end(&mut result).await {
-                    Ok(_) => match serde_json::from_slice(&result) {
-                        Ok(atlas) => Ok(atlas),
+                    Ok(_) => match Texture::from_bytes(
+                        device,
+                        queue,
+                        &result,
+                        &page.image,
+                        atlas.texture_options,
+                    ) {
+                        Ok(texture) => Ok(texture),
                         Err(_) => Err(LoadError::InvalidData),
                     },
                     Err(_) => Err(LoadError::FileNotReadable),
@@ -98,11 +121,69 @@ impl Loader<PartitionedTextureAtlasPaths, PartitionedTexture> for PartitionedTex
             }
             Err(_) => Err(LoadError::FileNotFound),
         }?;
+        if texture.dimensions != (page.width, page.height) {
+            tracing::error!(
+                "page {} of {:?} is {:?}, but should be {:?}",
+                page.image,
+                key,
+                texture.dimensions,
+                (page.width, page.height)
+            );
+            return Err(LoadError::InvalidData);
+        }
+        pages.push(texture);
+    }
+    Ok(pages)
+}
+
+/// Loads bitmap fonts in the BMFont text or XML format from a file, along with the texture for each page of the font.
+/// The page textures are found in the same directory as the font file.
+/// This also loads the distance field fonts baked by the asset pipeline.
+pub struct BitmapFontAssetLoader {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+}
+
+impl BitmapFontAssetLoader {
+    pub fn new(device: Arc<Device>, queue: Arc<Queue>) -> Self {
+        Self { device, queue }
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<AssetPath, BitmapFontTexture> for BitmapFontAssetLoader {
+    async fn load(&self, key: AssetPath) -> Result<BitmapFontTexture, LoadError> {
+        let font = match key.read_file().await {
+            Ok(mut reader) => {
+                let mut result = String::new();
+                match reader.read_to_string(&mut result).await {
+                    Ok(_) => match BitmapFont::parse(&result) {
+                        Ok(font) => Ok(font),
+                        Err(error) => {
+                            tracing::error!("could not read bitmap font {:?}: {}", key, error);
+                            Err(LoadError::InvalidData)
+                        }
+                    },
+                    Err(_) => Err(LoadError::FileNotReadable),
+                }
+            }
+            Err(_) => Err(LoadError::FileNotFound),
+        }?;
+        let atlas = font.to_atlas();
+        if let Err(errors) = atlas.validate() {
+            for error in errors {
+                tracing::error!("bitmap font {:?} is invalid: {}", key, error);
+            }
+            return Err(LoadError::InvalidData);
+        }
 
-        Ok(PartitionedTexture {
-            base_texture,
-            atlas,
-        })
+        let mut pages = load_pages(&self.device, &self.queue, &key, &atlas).await?;
+        if font.distance_range.is_some() {
+            for page in &mut pages {
+                page.encoding = TextureEncoding::DistanceField;
+            }
+        }
+        Ok(BitmapFontTexture::new(font, pages))
     }
 }
 
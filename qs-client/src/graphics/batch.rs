@@ -1,6 +1,13 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::sync::Arc;
 
-use crate::graphics::Texture;
+use crate::graphics::{
+    Texture, TextureEncoding, SAMPLER_BINDING, TEXTURE_BINDING, TEXTURE_SET, UNIFORMS_BINDING,
+    UNIFORMS_SET, UNIFORMS_SIZE, VERTEX_ATTRIBUTES,
+};
+use cgmath::Matrix4;
+use texture_atlas::Corners;
 use wgpu::*;
 
 /// The maximum anout of vertices that may be drawn in a single batched draw call.
@@ -38,17 +45,17 @@ impl Vertex {
             attributes: &[
                 VertexAttributeDescriptor {
                     offset: 0,
-                    shader_location: 0,
+                    shader_location: VERTEX_ATTRIBUTES[0].0,
                     format: VertexFormat::Float3,
                 },
                 VertexAttributeDescriptor {
                     offset: std::mem::size_of::<[f32; 3]>() as BufferAddress,
-                    shader_location: 1,
+                    shader_location: VERTEX_ATTRIBUTES[1].0,
                     format: VertexFormat::Float4,
                 },
                 VertexAttributeDescriptor {
                     offset: std::mem::size_of::<[f32; 7]>() as BufferAddress,
-                    shader_location: 2,
+                    shader_location: VERTEX_ATTRIBUTES[2].0,
                     format: VertexFormat::Float2,
                 },
             ],
@@ -65,14 +72,6 @@ struct Uniforms {
 unsafe impl bytemuck::Pod for Uniforms {}
 unsafe impl bytemuck::Zeroable for Uniforms {}
 
-impl Uniforms {
-    pub fn new(camera: &crate::graphics::Camera) -> Self {
-        Self {
-            combined: camera.get_projection_matrix() * camera.get_view_matrix(),
-        }
-    }
-}
-
 /// An item that can be rendered using a `Batch`.
 /// To render items using a batch, call the `render` method on the batch.
 #[derive(Debug, Copy, Clone)]
@@ -82,13 +81,27 @@ pub enum Renderable {
     Quadrilateral(Vertex, Vertex, Vertex, Vertex),
 }
 
+impl Renderable {
+    /// Creates a quadrilateral from the positions and texture coordinates of its corners,
+    /// given in the same order, as returned by the helpers in `texture_atlas`.
+    pub fn textured_quad(positions: Corners, tex_coords: Corners, color: [f32; 4]) -> Self {
+        let vertex = |i: usize| Vertex {
+            position: [positions[i][0], positions[i][1], 0.0],
+            color,
+            tex_coords: tex_coords[i],
+        };
+        Renderable::Quadrilateral(vertex(0), vertex(1), vertex(2), vertex(3))
+    }
+}
+
 /// The `Batch` combines multiple render calls with the same uniform parameters (textures, camera matrix, etc.)
 /// into a single render pass.
 pub struct Batch {
     device: Arc<Device>,
     queue: Arc<Queue>,
 
-    render_pipeline: RenderPipeline,
+    /// The pipeline that draws textures of each encoding this batch supports.
+    render_pipelines: HashMap<TextureEncoding, RenderPipeline>,
 
     vertex_buffer: Buffer,
     index_buffer: Buffer,
@@ -101,17 +114,19 @@ pub struct Batch {
 impl Batch {
     /// Creates a new batch. Note that allocating enough room on the graphics card to store a batch is a relatively
     /// expensive operation - don't create a batch every frame or just for one object, for example.
+    ///
+    /// Each texture is drawn using the fragment shader given for its encoding, so the batch can only draw textures
+    /// with the encodings in `fragment_sources`.
     pub fn new(
         device: Arc<Device>,
         queue: Arc<Queue>,
         vertex_source: ShaderModuleSource,
-        fragment_source: ShaderModuleSource,
+        fragment_sources: Vec<(TextureEncoding, ShaderModuleSource)>,
         texture_bind_group_layout: BindGroupLayout,
         uniform_bind_group_layout: BindGroupLayout,
         swap_chain_format: TextureFormat,
     ) -> Batch {
         let vs_module = device.create_shader_module(vertex_source);
-        let fs_module = device.create_shader_module(fragment_source);
 
         let render_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
@@ -119,51 +134,35 @@ impl Batch {
             push_constant_ranges: &[],
         });
 
-        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex_stage: ProgrammableStageDescriptor {
-                module: &vs_module,
-                entry_point: "main",
-            },
-            fragment_stage: Some(ProgrammableStageDescriptor {
-                module: &fs_module,
-                entry_point: "main",
-            }),
-            rasterization_state: Some(RasterizationStateDescriptor {
-                front_face: FrontFace::Ccw,
-                cull_mode: CullMode::None,
-                depth_bias: 0,
-                depth_bias_slope_scale: 0.0,
-                depth_bias_clamp: 0.0,
-                clamp_depth: false,
-            }),
-            color_states: &[ColorStateDescriptor {
-                format: swap_chain_format,
-                color_blend: BlendDescriptor {
-                    src_factor: BlendFactor::SrcAlpha,
-                    dst_factor: BlendFactor::OneMinusSrcAlpha,
-                    operation: BlendOperation::Add,
-                },
-                alpha_blend: BlendDescriptor {
-                    src_factor: BlendFactor::SrcAlpha,
-                    dst_factor: BlendFactor::OneMinusSrcAlpha,
-                    operation: BlendOperation::Add,
-                },
-                //color_blend: BlendDescriptor::REPLACE,
-                //alpha_blend: BlendDescriptor::REPLACE,
-                write_mask: ColorWrite::ALL,
-            }],
-            primitive_topology: PrimitiveTopology::TriangleList,
-            depth_stencil_state: None,
-            vertex_state: VertexStateDescriptor {
-                index_format: IndexFormat::Uint16,
-                vertex_buffers: &[Vertex::get_buffer_descriptor()],
-            },
-            sample_count: 1,
-            sample_mask: !0,
-            alpha_to_coverage_enabled: false,
-        });
+        let render_pipelines = fragment_sources
+            .into_iter()
+            .map(|(encoding, source)| {
+                let blend = match encoding {
+                    // The colour of a premultiplied texture has already been multiplied by its alpha.
+                    TextureEncoding::PremultipliedAlpha => BlendDescriptor {
+                        src_factor: BlendFactor::One,
+                        dst_factor: BlendFactor::OneMinusSrcAlpha,
+                        operation: BlendOperation::Add,
+                    },
+                    TextureEncoding::StraightAlpha | TextureEncoding::DistanceField => {
+                        BlendDescriptor {
+                            src_factor: BlendFactor::SrcAlpha,
+                            dst_factor: BlendFactor::OneMinusSrcAlpha,
+                            operation: BlendOperation::Add,
+                        }
+                    }
+                };
+                let render_pipeline = create_render_pipeline(
+                    &device,
+                    &render_pipeline_layout,
+                    &vs_module,
+                    &device.create_shader_module(source),
+                    swap_chain_format,
+                    blend,
+                );
+                (encoding, render_pipeline)
+            })
+            .collect();
 
         let vertex_buffer = device.create_buffer(&BufferDescriptor {
             label: Some("batch_vbo"),
@@ -182,7 +181,7 @@ impl Batch {
 
         let uniform_buffer = device.create_buffer(&BufferDescriptor {
             label: Some("batch_ubo"),
-            size: std::mem::size_of::<Uniforms>() as BufferAddress,
+            size: UNIFORMS_SIZE as BufferAddress,
             usage: BufferUsage::UNIFORM | BufferUsage::COPY_DST,
             mapped_at_creation: false,
         });
@@ -191,7 +190,7 @@ impl Batch {
             device,
             queue,
 
-            render_pipeline,
+            render_pipelines,
 
             vertex_buffer,
             index_buffer,
@@ -202,119 +201,109 @@ impl Batch {
         }
     }
 
-    /// Renders the contents of the `verts` and `inds` buffers to the screen.
-    #[inline(always)]
-    fn flush(
+    /// Draws the given vertices and indices to the screen with a single draw call.
+    /// The vertices are transformed by `transform`, the combined projection and view matrix of the camera.
+    /// There must be no more than `MAX_VERTEX_COUNT` vertices and `MAX_INDEX_COUNT` indices.
+    pub fn draw(
         &mut self,
         frame: &SwapChainTexture,
 
         texture: &Texture,
+        transform: Matrix4<f32>,
 
-        verts: &mut Vec<Vertex>,
-        inds: &mut Vec<u16>,
+        verts: &[Vertex],
+        inds: &[u16],
     ) {
-        if !inds.is_empty() {
-            if inds.len() % 2 == 1 {
-                inds.push(0); // dummy value to align the slice to a size that is a multiple of 4 bytes
-            }
-
-            let render = |texture: &Texture| {
-                // Create a command encoder that records our render information to be sent to the GPU.
-                let mut encoder =
-                    self.device
-                        .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                            label: Some("batch_render_encoder"),
-                        });
-
-                // Describe how we want to send the texture to the GPU.
-                let texture_bind_group =
-                    self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                        layout: &self.texture_bind_group_layout,
-                        entries: &[
-                            wgpu::BindGroupEntry {
-                                binding: 0,
-                                resource: wgpu::BindingResource::TextureView(&texture.view),
-                            },
-                            wgpu::BindGroupEntry {
-                                binding: 1,
-                                resource: wgpu::BindingResource::Sampler(&texture.sampler),
-                            },
-                        ],
-                        label: Some("texture_bind_group"),
-                    });
-
-                // Describe how we want to send the uniforms to the GPU.
-                let uniform_bind_group =
-                    self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                        layout: &self.uniform_bind_group_layout,
-                        entries: &[wgpu::BindGroupEntry {
-                            binding: 0,
-                            resource: wgpu::BindingResource::Buffer(self.uniform_buffer.slice(..)),
-                        }],
-                        label: Some("uniform_bind_group"),
-                    });
-
-                // Begin recording a render pass. When we drop this struct, `wgpu` will finish recording.
-                // This allows us to send this recorded list of commands to the GPU.
-                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                    color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                        attachment: &frame.view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Load,
-                            store: true,
-                        },
-                    }],
-                    depth_stencil_attachment: None,
-                });
-                render_pass.set_pipeline(&self.render_pipeline);
-
-                render_pass.set_bind_group(0, &texture_bind_group, &[]);
-                render_pass.set_bind_group(1, &uniform_bind_group, &[]);
-
-                render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-                render_pass.set_index_buffer(self.index_buffer.slice(..));
-
-                self.queue
-                    .write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&verts));
-                self.queue
-                    .write_buffer(&self.index_buffer, 0, bytemuck::cast_slice(&inds));
-
-                render_pass.draw_indexed(0..inds.len() as u32, 0, 0..1);
-
-                drop(render_pass);
-                self.queue.submit(std::iter::once(encoder.finish()));
-            };
-
-            // TODO make a default texture for unloaded textures.
-            render(texture);
+        if inds.is_empty() {
+            return;
         }
+        let index_count = inds.len() as u32;
+        let inds: Cow<[u16]> = if inds.len() % 2 == 1 {
+            // Add a dummy value to align the slice to a size that is a multiple of 4 bytes.
+            let mut padded = inds.to_vec();
+            padded.push(0);
+            Cow::Owned(padded)
+        } else {
+            Cow::Borrowed(inds)
+        };
+
+        let uniforms = Uniforms {
+            combined: transform,
+        };
+        self.queue
+            .write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
 
-        verts.clear();
-        inds.clear();
-    }
+        // Create a command encoder that records our render information to be sent to the GPU.
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("batch_render_encoder"),
+            });
+
+        // Describe how we want to send the texture to the GPU.
+        let texture_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.texture_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: TEXTURE_BINDING,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: SAMPLER_BINDING,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+            label: Some("texture_bind_group"),
+        });
 
-    /// If there is insufficient capacity to store this amount of new vertices and indices, we will flush
-    /// the batch's buffers so that they are free to be used.
-    #[inline(always)]
-    #[allow(clippy::too_many_arguments)] // We're going to ignore clippy's suggestion here because the function is internal and inline always.
-    fn ensure_capacity(
-        &mut self,
-        frame: &SwapChainTexture,
+        // Describe how we want to send the uniforms to the GPU.
+        let uniform_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.uniform_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: UNIFORMS_BINDING,
+                resource: wgpu::BindingResource::Buffer(self.uniform_buffer.slice(..)),
+            }],
+            label: Some("uniform_bind_group"),
+        });
 
-        texture: &Texture,
+        // Begin recording a render pass. When we drop this struct, `wgpu` will finish recording.
+        // This allows us to send this recorded list of commands to the GPU.
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: &frame.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        let render_pipeline = self.render_pipelines.get(&texture.encoding);
+        render_pass.set_pipeline(
+            render_pipeline.unwrap_or_else(|| {
+                panic!("this batch cannot draw {:?} textures", texture.encoding)
+            }),
+        );
 
-        verts: &mut Vec<Vertex>,
-        inds: &mut Vec<u16>,
+        render_pass.set_bind_group(TEXTURE_SET, &texture_bind_group, &[]);
+        render_pass.set_bind_group(UNIFORMS_SET, &uniform_bind_group, &[]);
 
-        new_verts: usize,
-        new_inds: usize,
-    ) {
-        if verts.len() + new_verts > MAX_VERTEX_COUNT || inds.len() + new_inds > MAX_INDEX_COUNT {
-            self.flush(frame, texture, verts, inds);
-        }
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..));
+
+        self.queue
+            .write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(verts));
+        self.queue
+            .write_buffer(&self.index_buffer, 0, bytemuck::cast_slice(&inds));
+
+        render_pass.draw_indexed(0..index_count, 0, 0..1);
+
+        drop(render_pass);
+        self.queue.submit(std::iter::once(encoder.finish()));
     }
 
+    /// Draws the items to the screen, using as few draw calls as will fit into the batch's buffers.
     pub fn render(
         &mut self,
         frame: &SwapChainTexture,
@@ -323,44 +312,137 @@ impl Batch {
         camera: &crate::graphics::Camera,
         items: impl Iterator<Item = Renderable>,
     ) {
-        // Store the vertices and indices so that we can write them to the vertex buffer and index buffer in a single function call.
-        let mut verts = Vec::<Vertex>::new();
-        let mut inds = Vec::<u16>::new();
+        let transform = camera.get_projection_matrix() * camera.get_view_matrix();
+        generate_geometry(items, |verts, inds| {
+            self.draw(frame, texture, transform, verts, inds);
+            verts.clear();
+            inds.clear();
+        });
+    }
+}
 
-        let uniforms = Uniforms::new(camera);
-        self.queue
-            .write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
+/// Creates a pipeline that draws textured triangles with the given shaders, blending with the given colour and alpha factors.
+fn create_render_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    vs_module: &ShaderModule,
+    fs_module: &ShaderModule,
+    swap_chain_format: TextureFormat,
+    blend: BlendDescriptor,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(layout),
+        vertex_stage: ProgrammableStageDescriptor {
+            module: vs_module,
+            entry_point: "main",
+        },
+        fragment_stage: Some(ProgrammableStageDescriptor {
+            module: fs_module,
+            entry_point: "main",
+        }),
+        rasterization_state: Some(RasterizationStateDescriptor {
+            front_face: FrontFace::Ccw,
+            cull_mode: CullMode::None,
+            depth_bias: 0,
+            depth_bias_slope_scale: 0.0,
+            depth_bias_clamp: 0.0,
+            clamp_depth: false,
+        }),
+        color_states: &[ColorStateDescriptor {
+            format: swap_chain_format,
+            color_blend: blend.clone(),
+            alpha_blend: blend,
+            //color_blend: BlendDescriptor::REPLACE,
+            //alpha_blend: BlendDescriptor::REPLACE,
+            write_mask: ColorWrite::ALL,
+        }],
+        primitive_topology: PrimitiveTopology::TriangleList,
+        depth_stencil_state: None,
+        vertex_state: VertexStateDescriptor {
+            index_format: IndexFormat::Uint16,
+            vertex_buffers: &[Vertex::get_buffer_descriptor()],
+        },
+        sample_count: 1,
+        sample_mask: !0,
+        alpha_to_coverage_enabled: false,
+    })
+}
 
-        for renderable in items {
-            match renderable {
-                Renderable::Empty => {}
-                Renderable::Triangle(v0, v1, v2) => {
-                    self.ensure_capacity(frame, texture, &mut verts, &mut inds, 3, 3);
-                    let i0 = verts.len() as u16;
-                    verts.push(v0);
-                    verts.push(v1);
-                    verts.push(v2);
-                    inds.push(i0);
-                    inds.push(i0 + 1);
-                    inds.push(i0 + 2);
-                }
-                Renderable::Quadrilateral(v0, v1, v2, v3) => {
-                    self.ensure_capacity(frame, texture, &mut verts, &mut inds, 4, 6);
-                    let i0 = verts.len() as u16;
-                    verts.push(v0);
-                    verts.push(v1);
-                    verts.push(v2);
-                    verts.push(v3);
-                    inds.push(i0);
-                    inds.push(i0 + 1);
-                    inds.push(i0 + 2);
-                    inds.push(i0);
-                    inds.push(i0 + 2);
-                    inds.push(i0 + 3);
-                }
+/// Converts renderables into vertex and index data.
+/// Whenever the data would no longer fit into a batch's buffers, and once more at the end, the data so far
+/// is passed to `flush`, which is expected to draw and then clear it.
+pub fn generate_geometry(
+    items: impl Iterator<Item = Renderable>,
+    mut flush: impl FnMut(&mut Vec<Vertex>, &mut Vec<u16>),
+) {
+    // Store the vertices and indices so that we can write them to the vertex buffer and index buffer in a single function call.
+    let mut verts = Vec::<Vertex>::new();
+    let mut inds = Vec::<u16>::new();
+
+    // If there is insufficient capacity to store this amount of new vertices and indices, we will flush
+    // the buffers so that they are free to be used.
+    let mut ensure_capacity =
+        |verts: &mut Vec<Vertex>, inds: &mut Vec<u16>, new_verts, new_inds| {
+            if verts.len() + new_verts > MAX_VERTEX_COUNT || inds.len() + new_inds > MAX_INDEX_COUNT
+            {
+                flush(verts, inds);
+                verts.clear();
+                inds.clear();
+            }
+        };
+
+    for renderable in items {
+        match renderable {
+            Renderable::Empty => {}
+            Renderable::Triangle(v0, v1, v2) => {
+                ensure_capacity(&mut verts, &mut inds, 3, 3);
+                let i0 = verts.len() as u16;
+                verts.push(v0);
+                verts.push(v1);
+                verts.push(v2);
+                inds.push(i0);
+                inds.push(i0 + 1);
+                inds.push(i0 + 2);
+            }
+            Renderable::Quadrilateral(v0, v1, v2, v3) => {
+                ensure_capacity(&mut verts, &mut inds, 4, 6);
+                let i0 = verts.len() as u16;
+                verts.push(v0);
+                verts.push(v1);
+                verts.push(v2);
+                verts.push(v3);
+                inds.push(i0);
+                inds.push(i0 + 1);
+                inds.push(i0 + 2);
+                inds.push(i0);
+                inds.push(i0 + 2);
+                inds.push(i0 + 3);
             }
         }
+    }
 
-        self.flush(frame, texture, &mut verts, &mut inds);
+    flush(&mut verts, &mut inds);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The build script checks the shaders against `shader_interface.rs`, so the Rust types must match it too.
+    #[test]
+    fn types_match_shader_interface() {
+        assert_eq!(std::mem::size_of::<Uniforms>(), UNIFORMS_SIZE as usize);
+        let descriptor = Vertex::get_buffer_descriptor();
+        assert_eq!(descriptor.attributes.len(), VERTEX_ATTRIBUTES.len());
+        for (attribute, &(location, components)) in
+            descriptor.attributes.iter().zip(&VERTEX_ATTRIBUTES)
+        {
+            assert_eq!(attribute.shader_location, location);
+            assert_eq!(
+                attribute.format.size(),
+                components as u64 * std::mem::size_of::<f32>() as u64
+            );
+        }
     }
 }
@@ -0,0 +1,34 @@
+use qs_common::assets::OwnedAsset;
+use texture_atlas::BitmapFont;
+
+use super::{PartitionedTexture, Texture, TextureRegion};
+
+/// A bitmap font whose glyphs are regions of a partitioned texture. Text in a bitmap font is drawn by the regular batch
+/// rather than the text renderer's glyph cache, which suits pixel-art fonts that shouldn't be antialiased.
+pub struct BitmapFontTexture {
+    pub font: BitmapFont,
+    /// Contains a region for each glyph that has an image, named by `BitmapFont::glyph_region_name`.
+    pub texture: OwnedAsset<PartitionedTexture>,
+}
+
+impl BitmapFontTexture {
+    /// Creates a bitmap font from its description and the textures of each of its pages.
+    pub fn new(font: BitmapFont, pages: Vec<Texture>) -> Self {
+        let atlas = font.to_atlas();
+        Self {
+            font,
+            texture: OwnedAsset::new(PartitionedTexture { pages, atlas }),
+        }
+    }
+
+    /// The region containing the image of the given character's glyph, or `None` if the font has no image for it.
+    pub fn glyph_region(&self, c: char) -> Option<TextureRegion> {
+        let glyph = self.font.glyphs.get(&c)?;
+        Some(TextureRegion::with_info(
+            (*self.texture).clone(),
+            glyph.page,
+            glyph.region_info()?,
+            self.font.page_size,
+        ))
+    }
+}
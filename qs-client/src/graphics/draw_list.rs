@@ -0,0 +1,245 @@
+use std::ops::Range;
+
+use cgmath::Matrix4;
+use stretch::geometry::Point;
+
+use crate::ui::RenderableWord;
+
+use super::{generate_geometry, BatchRenderTexture, Camera, MultiRenderable, RenderStep, Vertex};
+
+/// Which pipeline is used to draw a `DrawCommand`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DrawPipeline {
+    /// The regular batch, using its pipeline for the encoding of the command's texture.
+    Batch,
+    /// The text renderer, which draws glyphs from its glyph cache.
+    Text,
+}
+
+/// A single draw call in a `DrawList`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrawCommand {
+    pub pipeline: DrawPipeline,
+    /// The texture to draw with. Text is always drawn with the glyph cache, so this is `Nothing` for text.
+    pub texture: BatchRenderTexture,
+    /// The index of the camera in `DrawList::cameras`.
+    pub camera: usize,
+    /// The vertices to draw from `DrawList::vertices`.
+    pub vertices: Range<usize>,
+    /// The indices to draw from `DrawList::indices`. These are relative to the first of the command's vertices.
+    pub indices: Range<usize>,
+    /// The words to draw from `DrawList::words`.
+    /// Text can only be triangulated once its glyphs are in the glyph cache, so text commands have words instead of vertices.
+    pub words: Range<usize>,
+}
+
+/// A list of draw calls, recorded from `MultiRenderable`s, that doesn't depend on how they're actually drawn.
+/// `MultiBatch` executes draw lists on the graphics card, and `SoftwareRenderer` executes them without one.
+///
+/// Each command is drawn after the commands before it, so its output is on top of theirs.
+#[derive(Default)]
+pub struct DrawList {
+    /// The combined projection and view matrix of each camera that commands are drawn with.
+    pub cameras: Vec<Matrix4<f32>>,
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u16>,
+    pub words: Vec<(Point<f32>, RenderableWord)>,
+    pub commands: Vec<DrawCommand>,
+}
+
+impl DrawList {
+    /// Records the draw calls needed to render the renderable as seen by the given camera, after those already in the list.
+    ///
+    /// Items with no texture are skipped. Large batches are split into several draw calls, so that each one fits into the batch's buffers.
+    pub fn record(&mut self, renderable: MultiRenderable, camera: &Camera) {
+        let camera_index = self.cameras.len();
+        self.cameras
+            .push(camera.get_projection_matrix() * camera.get_view_matrix());
+
+        for step in renderable.flatten() {
+            match step {
+                RenderStep::Text(text) => {
+                    let words = self.words.len()..self.words.len() + text.len();
+                    self.words.extend(text);
+                    self.commands.push(DrawCommand {
+                        pipeline: DrawPipeline::Text,
+                        texture: BatchRenderTexture::Nothing,
+                        camera: camera_index,
+                        vertices: self.vertices.len()..self.vertices.len(),
+                        indices: self.indices.len()..self.indices.len(),
+                        words,
+                    });
+                }
+                RenderStep::Batch {
+                    texture: BatchRenderTexture::Nothing,
+                    ..
+                } => {}
+                RenderStep::Batch {
+                    texture,
+                    renderables,
+                } => {
+                    let DrawList {
+                        vertices,
+                        indices,
+                        words,
+                        commands,
+                        ..
+                    } = self;
+                    generate_geometry(renderables.into_iter(), |verts, inds| {
+                        if !inds.is_empty() {
+                            commands.push(DrawCommand {
+                                pipeline: DrawPipeline::Batch,
+                                texture: texture.clone(),
+                                camera: camera_index,
+                                vertices: vertices.len()..vertices.len() + verts.len(),
+                                indices: indices.len()..indices.len() + inds.len(),
+                                words: words.len()..words.len(),
+                            });
+                            vertices.append(verts);
+                            indices.append(inds);
+                        }
+                        verts.clear();
+                        inds.clear();
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::{CameraData, PartitionedTexture, Renderable, TextureRegion};
+    use qs_common::assets::OwnedAsset;
+    use std::collections::HashMap;
+    use texture_atlas::{Rect, TextureAtlas, TextureRegionInformation};
+
+    fn camera() -> Camera {
+        Camera::new(CameraData::Orthographic {
+            eye: cgmath::Point2::new(0.0, 0.0),
+            view_height: 100.0,
+            aspect_ratio: 1.0,
+        })
+    }
+
+    fn quad() -> Renderable {
+        let vertex = Vertex {
+            position: [0.0; 3],
+            color: [1.0; 4],
+            tex_coords: [0.0; 2],
+        };
+        Renderable::Quadrilateral(vertex, vertex, vertex, vertex)
+    }
+
+    /// Creates a partitioned texture with no pages, since tests have no graphics card to upload them to.
+    fn partitioned_texture() -> OwnedAsset<PartitionedTexture> {
+        OwnedAsset::new(PartitionedTexture {
+            pages: Vec::new(),
+            atlas: TextureAtlas {
+                pages: Vec::new(),
+                animations: HashMap::new(),
+                texture_options: Default::default(),
+            },
+        })
+    }
+
+    fn region(
+        texture: &OwnedAsset<PartitionedTexture>,
+        page: usize,
+        quads: usize,
+    ) -> MultiRenderable {
+        let rect = Rect {
+            x: 0,
+            y: 0,
+            w: 8,
+            h: 8,
+        };
+        let info = TextureRegionInformation {
+            frame: rect,
+            rotated: false,
+            trimmed: false,
+            source: rect,
+            nine_patch: None,
+            pivot: None,
+        };
+        MultiRenderable::ImageRegion {
+            texture: TextureRegion::with_info((*texture).clone(), page, info, (64, 64)),
+            renderables: vec![quad(); quads],
+        }
+    }
+
+    #[test]
+    fn draw_calls_follow_layers() {
+        let texture = partitioned_texture();
+        let page = |page| BatchRenderTexture::PartitionedTexture((*texture).clone(), page);
+        let mut draw_list = DrawList::default();
+        draw_list.record(
+            MultiRenderable::Layered(vec![
+                MultiRenderable::Adjacent(vec![
+                    region(&texture, 0, 1),
+                    MultiRenderable::Text {
+                        word: RenderableWord::new(Vec::new(), (0, 0)),
+                        offset: Point { x: 0.0, y: 0.0 },
+                    },
+                    region(&texture, 0, 2),
+                ]),
+                region(&texture, 1, 1),
+                region(&texture, 0, 1),
+            ]),
+            &camera(),
+        );
+        draw_list.record(region(&texture, 1, 1), &camera());
+
+        let summary = draw_list
+            .commands
+            .iter()
+            .map(|command| {
+                (
+                    command.pipeline,
+                    command.texture.clone(),
+                    command.camera,
+                    command.vertices.clone(),
+                    command.indices.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        // Text is drawn before the images in the same layer.
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    DrawPipeline::Text,
+                    BatchRenderTexture::Nothing,
+                    0,
+                    0..0,
+                    0..0
+                ),
+                (DrawPipeline::Batch, page(0), 0, 0..12, 0..18),
+                (DrawPipeline::Batch, page(1), 0, 12..16, 18..24),
+                (DrawPipeline::Batch, page(0), 0, 16..20, 24..30),
+                (DrawPipeline::Batch, page(1), 1, 20..24, 30..36),
+            ]
+        );
+        assert_eq!(draw_list.commands[0].words, 0..1);
+        assert_eq!(draw_list.cameras.len(), 2);
+        // Indices are relative to the first vertex of each draw call.
+        assert_eq!(&draw_list.indices[30..36], &[0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn large_batches_are_split() {
+        let texture = partitioned_texture();
+        let mut draw_list = DrawList::default();
+        draw_list.record(region(&texture, 0, 20000), &camera());
+
+        assert_eq!(draw_list.commands.len(), 2);
+        for command in &draw_list.commands {
+            let vertex_count = command.vertices.len() as u16;
+            assert!(draw_list.indices[command.indices.clone()]
+                .iter()
+                .all(|index| *index < vertex_count));
+        }
+        assert_eq!(draw_list.vertices.len(), 80000);
+    }
+}
@@ -15,19 +15,22 @@ use winit::{
 
 use crate::{
     assets::{
-        FontAssetLoader, PartitionedTextureAssetLoader, PartitionedTextureAtlasPaths,
-        TextureAssetLoader,
+        BitmapFontAssetLoader, FontAssetLoader, PartitionedTextureAssetLoader, TextureAssetLoader,
     },
     ui::*,
 };
 use qs_common::profile::InterpolatedStopwatch;
 use qs_common::{
     assets::{AssetManager, AssetPath},
-    profile::ProfileSegmentGuard,
+    profile::{CycleProfiler, ProfileSegmentGuard, SharedProfiler},
 };
 
 mod batch;
 pub use batch::*;
+mod draw_list;
+pub use draw_list::*;
+mod bitmap_font;
+pub use bitmap_font::*;
 mod texture;
 // want to use our texture struct over the wgpu texture
 pub use texture::Texture;
@@ -38,6 +41,10 @@ mod text;
 pub use text::*;
 mod multi_batch;
 pub use multi_batch::*;
+mod shader_interface;
+pub use shader_interface::*;
+mod software_renderer;
+pub use software_renderer::*;
 
 /// This struct represents the state of the whole application and contains all of the `winit`
 /// and `wgpu` data for rendering things to the screen.
@@ -57,14 +64,15 @@ pub struct Application {
 
     last_frame_time: Instant,
     fps_counter: InterpolatedStopwatch,
+    /// Background tasks such as asset loading and typesetting record their timings here.
+    /// This is merged into the main profiler's report.
+    shared_profiler: SharedProfiler,
 
     texture_am: AssetManager<AssetPath, Texture, TextureAssetLoader>,
-    _partitioned_texture_am: AssetManager<
-        PartitionedTextureAtlasPaths,
-        PartitionedTexture,
-        PartitionedTextureAssetLoader,
-    >,
+    _partitioned_texture_am:
+        AssetManager<AssetPath, PartitionedTexture, PartitionedTextureAssetLoader>,
     _font_am: AssetManager<AssetPath, rusttype::Font<'static>, FontAssetLoader>,
+    _bitmap_font_am: AssetManager<AssetPath, BitmapFontTexture, BitmapFontAssetLoader>,
     camera: Camera,
     ui_camera: Camera,
     multi_batch: MultiBatch,
@@ -141,7 +149,7 @@ impl Application {
         let texture_bind_group_layout_desc = &BindGroupLayoutDescriptor {
             entries: &[
                 BindGroupLayoutEntry {
-                    binding: 0,
+                    binding: TEXTURE_BINDING,
                     visibility: ShaderStage::FRAGMENT,
                     ty: BindingType::SampledTexture {
                         multisampled: false,
@@ -151,7 +159,7 @@ impl Application {
                     count: None,
                 },
                 BindGroupLayoutEntry {
-                    binding: 1,
+                    binding: SAMPLER_BINDING,
                     visibility: ShaderStage::FRAGMENT,
                     ty: BindingType::Sampler { comparison: false },
                     count: None,
@@ -162,7 +170,7 @@ impl Application {
         // Define how we want to bind uniforms.
         let uniform_bind_group_layout_desc = wgpu::BindGroupLayoutDescriptor {
             entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
+                binding: UNIFORMS_BINDING,
                 visibility: wgpu::ShaderStage::VERTEX,
                 ty: wgpu::BindingType::UniformBuffer {
                     dynamic: false,
@@ -189,23 +197,47 @@ impl Application {
             Arc::clone(&device),
             Arc::clone(&queue),
             include_spirv!("shader.vert.spv"),
-            include_spirv!("shader.frag.spv"),
+            vec![
+                (
+                    TextureEncoding::StraightAlpha,
+                    include_spirv!("shader.frag.spv"),
+                ),
+                (
+                    TextureEncoding::PremultipliedAlpha,
+                    include_spirv!("shader.premultiplied.frag.spv"),
+                ),
+                (
+                    TextureEncoding::DistanceField,
+                    include_spirv!("shader.distance_field.frag.spv"),
+                ),
+            ],
             device.create_bind_group_layout(&texture_bind_group_layout_desc),
             device.create_bind_group_layout(&uniform_bind_group_layout_desc),
             swap_chain_descriptor.format,
         );
 
+        let shared_profiler = SharedProfiler::new(25);
+
         let mut texture_am = AssetManager::new(TextureAssetLoader::new(
             Arc::clone(&device),
             Arc::clone(&queue),
         ));
+        texture_am.set_profiler(shared_profiler.clone());
 
         let mut partitioned_texture_am = AssetManager::new(PartitionedTextureAssetLoader::new(
             Arc::clone(&device),
             Arc::clone(&queue),
         ));
+        partitioned_texture_am.set_profiler(shared_profiler.clone());
 
         let mut font_am = AssetManager::new(FontAssetLoader::default());
+        font_am.set_profiler(shared_profiler.clone());
+
+        let mut bitmap_font_am = AssetManager::new(BitmapFontAssetLoader::new(
+            Arc::clone(&device),
+            Arc::clone(&queue),
+        ));
+        bitmap_font_am.set_profiler(shared_profiler.clone());
 
         let text_renderer = TextRenderer::new(
             Arc::clone(&device),
@@ -219,13 +251,28 @@ impl Application {
         let multi_batch = MultiBatch::new(batch, text_renderer);
 
         let mut test_text = RichText::new(Default::default());
-        let test_font_family = Arc::new(FontFamily::new(vec![FontFace::new(
-            "Noto Sans".to_string(),
-            font_am.get(AssetPath::new(vec!["NotoSans-Regular.ttf".to_string()])),
-            Some(font_am.get(AssetPath::new(vec!["NotoSans-Bold.ttf".to_string()]))),
-            Some(font_am.get(AssetPath::new(vec!["NotoSans-Italic.ttf".to_string()]))),
-            Some(font_am.get(AssetPath::new(vec!["NotoSans-BoldItalic.ttf".to_string()]))),
-        )]));
+        test_text.set_profiler(shared_profiler.clone());
+        // The distance field fonts baked by the build script only have some characters, so fall back to the vector fonts.
+        let test_font_family = Arc::new(FontFamily::new(vec![
+            FontFace::bitmap(
+                "Noto Sans SDF".to_string(),
+                bitmap_font_am.get(AssetPath::new(vec!["NotoSans-Regular.sdf.fnt".to_string()])),
+                Some(bitmap_font_am.get(AssetPath::new(vec!["NotoSans-Bold.sdf.fnt".to_string()]))),
+                Some(
+                    bitmap_font_am.get(AssetPath::new(vec!["NotoSans-Italic.sdf.fnt".to_string()])),
+                ),
+                Some(bitmap_font_am.get(AssetPath::new(vec![
+                    "NotoSans-BoldItalic.sdf.fnt".to_string(),
+                ]))),
+            ),
+            FontFace::new(
+                "Noto Sans".to_string(),
+                font_am.get(AssetPath::new(vec!["NotoSans-Regular.ttf".to_string()])),
+                Some(font_am.get(AssetPath::new(vec!["NotoSans-Bold.ttf".to_string()]))),
+                Some(font_am.get(AssetPath::new(vec!["NotoSans-Italic.ttf".to_string()]))),
+                Some(font_am.get(AssetPath::new(vec!["NotoSans-BoldItalic.ttf".to_string()]))),
+            ),
+        ]));
         let _ = test_text.set_text(Arc::clone(&test_font_family))
         .h1(|b| b
             .write("Header thing ")
@@ -263,10 +310,10 @@ impl Application {
         .write("Lorem ipsum dolor sit amet, consectetur adipiscing elit. Ut facilisis elit at massa placerat, in placerat est pretium. Curabitur consequat porta ante vel pharetra. Vestibulum sit amet mauris rhoncus, facilisis felis et, elementum arcu. In hac habitasse platea dictumst. Nam at felis non lectus aliquam consectetur nec quis tellus. Proin id dictum massa. Sed id condimentum mauris. Morbi eget dictum ligula, non faucibus ante. Morbi viverra ut diam vitae malesuada. Donec porta enim non porttitor euismod. Proin faucibus sit amet diam nec molestie. Fusce porta scelerisque lectus, quis ultrices augue maximus a.")
         .finish().await.expect("could not complete task");
 
-        let ui_atlas = partitioned_texture_am.get(PartitionedTextureAtlasPaths {
-            texture: AssetPath::new(vec!["ui".to_string(), "atlas.png".to_string()]),
-            atlas: AssetPath::new(vec!["ui".to_string(), "atlas.json".to_string()]),
-        });
+        let ui_atlas = partitioned_texture_am.get(AssetPath::new(vec![
+            "ui".to_string(),
+            "atlas.bin".to_string(),
+        ]));
 
         let tr_button = TextureRegion::new(ui_atlas.clone(), "button.png".to_string()).await;
         let tr_button_hovered =
@@ -278,34 +325,10 @@ impl Application {
         let tr_caret = TextureRegion::new(ui_atlas.clone(), "caret.png".to_string()).await;
 
         let button_style = ButtonStyle {
-            released_texture: NinePatch {
-                texture_region: tr_button,
-                left_margin: 2,
-                right_margin: 2,
-                top_margin: 2,
-                bottom_margin: 2,
-            },
-            hovered_texture: NinePatch {
-                texture_region: tr_button_hovered,
-                left_margin: 2,
-                right_margin: 2,
-                top_margin: 2,
-                bottom_margin: 2,
-            },
-            pressed_texture: NinePatch {
-                texture_region: tr_button_pressed,
-                left_margin: 2,
-                right_margin: 2,
-                top_margin: 2,
-                bottom_margin: 2,
-            },
-            disabled_texture: NinePatch {
-                texture_region: tr_button_disabled,
-                left_margin: 2,
-                right_margin: 2,
-                top_margin: 2,
-                bottom_margin: 2,
-            },
+            released_texture: NinePatch::from_region(tr_button).await,
+            hovered_texture: NinePatch::from_region(tr_button_hovered).await,
+            pressed_texture: NinePatch::from_region(tr_button_pressed).await,
+            disabled_texture: NinePatch::from_region(tr_button_disabled).await,
         };
 
         let test_button = Widget::new(
@@ -324,13 +347,7 @@ impl Application {
         );
 
         let test_field = Field::new(
-            NinePatch {
-                texture_region: tr_caret,
-                left_margin: 1,
-                right_margin: 1,
-                top_margin: 2,
-                bottom_margin: 2,
-            },
+            NinePatch::from_region(tr_caret).await,
             Arc::clone(&test_font_family),
             Default::default(),
             Default::default(),
@@ -383,10 +400,12 @@ impl Application {
 
             last_frame_time: Instant::now(),
             fps_counter: InterpolatedStopwatch::new(100),
+            shared_profiler,
 
             texture_am,
             _partitioned_texture_am: partitioned_texture_am,
             _font_am: font_am,
+            _bitmap_font_am: bitmap_font_am,
             camera,
             ui_camera,
             multi_batch,
@@ -570,7 +589,8 @@ impl Application {
 
     /// Executes the application.
     pub fn run(mut self, event_loop: EventLoop<()>) {
-        let mut profiler = qs_common::profile::CycleProfiler::new(25);
+        let mut profiler = CycleProfiler::new(25);
+        profiler.shared = self.shared_profiler.clone();
 
         event_loop.run(move |event, _, control_flow| {
             match event {
@@ -587,6 +607,33 @@ impl Application {
                             {
                                 *control_flow = ControlFlow::Exit;
                             }
+
+                            // F9 starts recording a flamegraph, and pressing it again writes the recording to disk.
+                            if let KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F9),
+                                ..
+                            } = input
+                            {
+                                match profiler.finish_recording() {
+                                    Some(recording) => {
+                                        match recording.write_folded_to_file("profile.folded") {
+                                            Ok(()) => tracing::info!(
+                                                "wrote {} frames of folded stacks to profile.folded",
+                                                recording.frames
+                                            ),
+                                            Err(e) => tracing::error!(
+                                                "could not write folded stacks: {}",
+                                                e
+                                            ),
+                                        }
+                                    }
+                                    None => {
+                                        tracing::info!("recording flamegraph");
+                                        profiler.start_recording();
+                                    }
+                                }
+                            }
                         }
 
                         WindowEvent::CursorMoved { position, .. } => {
@@ -616,6 +663,8 @@ impl Application {
                             futures::executor::block_on(self.render(render));
                         }
                     }
+                    // Any hitches are logged by the profiler's hitch detector.
+                    profiler.end_frame();
                     if profiler.main_segment.ticks % 100 == 0 {
                         //tracing::trace!("{}", profiler);
                     }
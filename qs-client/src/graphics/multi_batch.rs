@@ -1,7 +1,6 @@
 use std::mem::take;
 
 use crate::graphics::*;
-use futures::future::{BoxFuture, FutureExt};
 use qs_common::assets::Asset;
 use stretch::geometry::Point;
 
@@ -17,10 +16,11 @@ pub struct MultiBatch {
 
 /// What texture do we need to use to render the `batch_render_data`?
 #[derive(Debug, Clone, Eq, PartialEq)]
-enum BatchRenderTexture {
+pub enum BatchRenderTexture {
     Nothing,
     Texture(Asset<Texture>),
-    PartitionedTexture(Asset<PartitionedTexture>),
+    /// A page of a partitioned texture, given by its index.
+    PartitionedTexture(Asset<PartitionedTexture>, usize),
 }
 
 impl BatchRenderTexture {
@@ -35,12 +35,24 @@ impl BatchRenderTexture {
     }
 }
 
-struct MultiBatchRenderState<'a> {
-    text_render_data: &'a mut Vec<(Point<f32>, RenderableWord)>,
-    batch_render_data: &'a mut Vec<Renderable>,
-    batch_render_texture: &'a mut BatchRenderTexture,
-    frame: &'a wgpu::SwapChainTexture,
-    camera: &'a Camera,
+/// A group of items from a `MultiRenderable` that can be rendered together in a single call to one of the batches.
+/// Flattening a `MultiRenderable` produces a list of these, in the order they must be rendered.
+pub enum RenderStep {
+    /// Render these words using the text renderer.
+    Text(Vec<(Point<f32>, RenderableWord)>),
+    /// Render these items using the regular batch with the given texture.
+    Batch {
+        texture: BatchRenderTexture,
+        renderables: Vec<Renderable>,
+    },
+}
+
+struct MultiBatchRenderState {
+    text_render_data: Vec<(Point<f32>, RenderableWord)>,
+    batch_render_data: Vec<Renderable>,
+    batch_render_texture: BatchRenderTexture,
+    /// The steps that have been completed so far.
+    steps: Vec<RenderStep>,
 }
 
 impl MultiBatch {
@@ -51,131 +63,140 @@ impl MultiBatch {
         }
     }
 
-    /// The rendering algorithm essentially is that we should keep adding data to a list of
-    /// text/batch items to render until we hit a new layer, after which we should render the intermediate
-    /// lists to the batches.
+    /// The renderable is recorded into a `DrawList`, which is then executed on the graphics card.
     pub async fn render(
         &mut self,
         renderable: MultiRenderable,
         frame: &wgpu::SwapChainTexture,
         camera: &Camera,
-        _profiler: qs_common::profile::ProfileSegmentGuard<'_>,
+        mut profiler: qs_common::profile::ProfileSegmentGuard<'_>,
     ) {
-        let mut text_render_data: Vec<(Point<f32>, RenderableWord)> = Vec::new();
-        let mut batch_render_data: Vec<Renderable> = Vec::new();
-        let mut batch_render_texture = BatchRenderTexture::Nothing;
-        let mut state = MultiBatchRenderState {
-            text_render_data: &mut text_render_data,
-            batch_render_data: &mut batch_render_data,
-            batch_render_texture: &mut batch_render_texture,
-            frame,
-            camera,
-        };
-
-        state.incremental_render(renderable, self).await;
-        state.perform_render(self).await;
+        let mut draw_list = DrawList::default();
+        {
+            let _guard = profiler.task("record").time();
+            draw_list.record(renderable, camera);
+        }
+
+        let _guard = profiler.task("draw").time();
+        self.execute(&draw_list, frame).await;
+    }
+
+    /// Executes each command in the draw list in order. Commands whose texture has not loaded are skipped.
+    pub async fn execute(&mut self, draw_list: &DrawList, frame: &wgpu::SwapChainTexture) {
+        for command in &draw_list.commands {
+            let transform = draw_list.cameras[command.camera];
+            let vertices = &draw_list.vertices[command.vertices.clone()];
+            let indices = &draw_list.indices[command.indices.clone()];
+            let batch = &mut self.batch;
+            // TODO make a default texture for unloaded textures.
+            match (command.pipeline, &command.texture) {
+                (DrawPipeline::Text, _) => {
+                    self.text_renderer.draw_text(
+                        &draw_list.words[command.words.clone()],
+                        frame,
+                        transform,
+                    );
+                }
+                (DrawPipeline::Batch, BatchRenderTexture::Nothing) => {}
+                (DrawPipeline::Batch, BatchRenderTexture::Texture(tex)) => {
+                    tex.if_loaded(|tex| {
+                        batch.draw(frame, tex, transform, vertices, indices);
+                    })
+                    .await;
+                }
+                (DrawPipeline::Batch, BatchRenderTexture::PartitionedTexture(tex, page)) => {
+                    tex.if_loaded(|tex| {
+                        batch.draw(frame, &tex.pages[*page], transform, vertices, indices);
+                    })
+                    .await;
+                }
+            }
+        }
     }
 }
 
-impl<'a> MultiBatchRenderState<'a> {
-    /// Appends render information to the given data, calling `perform_render` if we need to.
-    fn incremental_render<'b>(
-        &'b mut self,
-        renderable: MultiRenderable,
-        batch: &'b mut MultiBatch,
-    ) -> BoxFuture<()> {
-        async move {
-            match renderable {
-                MultiRenderable::Nothing => {}
-                MultiRenderable::Layered(layers) => {
-                    for (layer, index) in layers.into_iter().zip(0i32..) {
-                        if index != 0 {
-                            self.perform_render(batch).await;
-                        }
-                        self.incremental_render(layer, batch).await;
+impl MultiBatchRenderState {
+    fn new() -> Self {
+        Self {
+            text_render_data: Vec::new(),
+            batch_render_data: Vec::new(),
+            batch_render_texture: BatchRenderTexture::Nothing,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Appends render information to the given data, calling `end_step` if we need to.
+    fn incremental_render(&mut self, renderable: MultiRenderable) {
+        match renderable {
+            MultiRenderable::Nothing => {}
+            MultiRenderable::Layered(layers) => {
+                for (layer, index) in layers.into_iter().zip(0i32..) {
+                    if index != 0 {
+                        self.end_step();
                     }
+                    self.incremental_render(layer);
                 }
-                MultiRenderable::Adjacent(items) => {
-                    for item in items {
-                        self.incremental_render(item, batch).await;
-                    }
+            }
+            MultiRenderable::Adjacent(items) => {
+                for item in items {
+                    self.incremental_render(item);
                 }
-                MultiRenderable::Text { word, offset } => {
-                    self.text_render_data.push((offset, word));
+            }
+            MultiRenderable::Text { word, offset } => {
+                self.text_render_data.push((offset, word));
+            }
+            MultiRenderable::Image {
+                texture,
+                mut renderables,
+            } => {
+                let new_render_texture = BatchRenderTexture::Texture(texture);
+                if !self
+                    .batch_render_texture
+                    .compatible_with(new_render_texture.clone())
+                {
+                    self.end_step();
                 }
-                MultiRenderable::Image {
-                    texture,
-                    mut renderables,
-                } => {
-                    let new_render_texture = BatchRenderTexture::Texture(texture);
-                    if !self
-                        .batch_render_texture
-                        .compatible_with(new_render_texture.clone())
-                    {
-                        self.perform_render(batch).await;
-                    }
-                    *self.batch_render_texture = new_render_texture;
+                self.batch_render_texture = new_render_texture;
 
-                    self.batch_render_data.append(&mut renderables);
+                self.batch_render_data.append(&mut renderables);
+            }
+            MultiRenderable::ImageRegion {
+                texture,
+                mut renderables,
+            } => {
+                // Regions on different pages of the same partitioned texture can't be rendered together.
+                let new_render_texture = BatchRenderTexture::PartitionedTexture(
+                    texture.partitioned_texture.clone(),
+                    texture.page().unwrap_or_default(),
+                );
+                if !self
+                    .batch_render_texture
+                    .compatible_with(new_render_texture.clone())
+                {
+                    self.end_step();
                 }
-                MultiRenderable::ImageRegion {
-                    texture,
-                    mut renderables,
-                } => {
-                    let new_render_texture =
-                        BatchRenderTexture::PartitionedTexture(texture.partitioned_texture.clone());
-                    if !self
-                        .batch_render_texture
-                        .compatible_with(new_render_texture.clone())
-                    {
-                        self.perform_render(batch).await;
-                    }
-                    *self.batch_render_texture = new_render_texture;
+                self.batch_render_texture = new_render_texture;
 
-                    self.batch_render_data.append(&mut renderables);
-                }
+                self.batch_render_data.append(&mut renderables);
             }
         }
-        .boxed()
     }
 
-    async fn perform_render<'b>(&'b mut self, batch: &'b mut MultiBatch) {
+    /// Moves the pending text and batch data into the list of completed steps.
+    /// Text is rendered before the batch data.
+    fn end_step(&mut self) {
         if !self.text_render_data.is_empty() {
-            batch.text_renderer.draw_text(
-                take(self.text_render_data),
-                self.frame,
-                self.camera,
-                //profiler.task("text").time(),
-            );
+            self.steps
+                .push(RenderStep::Text(take(&mut self.text_render_data)));
         }
         if !self.batch_render_data.is_empty() {
-            let render_texture =
-                std::mem::replace(self.batch_render_texture, BatchRenderTexture::Nothing);
-            match render_texture {
-                BatchRenderTexture::Nothing => {}
-                BatchRenderTexture::Texture(tex) => {
-                    tex.if_loaded(|tex| {
-                        batch.batch.render(
-                            self.frame,
-                            &tex,
-                            self.camera,
-                            take(self.batch_render_data).into_iter(),
-                        );
-                    })
-                    .await;
-                }
-                BatchRenderTexture::PartitionedTexture(tex) => {
-                    tex.if_loaded(|tex| {
-                        batch.batch.render(
-                            self.frame,
-                            &tex.base_texture,
-                            self.camera,
-                            take(self.batch_render_data).into_iter(),
-                        );
-                    })
-                    .await;
-                }
-            }
+            self.steps.push(RenderStep::Batch {
+                texture: std::mem::replace(
+                    &mut self.batch_render_texture,
+                    BatchRenderTexture::Nothing,
+                ),
+                renderables: take(&mut self.batch_render_data),
+            });
         }
     }
 }
@@ -216,3 +237,14 @@ pub enum MultiRenderable {
         renderables: Vec<Renderable>,
     },
 }
+
+impl MultiRenderable {
+    /// Splits this renderable into the list of steps needed to render it, in order.
+    /// Each step can be rendered with a single call to one of the batches.
+    pub fn flatten(self) -> Vec<RenderStep> {
+        let mut state = MultiBatchRenderState::new();
+        state.incremental_render(self);
+        state.end_step();
+        state.steps
+    }
+}
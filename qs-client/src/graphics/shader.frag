@@ -9,5 +9,19 @@ layout(set = 0, binding = 0) uniform texture2D t_diffuse;
 layout(set = 0, binding = 1) uniform sampler s_diffuse;
 
 void main() {
+#if defined(GLYPH_CACHE)
+    // The glyph cache only stores coverage, in the red channel.
+    f_color = v_color * vec4(1.0, 1.0, 1.0, texture(sampler2D(t_diffuse, s_diffuse), v_tex_coords).r);
+#elif defined(PREMULTIPLIED_ALPHA)
+    // The texture's colour has been multiplied by its alpha, so the vertex colour must be too.
+    f_color = vec4(v_color.rgb * v_color.a, v_color.a) * texture(sampler2D(t_diffuse, s_diffuse), v_tex_coords);
+#elif defined(DISTANCE_FIELD)
+    // The alpha channel stores the distance to the glyph's outline, which is at one half.
+    // Fading out over a single pixel on screen keeps the edge sharp however large the text is drawn.
+    float distance = texture(sampler2D(t_diffuse, s_diffuse), v_tex_coords).a;
+    float alpha = clamp((distance - 0.5) / max(fwidth(distance), 0.0001) + 0.5, 0.0, 1.0);
+    f_color = vec4(v_color.rgb, v_color.a * alpha);
+#else
     f_color = v_color * texture(sampler2D(t_diffuse, s_diffuse), v_tex_coords);
-}
\ No newline at end of file
+#endif
+}
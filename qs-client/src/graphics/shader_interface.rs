@@ -0,0 +1,17 @@
+//! Describes what the renderer provides to the batch shaders, so that the renderer and the shaders can't disagree.
+//! The build script includes this file as well, and checks every compiled shader against it,
+//! so it must not refer to anything outside this file.
+
+/// The shader location of each field of `Vertex`, and how many `f32` components it has.
+pub const VERTEX_ATTRIBUTES: [(u32, u32); 3] = [(0, 3), (1, 4), (2, 2)];
+
+/// The bind group containing the texture to draw and its sampler.
+pub const TEXTURE_SET: u32 = 0;
+pub const TEXTURE_BINDING: u32 = 0;
+pub const SAMPLER_BINDING: u32 = 1;
+
+/// The bind group containing `Uniforms`.
+pub const UNIFORMS_SET: u32 = 1;
+pub const UNIFORMS_BINDING: u32 = 0;
+/// The size of `Uniforms` in bytes.
+pub const UNIFORMS_SIZE: u32 = 64;
@@ -0,0 +1,597 @@
+use cgmath::{Matrix4, Vector4};
+use image::RgbaImage;
+use qs_common::assets::Asset;
+use stretch::geometry::Point;
+use texture_atlas::{linear_to_srgb, srgb_to_linear};
+
+use crate::ui::{Colour, GlyphImage, RenderableGlyph, RenderableWord};
+
+use super::{
+    generate_geometry, glyph_quad, BatchRenderTexture, Camera, DrawList, DrawPipeline,
+    MultiRenderable, PartitionedTexture, Texture, TextureEncoding, Vertex,
+};
+
+/// Vertex positions are snapped to this many fractions of a pixel, as a graphics card does. Since the positions are
+/// then whole numbers, triangles that share an edge agree exactly on which pixels along it belong to which triangle.
+const SUBPIXEL_PRECISION: i64 = 256;
+
+/// The pixels of a texture, which a `SoftwareRenderer` reads instead of the texture on the graphics card.
+pub struct SoftwareTexture {
+    /// The texture's image, with its colours in the sRGB colour space as it would be uploaded.
+    pub image: RgbaImage,
+    pub encoding: TextureEncoding,
+}
+
+impl SoftwareTexture {
+    pub fn new(image: RgbaImage, encoding: TextureEncoding) -> Self {
+        Self { image, encoding }
+    }
+
+    /// Reads the linear colour at the given texture coordinates, filtering linearly between the four nearest pixels
+    /// and clamping to the edge of the image.
+    fn sample(&self, [u, v]: [f32; 2]) -> [f32; 4] {
+        let (width, height) = self.image.dimensions();
+        if width == 0 || height == 0 {
+            return [0.0; 4];
+        }
+        let texel = |x: i64, y: i64| {
+            let x = x.clamp(0, width as i64 - 1) as u32;
+            let y = y.clamp(0, height as i64 - 1) as u32;
+            let [r, g, b, a] = self.image.get_pixel(x, y).0;
+            [
+                srgb_to_linear(r),
+                srgb_to_linear(g),
+                srgb_to_linear(b),
+                a as f32 / 255.0,
+            ]
+        };
+
+        let x = u * width as f32 - 0.5;
+        let y = v * height as f32 - 0.5;
+        let (left, top) = (x.floor(), y.floor());
+        let (fraction_x, fraction_y) = (x - left, y - top);
+        let (left, top) = (left as i64, top as i64);
+        let samples = [
+            (texel(left, top), (1.0 - fraction_x) * (1.0 - fraction_y)),
+            (texel(left + 1, top), fraction_x * (1.0 - fraction_y)),
+            (texel(left, top + 1), (1.0 - fraction_x) * fraction_y),
+            (texel(left + 1, top + 1), fraction_x * fraction_y),
+        ];
+        let mut result = [0.0; 4];
+        for (sample, weight) in samples.iter() {
+            for channel in 0..4 {
+                result[channel] += sample[channel] * weight;
+            }
+        }
+        result
+    }
+}
+
+/// Draws `MultiRenderable`s into an image without a graphics card, by executing their `DrawList`s in the same way as `MultiBatch`.
+/// This means that rendering can be tested on machines with no graphics card, for example by comparing against golden images.
+///
+/// Triangles are rasterised, shaded and blended as the batch's pipelines would, except that textures are always
+/// filtered linearly and never mipmapped, so the results are close to, but not exactly, what the graphics card draws.
+///
+/// Textures can't be read back from the graphics card, so their pixels must be given to the renderer with
+/// `add_texture` or `add_partitioned_texture`. Items with any other texture are skipped, just as `MultiBatch` skips
+/// textures that haven't loaded.
+pub struct SoftwareRenderer {
+    target: RenderTarget,
+    textures: Vec<(Asset<Texture>, SoftwareTexture)>,
+    partitioned_textures: Vec<(Asset<PartitionedTexture>, Vec<SoftwareTexture>)>,
+}
+
+/// The image being drawn into.
+struct RenderTarget {
+    width: u32,
+    height: u32,
+    /// The linear colour of each pixel, row by row from the top left. An sRGB render target also blends in linear space.
+    pixels: Vec<[f32; 4]>,
+}
+
+impl SoftwareRenderer {
+    /// Creates a renderer whose image has the given size, and is filled with the given colour.
+    pub fn new(width: u32, height: u32, clear_colour: Colour) -> Self {
+        Self {
+            target: RenderTarget {
+                width,
+                height,
+                pixels: vec![clear_colour.into(); (width * height) as usize],
+            },
+            textures: Vec::new(),
+            partitioned_textures: Vec::new(),
+        }
+    }
+
+    /// Gives the renderer the pixels of a texture, so that items drawn with it can be rendered.
+    pub fn add_texture(&mut self, texture: Asset<Texture>, pixels: SoftwareTexture) {
+        self.textures.push((texture, pixels));
+    }
+
+    /// Gives the renderer the pixels of each page of a partitioned texture, so that its regions can be rendered.
+    pub fn add_partitioned_texture(
+        &mut self,
+        texture: Asset<PartitionedTexture>,
+        pages: Vec<SoftwareTexture>,
+    ) {
+        self.partitioned_textures.push((texture, pages));
+    }
+
+    /// Draws the renderable on top of what has already been drawn, as seen by the given camera.
+    pub fn render(&mut self, renderable: MultiRenderable, camera: &Camera) {
+        let mut draw_list = DrawList::default();
+        draw_list.record(renderable, camera);
+        self.execute(&draw_list);
+    }
+
+    /// Executes each command in the draw list in order. Commands with textures the renderer hasn't been given are skipped.
+    pub fn execute(&mut self, draw_list: &DrawList) {
+        for command in &draw_list.commands {
+            let transform = draw_list.cameras[command.camera];
+            if command.pipeline == DrawPipeline::Text {
+                for (offset, word) in &draw_list.words[command.words.clone()] {
+                    self.target.draw_word(&transform, *offset, word);
+                }
+                continue;
+            }
+
+            let texture = match &command.texture {
+                BatchRenderTexture::Nothing => None,
+                BatchRenderTexture::Texture(asset) => self
+                    .textures
+                    .iter()
+                    .find(|(texture, _)| texture == asset)
+                    .map(|(_, pixels)| pixels),
+                BatchRenderTexture::PartitionedTexture(asset, page) => self
+                    .partitioned_textures
+                    .iter()
+                    .find(|(texture, _)| texture == asset)
+                    .and_then(|(_, pages)| pages.get(*page)),
+            };
+            if let Some(texture) = texture {
+                self.target.draw_triangles(
+                    &transform,
+                    &draw_list.vertices[command.vertices.clone()],
+                    &draw_list.indices[command.indices.clone()],
+                    texture,
+                );
+            }
+        }
+    }
+
+    /// The image drawn so far, with its colours converted to sRGB as they would be shown on the screen.
+    pub fn image(&self) -> RgbaImage {
+        let target = &self.target;
+        RgbaImage::from_fn(target.width, target.height, |x, y| {
+            let [r, g, b, a] = target.pixels[(y * target.width + x) as usize];
+            image::Rgba([
+                linear_to_srgb(r),
+                linear_to_srgb(g),
+                linear_to_srgb(b),
+                (a * 255.0).round() as u8,
+            ])
+        })
+    }
+}
+
+/// Twice the signed area of the triangle `abc`, which is positive if `c` is to the right of the line from `a` to `b`
+/// when the y axis points down.
+fn edge_function(a: [i64; 2], b: [i64; 2], c: [i64; 2]) -> i64 {
+    (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+}
+
+impl RenderTarget {
+    /// Draws the glyphs of a vector font in a word. The text renderer would draw them from its glyph cache;
+    /// instead, each glyph is rasterised into its own texture.
+    fn draw_word(&mut self, transform: &Matrix4<f32>, offset: Point<f32>, word: &RenderableWord) {
+        for RenderableGlyph { colour, glyph, .. } in &word.glyphs {
+            let glyph = match glyph {
+                GlyphImage::Vector(glyph) => glyph,
+                // Glyphs from bitmap fonts are drawn as image regions instead.
+                GlyphImage::Bitmap { .. } => continue,
+            };
+            let pixel_rect = match glyph.pixel_bounding_box() {
+                Some(pixel_rect) => pixel_rect,
+                None => continue,
+            };
+
+            // The glyph cache only stores coverage, which the text shader uses as the alpha of the glyph's colour.
+            let mut image = RgbaImage::new(pixel_rect.width() as u32, pixel_rect.height() as u32);
+            glyph.draw(|x, y, coverage| {
+                let alpha = (coverage * 255.0).round() as u8;
+                image.put_pixel(x, y, image::Rgba([255, 255, 255, alpha]));
+            });
+            let texture = SoftwareTexture::new(image, TextureEncoding::StraightAlpha);
+
+            let uv_rect = rusttype::Rect {
+                min: rusttype::point(0.0, 0.0),
+                max: rusttype::point(1.0, 1.0),
+            };
+            let quad = glyph_quad(
+                pixel_rect,
+                uv_rect,
+                offset,
+                word.size.1 as f32,
+                (*colour).into(),
+            );
+            generate_geometry(std::iter::once(quad), |verts, inds| {
+                self.draw_triangles(transform, verts, inds, &texture);
+                verts.clear();
+                inds.clear();
+            });
+        }
+    }
+
+    /// Draws indexed triangles, as a single draw call of the batch would.
+    fn draw_triangles(
+        &mut self,
+        transform: &Matrix4<f32>,
+        vertices: &[Vertex],
+        indices: &[u16],
+        texture: &SoftwareTexture,
+    ) {
+        for triangle in indices.chunks_exact(3) {
+            let vertex = |i: usize| &vertices[triangle[i] as usize];
+            self.draw_triangle(transform, [vertex(0), vertex(1), vertex(2)], texture);
+        }
+    }
+
+    /// Converts a vertex's position into pixels from the top left of the image, snapped to the subpixel grid.
+    fn to_pixels(&self, transform: &Matrix4<f32>, vertex: &Vertex) -> [i64; 2] {
+        let [x, y, z] = vertex.position;
+        let clip = transform * Vector4::new(x, y, z, 1.0);
+        let pixel_x = (clip.x / clip.w + 1.0) * 0.5 * self.width as f32;
+        let pixel_y = (1.0 - clip.y / clip.w) * 0.5 * self.height as f32;
+        [
+            (pixel_x * SUBPIXEL_PRECISION as f32).round() as i64,
+            (pixel_y * SUBPIXEL_PRECISION as f32).round() as i64,
+        ]
+    }
+
+    /// Draws a textured triangle, shading and blending each pixel whose centre is inside it as the batch's pipeline
+    /// for the texture's encoding would.
+    fn draw_triangle(
+        &mut self,
+        transform: &Matrix4<f32>,
+        mut vertices: [&Vertex; 3],
+        texture: &SoftwareTexture,
+    ) {
+        let mut positions = [
+            self.to_pixels(transform, vertices[0]),
+            self.to_pixels(transform, vertices[1]),
+            self.to_pixels(transform, vertices[2]),
+        ];
+        let mut area = edge_function(positions[0], positions[1], positions[2]);
+        if area == 0 {
+            return;
+        }
+        // Triangles aren't culled, so make every triangle wind the same way.
+        if area < 0 {
+            positions.swap(1, 2);
+            vertices.swap(1, 2);
+            area = -area;
+        }
+        // Edge `i` is opposite vertex `i`, so its edge function is the weight of that vertex.
+        let edges = [
+            (positions[1], positions[2]),
+            (positions[2], positions[0]),
+            (positions[0], positions[1]),
+        ];
+
+        // Pixels exactly on an edge belong to only one of the two triangles sharing it, which traverse it in opposite directions.
+        let owns_edge = |(a, b): ([i64; 2], [i64; 2])| {
+            let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+            dy > 0 || (dy == 0 && dx > 0)
+        };
+        let interpolate = |weights: [f32; 3], attribute: &dyn Fn(&Vertex) -> [f32; 4]| {
+            let mut result = [0.0; 4];
+            for (vertex, weight) in vertices.iter().zip(weights.iter()) {
+                let value = attribute(vertex);
+                for channel in 0..4 {
+                    result[channel] += value[channel] * weight;
+                }
+            }
+            result
+        };
+        let tex_coords = |vertex: &Vertex| [vertex.tex_coords[0], vertex.tex_coords[1], 0.0, 0.0];
+
+        // The change in texture coordinates from one pixel to the next, as found by `fwidth` in a shader.
+        let weight_steps = |step: &dyn Fn(([i64; 2], [i64; 2])) -> i64| {
+            let mut weights = [0.0; 3];
+            for (weight, edge) in weights.iter_mut().zip(edges.iter()) {
+                *weight = (step(*edge) * SUBPIXEL_PRECISION) as f32 / area as f32;
+            }
+            weights
+        };
+        let uv_step_x = interpolate(weight_steps(&|(a, b)| a[1] - b[1]), &tex_coords);
+        let uv_step_y = interpolate(weight_steps(&|(a, b)| b[0] - a[0]), &tex_coords);
+
+        let bound = |values: [i64; 3], limit: u32| {
+            let min = values.iter().min().unwrap().div_euclid(SUBPIXEL_PRECISION);
+            let max = values.iter().max().unwrap().div_euclid(SUBPIXEL_PRECISION) + 1;
+            (min.clamp(0, limit as i64), max.clamp(0, limit as i64))
+        };
+        let (left, right) = bound(
+            [positions[0][0], positions[1][0], positions[2][0]],
+            self.width,
+        );
+        let (top, bottom) = bound(
+            [positions[0][1], positions[1][1], positions[2][1]],
+            self.height,
+        );
+
+        for y in top..bottom {
+            for x in left..right {
+                let centre = [
+                    x * SUBPIXEL_PRECISION + SUBPIXEL_PRECISION / 2,
+                    y * SUBPIXEL_PRECISION + SUBPIXEL_PRECISION / 2,
+                ];
+                let mut weights = [0.0; 3];
+                let mut inside = true;
+                for (weight, edge) in weights.iter_mut().zip(edges.iter()) {
+                    let value = edge_function(edge.0, edge.1, centre);
+                    inside &= value > 0 || (value == 0 && owns_edge(*edge));
+                    *weight = value as f32 / area as f32;
+                }
+                if !inside {
+                    continue;
+                }
+
+                let colour = interpolate(weights, &|vertex| vertex.color);
+                let uv = interpolate(weights, &tex_coords);
+                let uv = [uv[0], uv[1]];
+                let texel = texture.sample(uv);
+                let (source, source_factor) = match texture.encoding {
+                    TextureEncoding::StraightAlpha => {
+                        let source = multiply(colour, texel);
+                        (source, source[3])
+                    }
+                    TextureEncoding::PremultipliedAlpha => {
+                        let [r, g, b, a] = colour;
+                        (multiply([r * a, g * a, b * a, a], texel), 1.0)
+                    }
+                    TextureEncoding::DistanceField => {
+                        let distance = texel[3];
+                        let step = |uv_step: [f32; 4]| {
+                            let neighbour =
+                                texture.sample([uv[0] + uv_step[0], uv[1] + uv_step[1]]);
+                            (neighbour[3] - distance).abs()
+                        };
+                        let width = (step(uv_step_x) + step(uv_step_y)).max(0.0001);
+                        let alpha = ((distance - 0.5) / width + 0.5).clamp(0.0, 1.0);
+                        let alpha = colour[3] * alpha;
+                        ([colour[0], colour[1], colour[2], alpha], alpha)
+                    }
+                };
+
+                // The colour and alpha are blended in the same way.
+                let pixel = &mut self.pixels[(y as u32 * self.width + x as u32) as usize];
+                for channel in 0..4 {
+                    pixel[channel] = (source[channel] * source_factor
+                        + pixel[channel] * (1.0 - source[3]))
+                        .clamp(0.0, 1.0);
+                }
+            }
+        }
+    }
+}
+
+fn multiply(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    [a[0] * b[0], a[1] * b[1], a[2] * b[2], a[3] * b[3]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::{CameraData, NinePatch, TextureRegion};
+    use crate::ui::{Button, ButtonStyle, UiElement};
+    use qs_common::assets::OwnedAsset;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use stretch::geometry::{Rect, Size};
+    use stretch::style::{Dimension, Style};
+    use texture_atlas::{
+        NinePatchMargins, TextureAtlas, TextureAtlasPage, TextureRegionInformation,
+    };
+
+    /// Compares the image against the golden image with the given name in the `golden` folder.
+    /// Run the tests with `UPDATE_GOLDEN_IMAGES=1` to replace the golden images with what is currently rendered.
+    fn assert_matches_golden_image(image: &RgbaImage, name: &str) {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("golden")
+            .join(format!("{}.png", name));
+        if std::env::var_os("UPDATE_GOLDEN_IMAGES").is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            image.save(&path).unwrap();
+            return;
+        }
+
+        let golden = image::open(&path)
+            .unwrap_or_else(|error| panic!("could not read golden image {:?}: {}", path, error));
+        let golden = golden.as_rgba8().expect("golden image is not RGBA");
+        // Allow for small differences in rounding between platforms.
+        let matches = golden.dimensions() == image.dimensions()
+            && golden.pixels().zip(image.pixels()).all(|(a, b)| {
+                a.0.iter()
+                    .zip(b.0.iter())
+                    .all(|(a, b)| (*a as i32 - *b as i32).abs() <= 2)
+            });
+        if !matches {
+            let actual = std::env::temp_dir().join(format!("{}.actual.png", name));
+            image.save(&actual).unwrap();
+            panic!(
+                "rendered image does not match golden image {:?}, it was written to {:?}",
+                path, actual
+            );
+        }
+    }
+
+    /// A camera showing the UI area `width` by `height` pixels, with its top left corner at the origin.
+    fn ui_camera(width: u32, height: u32) -> Camera {
+        Camera::new(CameraData::Orthographic {
+            eye: cgmath::Point2::new(width as f32 * -0.5, height as f32 * 0.5),
+            view_height: height as f32,
+            aspect_ratio: width as f32 / height as f32,
+        })
+    }
+
+    #[test]
+    fn widgets_match_golden_images() {
+        // Two 8x8 nine-patch images side by side, with 2 pixel margins and different colours for the corners, edges and centre.
+        let page = RgbaImage::from_fn(16, 8, |x, y| {
+            let edge = |value: u32| value % 8 < 2 || value % 8 >= 6;
+            let hovered = x >= 8;
+            match (edge(x), edge(y)) {
+                (true, true) => image::Rgba([40, 40, 160, 255]),
+                (true, false) | (false, true) if hovered => image::Rgba([255, 200, 60, 255]),
+                (true, false) | (false, true) => image::Rgba([90, 90, 220, 128]),
+                (false, false) if hovered => image::Rgba([255, 240, 180, 255]),
+                (false, false) => image::Rgba([200, 200, 255, 255]),
+            }
+        });
+        let region_info = |x| TextureRegionInformation {
+            frame: texture_atlas::Rect {
+                x,
+                y: 0,
+                w: 8,
+                h: 8,
+            },
+            rotated: false,
+            trimmed: false,
+            source: texture_atlas::Rect {
+                x: 0,
+                y: 0,
+                w: 8,
+                h: 8,
+            },
+            nine_patch: Some(NinePatchMargins {
+                left: 2,
+                right: 2,
+                top: 2,
+                bottom: 2,
+            }),
+            pivot: None,
+        };
+        let mut frames = HashMap::new();
+        frames.insert("button.png".to_string(), region_info(0));
+        frames.insert("button_hovered.png".to_string(), region_info(8));
+        let texture = OwnedAsset::new(PartitionedTexture {
+            pages: Vec::new(),
+            atlas: TextureAtlas {
+                pages: vec![TextureAtlasPage {
+                    image: "ui_0.png".to_string(),
+                    width: 16,
+                    height: 8,
+                    frames,
+                }],
+                animations: HashMap::new(),
+                texture_options: Default::default(),
+            },
+        });
+        let nine_patch = |x| {
+            let region = TextureRegion::with_info(texture.clone(), 0, region_info(x), (16, 8));
+            NinePatch {
+                texture_region: region,
+                left_margin: 2,
+                right_margin: 2,
+                top_margin: 2,
+                bottom_margin: 2,
+            }
+        };
+
+        let mut button = Button::new(
+            ButtonStyle {
+                released_texture: nine_patch(0),
+                hovered_texture: nine_patch(8),
+                pressed_texture: nine_patch(0),
+                disabled_texture: nine_patch(0),
+            },
+            || {},
+        );
+        button.mouse_enter();
+        let mut stretch = stretch::Stretch::new();
+        let button_node = stretch
+            .new_node(
+                Style {
+                    size: Size {
+                        width: Dimension::Points(40.0),
+                        height: Dimension::Points(10.0),
+                    },
+                    margin: Rect {
+                        start: Dimension::Points(4.0),
+                        top: Dimension::Points(18.0),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                vec![],
+            )
+            .unwrap();
+        let root = stretch
+            .new_node(
+                Style {
+                    size: Size {
+                        width: Dimension::Points(48.0),
+                        height: Dimension::Points(32.0),
+                    },
+                    ..Default::default()
+                },
+                vec![button_node],
+            )
+            .unwrap();
+        stretch.compute_layout(root, Size::undefined()).unwrap();
+        let button_layout = *stretch.layout(button_node).unwrap();
+
+        let renderable = MultiRenderable::Adjacent(vec![
+            nine_patch(0).generate_render_info(Colour::WHITE, 4.0, -16.0, 40.0, 12.0),
+            button.generate_render_info(&button_layout),
+        ]);
+        let mut renderer = SoftwareRenderer::new(48, 32, Colour::BLACK);
+        renderer.add_partitioned_texture(
+            texture.clone(),
+            vec![SoftwareTexture::new(page, TextureEncoding::StraightAlpha)],
+        );
+        renderer.render(renderable, &ui_camera(48, 32));
+        assert_matches_golden_image(&renderer.image(), "widgets");
+    }
+
+    #[test]
+    fn text_matches_golden_image() {
+        let font_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("assets")
+            .join("NotoSans-Regular.ttf");
+        let font = rusttype::Font::try_from_vec(std::fs::read(font_path).unwrap()).unwrap();
+        let scale = rusttype::Scale::uniform(20.0);
+        let v_metrics = font.v_metrics(scale);
+
+        // Lay out the glyphs in the same way as the typesetter.
+        let mut caret_x = 0.0;
+        let mut glyphs = Vec::new();
+        for (character_index, c) in "Hi".chars().enumerate() {
+            let glyph = font
+                .glyph(c)
+                .scaled(scale)
+                .positioned(rusttype::point(caret_x, v_metrics.descent));
+            caret_x += glyph.unpositioned().h_metrics().advance_width;
+            glyphs.push(RenderableGlyph {
+                font: 0,
+                colour: Colour::rgb(1.0, 0.5, 0.0),
+                glyph: GlyphImage::Vector(glyph),
+                character_index,
+            });
+        }
+        let line_height = v_metrics.ascent - v_metrics.descent + v_metrics.line_gap;
+        let word = RenderableWord::new(glyphs, (caret_x as u32, line_height as u32));
+
+        let mut renderer = SoftwareRenderer::new(32, 32, Colour::BLACK);
+        renderer.render(
+            MultiRenderable::Text {
+                word,
+                offset: Point { x: 4.0, y: 2.0 },
+            },
+            &ui_camera(32, 32),
+        );
+        assert_matches_golden_image(&renderer.image(), "text");
+    }
+}
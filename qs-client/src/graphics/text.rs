@@ -1,11 +1,12 @@
 use crate::graphics::Batch;
 use crate::ui::*;
+use cgmath::Matrix4;
 use rusttype::gpu_cache::Cache;
 use std::sync::Arc;
 use stretch::geometry::Point;
 use wgpu::*;
 
-use super::{Renderable, Vertex};
+use super::{generate_geometry, Renderable, TextureEncoding, Vertex};
 
 /// Caches rendered glyphs to speed up the rendering process of text.
 /// Contains a font used to render this text.
@@ -45,8 +46,11 @@ impl TextRenderer {
         let batch = Batch::new(
             Arc::clone(&device),
             Arc::clone(&queue),
-            include_spirv!("text.vert.spv"),
-            include_spirv!("text.frag.spv"),
+            include_spirv!("shader.vert.spv"),
+            vec![(
+                TextureEncoding::StraightAlpha,
+                include_spirv!("shader.text.frag.spv"),
+            )],
             texture_bind_group_layout,
             uniform_bind_group_layout,
             swap_chain_format,
@@ -104,16 +108,19 @@ impl TextRenderer {
     /// Text is a list of words together with an offset at which to draw them.
     pub fn draw_text(
         &mut self,
-        text: Vec<(Point<f32>, RenderableWord)>,
+        text: &[(Point<f32>, RenderableWord)],
         frame: &wgpu::SwapChainTexture,
-        camera: &crate::graphics::Camera,
+        transform: Matrix4<f32>,
         //mut profiler: qs_common::profile::ProfileSegmentGuard<'_>,
     ) {
         {
             //let _guard = profiler.task("queuing glyphs").time();
-            for (_, word) in &text {
+            for (_, word) in text {
                 for RenderableGlyph { font, glyph, .. } in &word.glyphs {
-                    self.cache.queue_glyph(*font, glyph.clone());
+                    // Glyphs from bitmap fonts are drawn by the regular batch instead.
+                    if let GlyphImage::Vector(glyph) = glyph {
+                        self.cache.queue_glyph(*font, glyph.clone());
+                    }
                 }
             }
         }
@@ -169,46 +176,21 @@ impl TextRenderer {
                         ..
                     } in &word.glyphs
                     {
+                        let glyph = match glyph {
+                            GlyphImage::Vector(glyph) => glyph,
+                            GlyphImage::Bitmap { .. } => continue,
+                        };
                         if let Some((uv_rect, pixel_rect)) = self
                             .cache
                             .rect_for(*font, glyph)
                             .expect("Could not load cache entry for glyph")
                         {
-                            // TODO this includes the height of descenders of glyphs, which is not intended!
-                            // This displays text slightly too low!
-                            let line_height = word.size.1 as f32;
-                            let (x1, y1) = (
-                                pixel_rect.min.x as f32 + offset.x,
-                                -pixel_rect.min.y as f32 - line_height - offset.y,
-                            );
-                            let (x2, y2) = (
-                                pixel_rect.max.x as f32 + offset.x,
-                                -pixel_rect.max.y as f32 - line_height - offset.y,
-                            );
-                            let (u1, v1) = (uv_rect.min.x, uv_rect.min.y);
-                            let (u2, v2) = (uv_rect.max.x, uv_rect.max.y);
-                            let color = (*colour).into();
-                            items.push(Renderable::Quadrilateral(
-                                Vertex {
-                                    position: [x1, y1, 0.0],
-                                    color,
-                                    tex_coords: [u1, v1],
-                                },
-                                Vertex {
-                                    position: [x2, y1, 0.0],
-                                    color,
-                                    tex_coords: [u2, v1],
-                                },
-                                Vertex {
-                                    position: [x2, y2, 0.0],
-                                    color,
-                                    tex_coords: [u2, v2],
-                                },
-                                Vertex {
-                                    position: [x1, y2, 0.0],
-                                    color,
-                                    tex_coords: [u1, v2],
-                                },
+                            items.push(glyph_quad(
+                                pixel_rect,
+                                uv_rect,
+                                *offset,
+                                word.size.1 as f32,
+                                (*colour).into(),
                             ));
                         }
                     }
@@ -220,8 +202,58 @@ impl TextRenderer {
 
         {
             //let _guard = profiler.task("rendering text").time();
-            self.batch
-                .render(frame, &self.font_texture, camera, items.into_iter());
+            let batch = &mut self.batch;
+            let font_texture = &self.font_texture;
+            generate_geometry(items.into_iter(), |verts, inds| {
+                batch.draw(frame, font_texture, transform, verts, inds);
+                verts.clear();
+                inds.clear();
+            });
         }
     }
 }
+
+/// Creates the quadrilateral for a glyph of a word drawn at the given offset, where `pixel_rect` is the glyph's
+/// bounding box relative to the word's origin and `uv_rect` is where its image is in the texture.
+pub fn glyph_quad(
+    pixel_rect: rusttype::Rect<i32>,
+    uv_rect: rusttype::Rect<f32>,
+    offset: Point<f32>,
+    line_height: f32,
+    color: [f32; 4],
+) -> Renderable {
+    // TODO this includes the height of descenders of glyphs, which is not intended!
+    // This displays text slightly too low!
+    let (x1, y1) = (
+        pixel_rect.min.x as f32 + offset.x,
+        -pixel_rect.min.y as f32 - line_height - offset.y,
+    );
+    let (x2, y2) = (
+        pixel_rect.max.x as f32 + offset.x,
+        -pixel_rect.max.y as f32 - line_height - offset.y,
+    );
+    let (u1, v1) = (uv_rect.min.x, uv_rect.min.y);
+    let (u2, v2) = (uv_rect.max.x, uv_rect.max.y);
+    Renderable::Quadrilateral(
+        Vertex {
+            position: [x1, y1, 0.0],
+            color,
+            tex_coords: [u1, v1],
+        },
+        Vertex {
+            position: [x2, y1, 0.0],
+            color,
+            tex_coords: [u2, v1],
+        },
+        Vertex {
+            position: [x2, y2, 0.0],
+            color,
+            tex_coords: [u2, v2],
+        },
+        Vertex {
+            position: [x1, y2, 0.0],
+            color,
+            tex_coords: [u1, v2],
+        },
+    )
+}
@@ -1,7 +1,13 @@
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use qs_common::assets::Asset;
-use texture_atlas::{TextureAtlas, TextureRegionInformation};
+use qs_common::assets::{Asset, OwnedAsset};
+use texture_atlas::{
+    generate_mipmaps, pixel_snapped_quad, premultiply_alpha, suggest_name, Animation, Corners,
+    DynamicTextureAtlas, Rect, TextureAtlas, TextureAtlasPage, TextureOptions,
+    TextureRegionInformation,
+};
 
 use crate::ui::Colour;
 
@@ -14,6 +20,19 @@ pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
+    /// What the pixels of the texture mean, which changes how the texture must be drawn.
+    pub encoding: TextureEncoding,
+}
+
+/// What the pixels of a texture mean. A batch draws each encoding with a different pipeline.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TextureEncoding {
+    /// Ordinary colours with straight alpha.
+    StraightAlpha,
+    /// Colours that have been multiplied by their alpha.
+    PremultipliedAlpha,
+    /// The alpha channel stores a signed distance field, as in a distance field font, and the other channels are unused.
+    DistanceField,
 }
 
 // https://sotrh.github.io/learn-wgpu/beginner/tutorial5-textures/#cleaning-things-up
@@ -53,6 +72,7 @@ impl Texture {
             texture,
             view,
             sampler: device.create_sampler(desc),
+            encoding: TextureEncoding::StraightAlpha,
         }
     }
 
@@ -61,9 +81,10 @@ impl Texture {
         queue: &wgpu::Queue,
         bytes: &[u8],
         label: &str,
+        options: TextureOptions,
     ) -> Result<Self, image::ImageError> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        Self::from_image(device, queue, &img, Some(label), options)
     }
 
     pub fn from_image(
@@ -71,10 +92,19 @@ impl Texture {
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
+        options: TextureOptions,
     ) -> Result<Self, image::ImageError> {
         use image::GenericImageView;
-        let rgba = img.to_rgba();
+        let mut rgba = img.to_rgba();
         let dimensions = img.dimensions();
+        if options.premultiply_alpha {
+            premultiply_alpha(&mut rgba);
+        }
+        let mip_levels = if options.mipmaps {
+            generate_mipmaps(dimensions.0, dimensions.1, &rgba)
+        } else {
+            Vec::new()
+        };
 
         let size = wgpu::Extent3d {
             width: dimensions.0,
@@ -84,36 +114,52 @@ impl Texture {
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count: 1 + mip_levels.len() as u32,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Rgba8UnormSrgb,
             usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
         });
 
-        queue.write_texture(
-            wgpu::TextureCopyView {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            &rgba,
-            wgpu::TextureDataLayout {
-                offset: 0,
-                bytes_per_row: 4 * dimensions.0,
-                rows_per_image: dimensions.1,
-            },
-            size,
-        );
+        let write_level = |mip_level: u32, width: u32, height: u32, pixels: &[u8]| {
+            queue.write_texture(
+                wgpu::TextureCopyView {
+                    texture: &texture,
+                    mip_level,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                pixels,
+                wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: 4 * width,
+                    rows_per_image: height,
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth: 1,
+                },
+            );
+        };
+        write_level(0, dimensions.0, dimensions.1, &rgba);
+        for (index, level) in mip_levels.iter().enumerate() {
+            write_level(index as u32 + 1, level.width, level.height, &level.pixels);
+        }
 
+        // Without mipmaps, filtering when the texture is drawn smaller only blurs it without stopping it shimmering.
+        let min_filter = if options.mipmaps {
+            wgpu::FilterMode::Linear
+        } else {
+            wgpu::FilterMode::Nearest
+        };
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+            min_filter,
+            mipmap_filter: min_filter,
             ..Default::default()
         });
 
@@ -122,27 +168,204 @@ impl Texture {
             texture,
             view,
             sampler,
+            encoding: if options.premultiply_alpha {
+                TextureEncoding::PremultipliedAlpha
+            } else {
+                TextureEncoding::StraightAlpha
+            },
         })
     }
 }
 
 /// Represents a texture that has been split into several regions.
 /// The regions are addressable using the texture atlas provided.
+/// The texture may be split into several pages, each of which is a separate texture.
 pub struct PartitionedTexture {
-    /// The texture from which to retrieve texture regions.
-    pub base_texture: Texture,
+    /// The textures from which to retrieve texture regions, one for each page of the atlas.
+    pub pages: Vec<Texture>,
     /// The atlas that contains useful information about how texture regions are contained within this texture.
     pub atlas: TextureAtlas,
 }
 
+/// A partitioned texture that images can be added to and removed from while the game is running,
+/// for example to store sprites downloaded from the server.
+/// Regions of this texture can be rendered in the same batch as each other.
+pub struct DynamicPartitionedTexture {
+    queue: Arc<wgpu::Queue>,
+    texture: OwnedAsset<PartitionedTexture>,
+    /// Keeps track of which parts of the texture are in use.
+    /// The atlas of the partitioned texture itself is kept in sync with this.
+    packer: DynamicTextureAtlas,
+}
+
+impl DynamicPartitionedTexture {
+    /// How many pixels to leave between images, so that they don't bleed into each other when sampled.
+    const PADDING: u32 = 2;
+
+    /// Creates an empty texture of the given size.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: Arc<wgpu::Queue>,
+        dimensions: (u32, u32),
+        label: Option<&str>,
+    ) -> Result<Self, image::ImageError> {
+        let packer = DynamicTextureAtlas::new(dimensions.0, dimensions.1, Self::PADDING);
+        let img = image::RgbaImage::new(dimensions.0, dimensions.1);
+        Self::from_packed_image(device, queue, img, packer, label)
+    }
+
+    /// Creates a texture of the given size, where the top-left corner contains a page packed by the build script,
+    /// such as a page of the UI atlas. This means that images added at runtime can be rendered in the same batch as the prebuilt regions.
+    pub fn with_prebuilt(
+        device: &wgpu::Device,
+        queue: Arc<wgpu::Queue>,
+        dimensions: (u32, u32),
+        prebuilt_image: &image::DynamicImage,
+        prebuilt_page: TextureAtlasPage,
+        label: Option<&str>,
+    ) -> Result<Self, image::ImageError> {
+        let packer = DynamicTextureAtlas::from_page(
+            prebuilt_page,
+            dimensions.0,
+            dimensions.1,
+            Self::PADDING,
+        );
+        let mut img = image::RgbaImage::new(dimensions.0, dimensions.1);
+        image::imageops::replace(&mut img, prebuilt_image, 0, 0);
+        Self::from_packed_image(device, queue, img, packer, label)
+    }
+
+    fn from_packed_image(
+        device: &wgpu::Device,
+        queue: Arc<wgpu::Queue>,
+        img: image::RgbaImage,
+        packer: DynamicTextureAtlas,
+        label: Option<&str>,
+    ) -> Result<Self, image::ImageError> {
+        // Images are written into the texture as they are inserted, which only updates the full size image,
+        // so the texture can't have mipmaps. Inserted images are uploaded as they are, with straight alpha.
+        let base_texture = Texture::from_image(
+            device,
+            &queue,
+            &image::DynamicImage::ImageRgba8(img),
+            label,
+            TextureOptions::default(),
+        )?;
+        Ok(Self {
+            queue,
+            texture: OwnedAsset::new(PartitionedTexture {
+                pages: vec![base_texture],
+                atlas: TextureAtlas {
+                    pages: vec![packer.to_page(String::new())],
+                    animations: HashMap::new(),
+                    texture_options: TextureOptions::default(),
+                },
+            }),
+            packer,
+        })
+    }
+
+    /// The partitioned texture that regions are packed into.
+    /// This asset is always loaded, and only has a single page.
+    pub fn texture(&self) -> &Asset<PartitionedTexture> {
+        &self.texture
+    }
+
+    /// Packs the image into the texture under the given name, and uploads it to the graphics card.
+    /// If there was already an image with this name, it is replaced.
+    /// Returns `None` if there is not enough space left in the texture.
+    pub async fn insert(&mut self, name: String, img: &image::RgbaImage) -> Option<TextureRegion> {
+        self.remove(&name).await;
+        let info = self
+            .packer
+            .insert(name.clone(), img.width(), img.height())?;
+        let queue = &self.queue;
+        let mut dimensions = (0, 0);
+        self.texture
+            .if_loaded_mut(|tex| {
+                write_sub_rect(queue, &tex.pages[0].texture, info, img);
+                tex.atlas.pages[0].frames.insert(name, info);
+                dimensions = tex.pages[0].dimensions;
+            })
+            .await;
+        Some(TextureRegion::with_info(
+            Asset::clone(&self.texture),
+            0,
+            info,
+            dimensions,
+        ))
+    }
+
+    /// Removes the image with the given name, so that its space can be reused.
+    /// Any texture regions referring to this image should no longer be rendered.
+    /// Returns true if there was an image with this name.
+    pub async fn remove(&mut self, name: &str) -> bool {
+        let info = match self.packer.remove(name) {
+            Some(info) => info,
+            None => return false,
+        };
+        let queue = &self.queue;
+        self.texture
+            .if_loaded_mut(|tex| {
+                // Clear the old image so that it can't bleed into images placed next to this space later.
+                let blank = image::RgbaImage::new(info.frame.w, info.frame.h);
+                write_sub_rect(queue, &tex.pages[0].texture, info, &blank);
+                tex.atlas.pages[0].frames.remove(name);
+            })
+            .await;
+        true
+    }
+}
+
+/// Uploads the image into the given region of the texture.
+fn write_sub_rect(
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    info: TextureRegionInformation,
+    img: &image::RgbaImage,
+) {
+    queue.write_texture(
+        wgpu::TextureCopyView {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d {
+                x: info.frame.x,
+                y: info.frame.y,
+                z: 0,
+            },
+        },
+        img,
+        wgpu::TextureDataLayout {
+            offset: 0,
+            bytes_per_row: 4 * info.frame.w,
+            rows_per_image: info.frame.h,
+        },
+        wgpu::Extent3d {
+            width: info.frame.w,
+            height: info.frame.h,
+            depth: 1,
+        },
+    );
+}
+
 #[derive(Debug, Copy, Clone)]
 struct InternalTextureRegionInformation {
+    /// The index of the page of the partitioned texture that this region is on.
+    page: usize,
     /// Contains most of the info about how to render this region.
     info: TextureRegionInformation,
-    /// The width and height of the original partitioned texture.
+    /// The width and height of the page of the partitioned texture.
     partitioned_texture_size: (u32, u32),
 }
 
+impl InternalTextureRegionInformation {
+    /// Converts a position in the original image, in pixels from its top-left corner, into texture coordinates
+    /// in the partitioned texture.
+    fn uv_at(&self, x: f32, y: f32) -> [f32; 2] {
+        self.info.uv_at(x, y, self.partitioned_texture_size)
+    }
+}
+
 /// A smaller region of a partitioned texture. This is commonly used to refer to smaller images inside a large texture that packs them all together.
 ///
 /// The info field is populated automatically on a background task when the texture has finished loading.
@@ -165,20 +388,208 @@ impl TextureRegion {
         };
         let cloned = region.clone();
         partitioned_texture
-            .on_load(move |tex| match tex.atlas.frames.get(&name) {
-                Some(info) => {
+            .on_load(move |tex| match tex.atlas.find(&name) {
+                Some((page, info)) => {
                     *cloned.info.try_lock().unwrap() = Some(InternalTextureRegionInformation {
+                        page,
                         info: *info,
-                        partitioned_texture_size: tex.base_texture.dimensions,
+                        partitioned_texture_size: tex.pages[page].dimensions,
                     });
                 }
-                None => {
-                    tracing::error!("region {} not found in partitioned texture", name);
+                None => match tex.atlas.suggest_region(&name) {
+                    Some(suggestion) => tracing::error!(
+                        "region {} not found in partitioned texture, did you mean {}?",
+                        name,
+                        suggestion
+                    ),
+                    None => tracing::error!("region {} not found in partitioned texture", name),
+                },
+            })
+            .await;
+        region
+    }
+
+    /// Creates a texture region whose location in the partitioned texture is already known,
+    /// so there is no need to wait for the partitioned texture to load.
+    pub fn with_info(
+        partitioned_texture: Asset<PartitionedTexture>,
+        page: usize,
+        info: TextureRegionInformation,
+        partitioned_texture_size: (u32, u32),
+    ) -> Self {
+        Self {
+            partitioned_texture,
+            info: Arc::new(Mutex::new(Some(InternalTextureRegionInformation {
+                page,
+                info,
+                partitioned_texture_size,
+            }))),
+        }
+    }
+
+    /// The index of the page of the partitioned texture that this region is on,
+    /// or `None` if the partitioned texture hasn't loaded yet.
+    pub fn page(&self) -> Option<usize> {
+        self.info.try_lock().unwrap().map(|info| info.page)
+    }
+
+    fn region_info(&self) -> Option<InternalTextureRegionInformation> {
+        *self.info.try_lock().unwrap()
+    }
+
+    /// The size of the image before it was packed, including any transparent space that was trimmed away,
+    /// or `None` if the partitioned texture hasn't loaded yet.
+    pub fn source_size(&self) -> Option<(u32, u32)> {
+        self.region_info().map(|region| region.info.source_size())
+    }
+
+    /// Converts a position in the original image, in pixels from its top-left corner, into texture coordinates
+    /// in the partitioned texture, or `None` if the partitioned texture hasn't loaded yet.
+    pub fn uv_at(&self, x: f32, y: f32) -> Option<[f32; 2]> {
+        self.region_info().map(|region| region.uv_at(x, y))
+    }
+
+    /// The texture coordinates of the corners of this region, ordered as they were in the original image
+    /// so that rotated regions are drawn the right way up, or `None` if the partitioned texture hasn't loaded yet.
+    pub fn corner_uvs(&self) -> Option<Corners> {
+        self.region_info()
+            .map(|region| region.info.corner_uvs(region.partitioned_texture_size))
+    }
+
+    /// The texture coordinates of the corners of a rectangle in the original image, given in pixels from its top-left corner,
+    /// or `None` if the partitioned texture hasn't loaded yet.
+    pub fn sub_region_uvs(&self, rect: Rect) -> Option<Corners> {
+        self.region_info().map(|region| {
+            region
+                .info
+                .sub_region_uvs(rect, region.partitioned_texture_size)
+        })
+    }
+
+    /// Draws the whole region stretched over the given area, with its edges snapped to the nearest pixel.
+    /// `pixels_per_unit` is the number of screen pixels per unit of position.
+    /// `x` and `y` represent the bottom-left corner of the area. As with `NinePatch`, the top row of the image
+    /// is drawn at `y + height`, and the bottom row at `y`.
+    pub fn generate_render_info(
+        &self,
+        colour: Colour,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        pixels_per_unit: f32,
+    ) -> MultiRenderable {
+        let region = match self.region_info() {
+            Some(region) => region,
+            None => return MultiRenderable::Nothing,
+        };
+        // If the image was trimmed, only draw the part of the area that the trimmed image covers.
+        // Rows of the image count down from its top, but the y axis on the screen points up,
+        // so the space trimmed from the bottom of the image is what lies between `y` and the quad.
+        let (source_w, source_h) = region.info.source_size();
+        let (scale_x, scale_y) = (width / source_w as f32, height / source_h as f32);
+        let trimmed = region.info.trimmed_rect();
+        let [[left, bottom], [right, _], [_, top], _] = pixel_snapped_quad(
+            x + trimmed.x as f32 * scale_x,
+            y + (source_h - trimmed.y - trimmed.h) as f32 * scale_y,
+            trimmed.w as f32 * scale_x,
+            trimmed.h as f32 * scale_y,
+            pixels_per_unit,
+        );
+        MultiRenderable::ImageRegion {
+            texture: self.clone(),
+            renderables: vec![Renderable::textured_quad(
+                [[left, top], [right, top], [right, bottom], [left, bottom]],
+                region.info.corner_uvs(region.partitioned_texture_size),
+                colour.into(),
+            )],
+        }
+    }
+}
+
+/// A sequence of regions of a partitioned texture that are displayed one after another, as defined by an animation in its atlas.
+///
+/// The frames are populated automatically on a background task when the texture has finished loading.
+#[derive(Debug, Clone)]
+pub struct AnimatedTextureRegion {
+    /// The texture that this animation's frames are contained within.
+    pub partitioned_texture: Asset<PartitionedTexture>,
+
+    /// This is a mutex not a rwlock for simplicity since it'll only ever be written to once.
+    frames: Arc<Mutex<Option<AnimationFrames>>>,
+}
+
+#[derive(Debug)]
+struct AnimationFrames {
+    animation: Animation,
+    /// The texture region for each frame of the animation.
+    regions: Vec<TextureRegion>,
+}
+
+impl AnimatedTextureRegion {
+    /// Creates a new animated texture region from the named animation in the given partitioned texture's atlas.
+    pub async fn new(partitioned_texture: Asset<PartitionedTexture>, name: String) -> Self {
+        let region = Self {
+            partitioned_texture: partitioned_texture.clone(),
+            frames: Arc::new(Mutex::new(None)),
+        };
+        let cloned = region.clone();
+        partitioned_texture
+            .on_load(move |tex| {
+                let animation = match tex.atlas.animations.get(&name) {
+                    Some(animation) => animation.clone(),
+                    None => {
+                        let names = tex.atlas.animations.keys().map(String::as_str);
+                        match suggest_name(&name, names) {
+                            Some(suggestion) => tracing::error!(
+                                "animation {} not found in partitioned texture, did you mean {}?",
+                                name,
+                                suggestion
+                            ),
+                            None => {
+                                tracing::error!(
+                                    "animation {} not found in partitioned texture",
+                                    name
+                                )
+                            }
+                        }
+                        return;
+                    }
+                };
+                let mut regions = Vec::new();
+                for frame in &animation.frames {
+                    match tex.atlas.find(&frame.region) {
+                        Some((page, info)) => regions.push(TextureRegion::with_info(
+                            cloned.partitioned_texture.clone(),
+                            page,
+                            *info,
+                            tex.pages[page].dimensions,
+                        )),
+                        None => {
+                            tracing::error!(
+                                "region {} of animation {} not found in partitioned texture",
+                                frame.region,
+                                name
+                            );
+                            return;
+                        }
+                    }
                 }
+                *cloned.frames.try_lock().unwrap() = Some(AnimationFrames { animation, regions });
             })
             .await;
         region
     }
+
+    /// Retrieves the texture region that should be displayed when the given amount of time has passed since the animation started,
+    /// or `None` if the partitioned texture hasn't loaded yet.
+    pub fn frame_at(&self, elapsed: Duration) -> Option<TextureRegion> {
+        let frames = self.frames.try_lock().unwrap();
+        let AnimationFrames { animation, regions } = frames.as_ref()?;
+        animation
+            .frame_index_at(elapsed.as_secs_f64())
+            .map(|i| regions[i].clone())
+    }
 }
 
 /// Splits a texture into nine pieces, a 3x3 grid, where the sizes of the pieces are represented using pixel measurements.
@@ -204,6 +615,34 @@ impl NinePatch {
         }
     }
 
+    /// Creates a nine-patch using the margins stored alongside the texture region in the atlas.
+    /// This waits for the partitioned texture to load, so that the margins are known.
+    /// If the region has no margins, the whole region is stretched.
+    pub async fn from_region(texture_region: TextureRegion) -> Self {
+        texture_region
+            .partitioned_texture
+            .wait_until_loaded_or_failed()
+            .await;
+        let margins = texture_region
+            .info
+            .try_lock()
+            .unwrap()
+            .and_then(|region| region.info.nine_patch);
+        match margins {
+            Some(margins) => Self {
+                texture_region,
+                left_margin: margins.left,
+                right_margin: margins.right,
+                top_margin: margins.top,
+                bottom_margin: margins.bottom,
+            },
+            None => {
+                tracing::warn!("texture region has no nine-patch margins");
+                Self::no_margins(texture_region)
+            }
+        }
+    }
+
     /// `x` and `y` represent the bottom-left corner of the shape.
     pub fn generate_render_info(
         &self,
@@ -213,32 +652,35 @@ impl NinePatch {
         width: f32,
         height: f32,
     ) -> MultiRenderable {
-        // We need to create 16 vertices for the 3x3 grid.
+        // The image is split into a 3x3 grid of cells, which are stretched to fill the given area.
+        // If the image was trimmed, only the parts of each cell that were kept after trimming are rendered.
 
-        let InternalTextureRegionInformation {
-            info: TextureRegionInformation { frame, .. },
-            partitioned_texture_size,
-        } = match *self.texture_region.info.try_lock().unwrap() {
-            Some(tex) => tex,
+        let region = match *self.texture_region.info.try_lock().unwrap() {
+            Some(region) => region,
             None => return MultiRenderable::Nothing,
         };
 
-        let tex_w = partitioned_texture_size.0 as f32;
-        let tex_h = partitioned_texture_size.1 as f32;
+        let (source_w, source_h) = region.info.source_size();
+        let (source_w, source_h) = (source_w as f32, source_h as f32);
+        let trimmed = region.info.trimmed_rect();
+        let (trim_left, trim_top) = (trimmed.x as f32, trimmed.y as f32);
+        let (trim_right, trim_bottom) = (trim_left + trimmed.w as f32, trim_top + trimmed.h as f32);
 
-        // Therefore, we have four x-positions and four y-positions for coordinates,
-        // and four u-positions and v-positions for texture coordinates.
-        let u_positions = [
-            frame.x as f32 / tex_w,
-            (frame.x as f32 + self.left_margin as f32) / tex_w,
-            (frame.x as f32 + frame.w as f32 - self.right_margin as f32) / tex_w,
-            (frame.x as f32 + frame.w as f32) / tex_w,
+        // Therefore, we have four x-positions and four y-positions for the grid lines in the original image,
+        // and the four x-positions and y-positions on the screen that they are stretched to.
+        // Rows of the image count down from its top, but the y axis on the screen points up,
+        // so the top row of the image is stretched to the top of the shape.
+        let image_x_positions = [
+            0.0,
+            self.left_margin as f32,
+            source_w - self.right_margin as f32,
+            source_w,
         ];
-        let v_positions = [
-            frame.y as f32 / tex_h,
-            (frame.y as f32 + self.bottom_margin as f32) / tex_h,
-            (frame.y as f32 + frame.h as f32 - self.top_margin as f32) / tex_h,
-            (frame.y as f32 + frame.h as f32) / tex_h,
+        let image_y_positions = [
+            0.0,
+            self.top_margin as f32,
+            source_h - self.bottom_margin as f32,
+            source_h,
         ];
 
         let x_positions = [
@@ -248,12 +690,20 @@ impl NinePatch {
             x + width,
         ];
         let y_positions = [
-            y,
-            y + self.bottom_margin as f32,
-            y + height - self.top_margin as f32,
             y + height,
+            y + height - self.top_margin as f32,
+            y + self.bottom_margin as f32,
+            y,
         ];
 
+        // Converts a position in the original image inside the given cell to a position on the screen.
+        let to_screen =
+            |image_positions: [f32; 4], positions: [f32; 4], cell: usize, value: f32| {
+                let t = (value - image_positions[cell])
+                    / (image_positions[cell + 1] - image_positions[cell]);
+                positions[cell] + t * (positions[cell + 1] - positions[cell])
+            };
+
         let color = colour.into();
 
         MultiRenderable::ImageRegion {
@@ -271,31 +721,196 @@ impl NinePatch {
             ]
             .iter()
             .copied()
-            .map(|(i, j)| {
-                Renderable::Quadrilateral(
-                    Vertex {
-                        position: [x_positions[i], y_positions[j], 0.0],
-                        color,
-                        tex_coords: [u_positions[i], v_positions[j]],
-                    },
-                    Vertex {
-                        position: [x_positions[i + 1], y_positions[j], 0.0],
-                        color,
-                        tex_coords: [u_positions[i + 1], v_positions[j]],
-                    },
-                    Vertex {
-                        position: [x_positions[i + 1], y_positions[j + 1], 0.0],
-                        color,
-                        tex_coords: [u_positions[i + 1], v_positions[j + 1]],
-                    },
-                    Vertex {
-                        position: [x_positions[i], y_positions[j + 1], 0.0],
-                        color,
-                        tex_coords: [u_positions[i], v_positions[j + 1]],
-                    },
-                )
+            .filter_map(|(i, j)| {
+                let left = image_x_positions[i].max(trim_left);
+                let right = image_x_positions[i + 1].min(trim_right);
+                let top = image_y_positions[j].max(trim_top);
+                let bottom = image_y_positions[j + 1].min(trim_bottom);
+                if left >= right || top >= bottom {
+                    // This cell was entirely trimmed away, or has no size.
+                    return None;
+                }
+
+                let vertex = |image_x, image_y| Vertex {
+                    position: [
+                        to_screen(image_x_positions, x_positions, i, image_x),
+                        to_screen(image_y_positions, y_positions, j, image_y),
+                        0.0,
+                    ],
+                    color,
+                    tex_coords: region.uv_at(image_x, image_y),
+                };
+                Some(Renderable::Quadrilateral(
+                    vertex(left, top),
+                    vertex(right, top),
+                    vertex(right, bottom),
+                    vertex(left, bottom),
+                ))
             })
             .collect(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use texture_atlas::NinePatchMargins;
+
+    #[test]
+    fn rotated_trimmed_regions_map_to_frame() {
+        // A 10x8 image, trimmed to the 6x4 area at (2, 1), then rotated into a 4x6 frame.
+        let region = InternalTextureRegionInformation {
+            page: 0,
+            info: TextureRegionInformation {
+                frame: Rect {
+                    x: 20,
+                    y: 30,
+                    w: 4,
+                    h: 6,
+                },
+                rotated: true,
+                trimmed: true,
+                source: Rect {
+                    x: 2,
+                    y: 1,
+                    w: 10,
+                    h: 8,
+                },
+                nine_patch: None,
+                pivot: None,
+            },
+            partitioned_texture_size: (100, 100),
+        };
+
+        assert_eq!(region.info.source_size(), (10, 8));
+        assert_eq!(
+            region.info.trimmed_rect(),
+            Rect {
+                x: 2,
+                y: 1,
+                w: 6,
+                h: 4
+            }
+        );
+        // The top-left of the image ends up in the top-right of the frame, and the bottom-right in the bottom-left.
+        assert_eq!(region.uv_at(2.0, 1.0), [0.24, 0.30]);
+        assert_eq!(region.uv_at(8.0, 5.0), [0.20, 0.36]);
+    }
+
+    #[tokio::test]
+    async fn nine_patch_top_margin_is_drawn_at_the_top() {
+        let rect = Rect {
+            x: 0,
+            y: 0,
+            w: 8,
+            h: 8,
+        };
+        let info = TextureRegionInformation {
+            frame: rect,
+            rotated: false,
+            trimmed: false,
+            source: rect,
+            nine_patch: Some(NinePatchMargins {
+                left: 2,
+                right: 2,
+                top: 1,
+                bottom: 3,
+            }),
+            pivot: None,
+        };
+        let texture = OwnedAsset::new(PartitionedTexture {
+            pages: Vec::new(),
+            atlas: TextureAtlas {
+                pages: Vec::new(),
+                animations: HashMap::new(),
+                texture_options: Default::default(),
+            },
+        });
+        let region = TextureRegion::with_info((*texture).clone(), 0, info, (8, 8));
+        let nine_patch = NinePatch::from_region(region).await;
+        let renderables = match nine_patch.generate_render_info(Colour::WHITE, 0.0, 0.0, 20.0, 20.0)
+        {
+            MultiRenderable::ImageRegion { renderables, .. } => renderables,
+            _ => panic!("nine-patch was not rendered"),
+        };
+
+        // Find which row of the image is drawn at each height on the screen.
+        let mut rows = renderables
+            .iter()
+            .flat_map(|renderable| match renderable {
+                Renderable::Quadrilateral(a, b, c, d) => vec![*a, *b, *c, *d],
+                _ => panic!("nine-patch cells should be quadrilaterals"),
+            })
+            .map(|vertex| {
+                (
+                    vertex.position[1] as i32,
+                    (vertex.tex_coords[1] * 8.0) as i32,
+                )
+            })
+            .collect::<Vec<_>>();
+        rows.sort_unstable();
+        rows.dedup();
+        // The top row of the image is at the top of the shape, and the one-pixel top margin stays one pixel high.
+        assert_eq!(rows, vec![(0, 8), (3, 5), (19, 1), (20, 0)]);
+    }
+
+    #[test]
+    fn region_top_row_is_drawn_at_the_top() {
+        // An 8x8 image, trimmed to the 4x4 area at (2, 1), so three rows were trimmed from its bottom.
+        let info = TextureRegionInformation {
+            frame: Rect {
+                x: 0,
+                y: 0,
+                w: 4,
+                h: 4,
+            },
+            rotated: false,
+            trimmed: true,
+            source: Rect {
+                x: 2,
+                y: 1,
+                w: 8,
+                h: 8,
+            },
+            nine_patch: None,
+            pivot: None,
+        };
+        let texture = OwnedAsset::new(PartitionedTexture {
+            pages: Vec::new(),
+            atlas: TextureAtlas {
+                pages: Vec::new(),
+                animations: HashMap::new(),
+                texture_options: Default::default(),
+            },
+        });
+        let region = TextureRegion::with_info((*texture).clone(), 0, info, (8, 8));
+        let renderables =
+            match region.generate_render_info(Colour::WHITE, 0.0, 0.0, 16.0, 16.0, 1.0) {
+                MultiRenderable::ImageRegion { renderables, .. } => renderables,
+                _ => panic!("region was not rendered"),
+            };
+
+        let mut corners = renderables
+            .iter()
+            .flat_map(|renderable| match renderable {
+                Renderable::Quadrilateral(a, b, c, d) => vec![*a, *b, *c, *d],
+                _ => panic!("regions should be quadrilaterals"),
+            })
+            .map(|vertex| {
+                (
+                    vertex.position[0] as i32,
+                    vertex.position[1] as i32,
+                    (vertex.tex_coords[0] * 8.0) as i32,
+                    (vertex.tex_coords[1] * 8.0) as i32,
+                )
+            })
+            .collect::<Vec<_>>();
+        corners.sort_unstable();
+        // The top row of the frame is at the higher y, and the trimmed rows are left empty below the quad.
+        assert_eq!(
+            corners,
+            vec![(4, 6, 0, 4), (4, 14, 0, 0), (12, 6, 4, 4), (12, 14, 4, 0)]
+        );
+    }
+}
@@ -0,0 +1,3 @@
+pub mod assets;
+pub mod graphics;
+pub mod ui;
@@ -1,6 +1,8 @@
-pub mod assets;
-pub mod graphics;
-pub mod ui;
+use qs_client::graphics;
+
+#[cfg(feature = "count-allocations")]
+#[global_allocator]
+static ALLOCATOR: qs_common::profile::CountingAllocator = qs_common::profile::CountingAllocator;
 
 fn register_tracing_subscriber() {
     let subscriber = tracing_subscriber::FmtSubscriber::builder()
@@ -1,6 +1,7 @@
-use crate::graphics::{MultiRenderable, Renderable};
+use crate::graphics::{BitmapFontTexture, MultiRenderable, Renderable, TextureRegion};
 use futures::future::{AbortHandle, AbortRegistration, Abortable, Aborted};
 use qs_common::assets::Asset;
+use qs_common::profile::SharedProfiler;
 use rusttype::{point, Font, PositionedGlyph, Scale};
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
@@ -24,10 +25,19 @@ pub struct FontFace {
     id: usize,
     /// A (preferably) unique name to distinguish font faces in debug messages.
     name: String,
-    regular: Asset<Font<'static>>,
-    bold: Option<Asset<Font<'static>>>,
-    italic: Option<Asset<Font<'static>>>,
-    bold_italic: Option<Asset<Font<'static>>>,
+    regular: FontAsset,
+    bold: Option<FontAsset>,
+    italic: Option<FontAsset>,
+    bold_italic: Option<FontAsset>,
+}
+
+/// A single variant of a font face, such as its bold variant.
+#[derive(Clone)]
+enum FontAsset {
+    /// A vector font, whose glyphs are rendered into the text renderer's glyph cache.
+    Vector(Asset<Font<'static>>),
+    /// A bitmap font, whose glyphs are drawn from its partitioned texture by the regular batch.
+    Bitmap(Asset<BitmapFontTexture>),
 }
 
 impl FontFace {
@@ -41,10 +51,32 @@ impl FontFace {
         Self {
             id: new_font_face_id(),
             name,
-            regular,
-            bold,
-            italic,
-            bold_italic,
+            regular: FontAsset::Vector(regular),
+            bold: bold.map(FontAsset::Vector),
+            italic: italic.map(FontAsset::Vector),
+            bold_italic: bold_italic.map(FontAsset::Vector),
+        }
+    }
+
+    /// Creates a font face from bitmap fonts. Its glyphs are drawn by the regular batch instead of the text renderer,
+    /// and are only ever scaled by a whole number, so pixel-art fonts stay crisp.
+    ///
+    /// Distance field fonts, such as those baked from `.ttf` files by the asset pipeline, are scaled to exactly
+    /// the size of the text instead, since they stay crisp at any size.
+    pub fn bitmap(
+        name: String,
+        regular: Asset<BitmapFontTexture>,
+        bold: Option<Asset<BitmapFontTexture>>,
+        italic: Option<Asset<BitmapFontTexture>>,
+        bold_italic: Option<Asset<BitmapFontTexture>>,
+    ) -> Self {
+        Self {
+            id: new_font_face_id(),
+            name,
+            regular: FontAsset::Bitmap(regular),
+            bold: bold.map(FontAsset::Bitmap),
+            italic: italic.map(FontAsset::Bitmap),
+            bold_italic: bold_italic.map(FontAsset::Bitmap),
         }
     }
 }
@@ -154,9 +186,15 @@ impl RichText {
             widget,
             typeset_abort_handle: None,
             word_info: HashMap::new(),
+            profiler: None,
         })))
     }
 
+    /// Records the time taken to typeset this text from now on in the `text` lane of the given profiler.
+    pub fn set_profiler(&self, profiler: SharedProfiler) {
+        self.0.write().unwrap().profiler = Some(profiler);
+    }
+
     pub fn set_text(&mut self, font_family: Arc<FontFamily>) -> RichTextContentsBuilder {
         let mut write = self.0.write().unwrap();
         let (abort_handle, abort_registration) = AbortHandle::new_pair();
@@ -211,9 +249,52 @@ impl UiElement for RenderableWord {
     }
 
     fn generate_render_info(&self, layout: &stretch::result::Layout) -> MultiRenderable {
-        MultiRenderable::Text {
+        let text = MultiRenderable::Text {
             word: self.clone(),
             offset: layout.location,
+        };
+
+        // Glyphs from bitmap fonts are drawn by the regular batch, in the same place the text renderer would draw them.
+        let line_height = self.size.1 as f32;
+        let bitmap_glyphs = self
+            .glyphs
+            .iter()
+            .filter_map(|glyph| match &glyph.glyph {
+                GlyphImage::Bitmap {
+                    region: Some(region),
+                    bounding_box,
+                } => {
+                    let (x1, y1) = (
+                        bounding_box.min.x as f32 + layout.location.x,
+                        -bounding_box.min.y as f32 - line_height - layout.location.y,
+                    );
+                    let (x2, y2) = (
+                        bounding_box.max.x as f32 + layout.location.x,
+                        -bounding_box.max.y as f32 - line_height - layout.location.y,
+                    );
+                    Some(MultiRenderable::ImageRegion {
+                        texture: region.clone(),
+                        renderables: region
+                            .corner_uvs()
+                            .map(|tex_coords| {
+                                Renderable::textured_quad(
+                                    [[x1, y1], [x2, y1], [x2, y2], [x1, y2]],
+                                    tex_coords,
+                                    glyph.colour.into(),
+                                )
+                            })
+                            .into_iter()
+                            .collect(),
+                    })
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        if bitmap_glyphs.is_empty() {
+            text
+        } else {
+            MultiRenderable::Adjacent(std::iter::once(text).chain(bitmap_glyphs).collect())
         }
     }
 }
@@ -270,6 +351,9 @@ pub struct RichTextContents {
     /// to cancel the typeset task so we don't accidentally typeset something twice (or worse, the order of execution
     /// of the tasks is swapped).
     typeset_abort_handle: Option<AbortHandle>,
+
+    /// If set, the time taken to typeset this text is recorded in this profiler.
+    profiler: Option<SharedProfiler>,
 }
 
 impl RichTextContents {
@@ -458,6 +542,22 @@ impl RichTextContentsBuilder {
         self
     }
 
+    /// Typesets the text in this builder without writing it to the rich text struct.
+    ///
+    /// # Panics
+    /// If this is an internal builder (e.g. produced by the `h1` function), this will panic.
+    pub async fn typeset(self) -> TypesetText {
+        if self.is_internal {
+            panic!("cannot call `typeset` on internal builders");
+        }
+
+        let mut paragraphs = self.paragraphs;
+        if !self.current_paragraph.is_empty() {
+            paragraphs.push(self.current_paragraph);
+        }
+        typeset_rich_text(paragraphs).await
+    }
+
     /// Writes the output of this builder to the rich text struct. Returns a handle to the task that is typesetting the text.
     /// To wait until typesetting is finished, `.await` on this handle.
     ///
@@ -473,8 +573,12 @@ impl RichTextContentsBuilder {
             paragraphs.push(self.current_paragraph);
         }
         let output = self.output;
+        let profiler = output.0.read().unwrap().profiler.clone();
         tokio::spawn(Abortable::new(
             async move {
+                let _guard = profiler
+                    .as_ref()
+                    .map(|profiler| profiler.time("text", "typeset"));
                 // We clone the paragraph data here so that the background thread can't cause the main thread to halt.
                 let paragraphs_cloned = paragraphs.clone();
                 let typeset_text = typeset_rich_text(paragraphs_cloned).await;
@@ -499,11 +603,38 @@ pub struct TypesetText {
 pub struct RenderableGlyph {
     pub font: usize,
     pub colour: Colour,
-    pub glyph: PositionedGlyph<'static>,
+    pub glyph: GlyphImage,
     /// This is the index of the character in the original text.
     pub character_index: usize,
 }
 
+/// The image drawn for a glyph, which depends on the kind of font the glyph came from.
+#[derive(Debug, Clone)]
+pub enum GlyphImage {
+    /// A glyph of a vector font, which the text renderer rasterises into its glyph cache.
+    Vector(PositionedGlyph<'static>),
+    /// A glyph of a bitmap font, drawn from a region of the font's partitioned texture by the regular batch.
+    Bitmap {
+        /// The region containing the glyph's image, or `None` if the glyph has no image, such as a space.
+        region: Option<TextureRegion>,
+        /// Where the image is drawn, in pixels relative to the word's origin.
+        bounding_box: rusttype::Rect<i32>,
+    },
+}
+
+impl GlyphImage {
+    /// The pixels covered by the glyph relative to the word's origin, or `None` if the glyph has no image.
+    pub fn pixel_bounding_box(&self) -> Option<rusttype::Rect<i32>> {
+        match self {
+            GlyphImage::Vector(glyph) => glyph.pixel_bounding_box(),
+            GlyphImage::Bitmap {
+                region,
+                bounding_box,
+            } => region.as_ref().map(|_| *bounding_box),
+        }
+    }
+}
+
 /// An indivisible unit of text, represented as a list of glyphs positioned relative to the word's origin point.
 #[derive(Debug, Clone)]
 pub struct RenderableWord {
@@ -520,6 +651,18 @@ pub struct RenderableWord {
     cache_generation: u64,
 }
 
+impl RenderableWord {
+    /// Creates a word from its glyphs, positioned relative to the word's origin, and the size of the word in pixels.
+    pub fn new(glyphs: Vec<RenderableGlyph>, size: (u32, u32)) -> Self {
+        Self {
+            glyphs,
+            size,
+            cached_renderables: None,
+            cache_generation: 0,
+        }
+    }
+}
+
 /// An paragraph of text comprised of a number of words.
 pub struct RenderableParagraph(pub Vec<RenderableWord>);
 
@@ -534,7 +677,7 @@ lazy_static::lazy_static! {
     /// Maps font specifiers to the font IDs.
     static ref FONT_ID_MAP: tokio::sync::RwLock<HashMap<FontIdSpecifier, usize>> = tokio::sync::RwLock::new(HashMap::new());
     /// A many-to-one map, mapping font IDs to the actual font asset.
-    static ref FONT_ID_TO_FONT_MAP: tokio::sync::RwLock<HashMap<usize, Asset<Font<'static>>>> = tokio::sync::RwLock::new(HashMap::new());
+    static ref FONT_ID_TO_FONT_MAP: tokio::sync::RwLock<HashMap<usize, FontAsset>> = tokio::sync::RwLock::new(HashMap::new());
 }
 
 static FONT_ID_COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(1);
@@ -571,75 +714,60 @@ async fn get_font_id(font_face: &FontFace, emphasis: FontEmphasis, font_size: Fo
     }))
 }
 
+/// Works out whether the given font has a glyph for the character, waiting for it to load if necessary.
+async fn has_glyph(font: &FontAsset, c: char) -> bool {
+    let mut found = false;
+    match font {
+        FontAsset::Vector(font) => {
+            font.wait_until_loaded().await;
+            font.if_loaded(|font| found = font.glyph(c).id().0 != 0)
+                .await;
+        }
+        FontAsset::Bitmap(font) => {
+            font.wait_until_loaded().await;
+            font.if_loaded(|font| found = font.font.glyphs.contains_key(&c))
+                .await;
+        }
+    }
+    found
+}
+
+/// Finds the most preferred font in the family that has a glyph for the given character, and returns its font ID.
 async fn get_font_for_character(
     font_family: &FontFamily,
     emphasis: FontEmphasis,
     font_size: FontSize,
     c: char,
-) -> Option<(usize, rusttype::Glyph<'static>)> {
+) -> Option<usize> {
     for font_face in &font_family.0 {
-        if emphasis == FontEmphasis::BoldItalic {
-            if let Some(ref font_style) = font_face.bold_italic {
-                font_style.wait_until_loaded().await;
-                if let Some(data) = font_style.data.upgrade() {
-                    if let qs_common::assets::LoadStatus::Loaded(ref font) = &*data.write().await {
-                        let glyph = font.glyph(c);
-                        if glyph.id().0 != 0 {
-                            return Some((
-                                get_font_id(font_face, FontEmphasis::BoldItalic, font_size).await,
-                                glyph,
-                            ));
-                        }
-                    }
-                }
-            }
-        }
-
-        if emphasis == FontEmphasis::Bold || emphasis == FontEmphasis::BoldItalic {
-            if let Some(ref font_style) = font_face.bold {
-                font_style.wait_until_loaded().await;
-                if let Some(data) = font_style.data.upgrade() {
-                    if let qs_common::assets::LoadStatus::Loaded(ref font) = &*data.write().await {
-                        let glyph = font.glyph(c);
-                        if glyph.id().0 != 0 {
-                            return Some((
-                                get_font_id(font_face, FontEmphasis::Bold, font_size).await,
-                                glyph,
-                            ));
-                        }
-                    }
-                }
-            }
-        }
-
-        if emphasis == FontEmphasis::Italic || emphasis == FontEmphasis::BoldItalic {
-            if let Some(ref font_style) = font_face.italic {
-                font_style.wait_until_loaded().await;
-                if let Some(data) = font_style.data.upgrade() {
-                    if let qs_common::assets::LoadStatus::Loaded(ref font) = &*data.write().await {
-                        let glyph = font.glyph(c);
-                        if glyph.id().0 != 0 {
-                            return Some((
-                                get_font_id(font_face, FontEmphasis::Italic, font_size).await,
-                                glyph,
-                            ));
-                        }
-                    }
+        // Bold-italic falls back to bold, then italic, then regular.
+        let variants = [
+            (
+                FontEmphasis::BoldItalic,
+                &font_face.bold_italic,
+                emphasis == FontEmphasis::BoldItalic,
+            ),
+            (
+                FontEmphasis::Bold,
+                &font_face.bold,
+                emphasis == FontEmphasis::Bold || emphasis == FontEmphasis::BoldItalic,
+            ),
+            (
+                FontEmphasis::Italic,
+                &font_face.italic,
+                emphasis == FontEmphasis::Italic || emphasis == FontEmphasis::BoldItalic,
+            ),
+        ];
+        for (variant, font, wanted) in variants.iter() {
+            if let (true, Some(font)) = (wanted, font) {
+                if has_glyph(font, c).await {
+                    return Some(get_font_id(font_face, *variant, font_size).await);
                 }
             }
         }
 
-        font_face.regular.wait_until_loaded().await;
-        if let Some(data) = font_face.regular.data.upgrade() {
-            if let qs_common::assets::LoadStatus::Loaded(ref font) = &*data.write().await {
-                let glyph = font.glyph(c);
-                if glyph.id().0 != 0 {
-                    return Some((
-                        get_font_id(font_face, FontEmphasis::Regular, font_size).await,
-                        glyph,
-                    ));
-                }
-            }
+        if has_glyph(&font_face.regular, c).await {
+            return Some(get_font_id(font_face, FontEmphasis::Regular, font_size).await);
         }
     }
 
@@ -689,88 +817,137 @@ async fn typeset_rich_text_paragraph(
 
         if !segment.glue_to_previous {
             // Add the previous word to the paragraph.
-            output.push(RenderableWord {
-                glyphs: std::mem::take(&mut word),
-                size: (caret_x as u32, line_height as u32),
-                cached_renderables: None,
-                cache_generation: 0,
-            });
+            output.push(RenderableWord::new(
+                std::mem::take(&mut word),
+                (caret_x as u32, line_height as u32),
+            ));
             caret_x = 0.0;
             line_height = 0.0;
         }
 
         for c in segment.text.chars() {
-            let mut font_and_glyph = get_font_for_character(
-                &*segment.style.font_family,
-                segment.style.emphasis,
-                segment.style.size,
-                c,
-            )
-            .await;
-
-            if font_and_glyph.is_none() {
-                // Replace this glyph with a generic 'character not found' glyph.
-                font_and_glyph = get_font_for_character(
+            // If no font has this character, replace it with a generic 'character not found' glyph,
+            // or failing that, a normal question mark. If even that isn't there, we just don't render this character.
+            let mut font_and_character = None;
+            for candidate in [c, '\u{FFFD}', '?'].iter().copied() {
+                if let Some(font) = get_font_for_character(
                     &*segment.style.font_family,
                     segment.style.emphasis,
                     segment.style.size,
-                    '\u{FFFD}',
+                    candidate,
                 )
-                .await;
-
-                if font_and_glyph.is_none() {
-                    // If that glyph wasn't in the font, we'll just try a normal question mark.
-                    font_and_glyph = get_font_for_character(
-                        &*segment.style.font_family,
-                        segment.style.emphasis,
-                        segment.style.size,
-                        '?',
-                    )
-                    .await;
-
-                    if font_and_glyph.is_none() {
-                        // Really at this point there's no alternatives left.
-                        // We'll just not render this character.
-                        character_index += 1;
-                        continue;
-                    }
+                .await
+                {
+                    font_and_character = Some((font, candidate));
+                    break;
                 }
             }
-
-            let (font, base_glyph) =
-                font_and_glyph.expect("no replacement characters found in font");
+            let (font, c) = match font_and_character {
+                Some(font_and_character) => font_and_character,
+                None => {
+                    character_index += 1;
+                    continue;
+                }
+            };
 
             let font_id_to_font_map = FONT_ID_TO_FONT_MAP.read().await;
             let font_asset = font_id_to_font_map
                 .get(&font)
                 .expect("could not retrieve font for font ID");
-            let font_asset_data = font_asset
-                .data
-                .upgrade()
-                .expect("asset manager containing font was dropped");
-
-            let mut descender_height = 0.0;
-            if let qs_common::assets::LoadStatus::Loaded(font_data) = &*font_asset_data.read().await
-            {
-                descender_height = font_data.v_metrics(scale).descent;
-                if let Some((last_font_id, last_glyph_id)) = last_glyph.take() {
-                    if font == last_font_id {
-                        caret_x += font_data.pair_kerning(scale, last_glyph_id, base_glyph.id());
+            let kerning_with_previous = last_glyph
+                .take()
+                .filter(|(last_font_id, _)| *last_font_id == font)
+                .map(|(_, last_char)| last_char);
+            last_glyph = Some((font, c));
+
+            let glyph = match font_asset {
+                FontAsset::Vector(font_asset) => {
+                    let font_asset_data = font_asset
+                        .data
+                        .upgrade()
+                        .expect("asset manager containing font was dropped");
+                    let font_data = font_asset_data.read().await;
+                    let font_data = match &*font_data {
+                        qs_common::assets::LoadStatus::Loaded(font_data) => font_data,
+                        _ => {
+                            character_index += 1;
+                            continue;
+                        }
+                    };
+
+                    let descender_height = font_data.v_metrics(scale).descent;
+                    if let Some(last_char) = kerning_with_previous {
+                        caret_x += font_data.pair_kerning(scale, last_char, c);
                     }
+
+                    let glyph = font_data
+                        .glyph(c)
+                        .scaled(scale)
+                        .positioned(point(caret_x, descender_height));
+
+                    caret_x += glyph.unpositioned().h_metrics().advance_width;
+                    let v_metrics = font_data.v_metrics(scale);
+                    let glyph_line_height =
+                        v_metrics.ascent - v_metrics.descent + v_metrics.line_gap;
+                    if glyph_line_height > line_height {
+                        line_height = glyph_line_height
+                    }
+                    GlyphImage::Vector(glyph)
                 }
-            };
+                FontAsset::Bitmap(font_asset) => {
+                    let font_asset_data = font_asset
+                        .data
+                        .upgrade()
+                        .expect("asset manager containing font was dropped");
+                    let font_data = font_asset_data.read().await;
+                    let bitmap_font = match &*font_data {
+                        qs_common::assets::LoadStatus::Loaded(font_data) => font_data,
+                        _ => {
+                            character_index += 1;
+                            continue;
+                        }
+                    };
+                    let font = &bitmap_font.font;
+                    let bitmap_glyph = font.glyphs[&c];
+
+                    // Distance field fonts stay crisp at any size, but pixel-art fonts look wrong when stretched
+                    // by fractional amounts, so only scale them by whole numbers.
+                    let pixel_scale = scale.y / font.size.max(1) as f32;
+                    let pixel_scale = if font.distance_range.is_some() {
+                        pixel_scale
+                    } else {
+                        pixel_scale.round().max(1.0)
+                    };
+                    if let Some(last_char) = kerning_with_previous {
+                        caret_x += font.kerning(last_char, c) as f32 * pixel_scale;
+                    }
 
-            last_glyph = Some((font, base_glyph.id()));
-            let glyph = base_glyph
-                .scaled(scale)
-                .positioned(point(caret_x, descender_height));
+                    // Put the baseline in the same place as it would be for a vector font, so the two can be mixed on one line.
+                    let descender_height =
+                        -(font.line_height.saturating_sub(font.base) as f32) * pixel_scale;
+                    let left = caret_x + bitmap_glyph.x_offset as f32 * pixel_scale;
+                    let top = descender_height
+                        + (bitmap_glyph.y_offset - font.base as i32) as f32 * pixel_scale;
+                    let bounding_box = rusttype::Rect {
+                        min: point(left.round() as i32, top.round() as i32),
+                        max: point(
+                            (left + bitmap_glyph.frame.w as f32 * pixel_scale).round() as i32,
+                            (top + bitmap_glyph.frame.h as f32 * pixel_scale).round() as i32,
+                        ),
+                    };
+
+                    caret_x += bitmap_glyph.x_advance as f32 * pixel_scale;
+                    let glyph_line_height = font.line_height as f32 * pixel_scale;
+                    if glyph_line_height > line_height {
+                        line_height = glyph_line_height
+                    }
+                    GlyphImage::Bitmap {
+                        region: bitmap_font.glyph_region(c),
+                        bounding_box,
+                    }
+                }
+            };
 
-            caret_x += glyph.unpositioned().h_metrics().advance_width;
-            let v_metrics = glyph.unpositioned().font().v_metrics(scale);
-            let glyph_line_height = v_metrics.ascent - v_metrics.descent + v_metrics.line_gap;
-            if glyph_line_height > line_height {
-                line_height = glyph_line_height
-            }
             word.push(RenderableGlyph {
                 font,
                 colour: segment.style.colour,
@@ -783,12 +960,10 @@ async fn typeset_rich_text_paragraph(
     }
 
     // Add the current word to the line.
-    output.push(RenderableWord {
-        glyphs: std::mem::take(&mut word),
-        size: (caret_x as u32, line_height as u32),
-        cached_renderables: None,
-        cache_generation: 0,
-    });
+    output.push(RenderableWord::new(
+        std::mem::take(&mut word),
+        (caret_x as u32, line_height as u32),
+    ));
 
     RenderableParagraph(output)
 }
@@ -1,7 +1,8 @@
 use qs_common::assets::Asset;
 use stretch::{geometry::Size, result::Layout, style::Dimension};
+use texture_atlas::UvRect;
 
-use crate::graphics::{MultiRenderable, Renderable, Texture, Vertex};
+use crate::graphics::{MultiRenderable, Renderable, Texture};
 
 use super::{Colour, UiElement};
 
@@ -17,42 +18,19 @@ impl UiElement for ImageElement {
     }
 
     fn generate_render_info(&self, layout: &Layout) -> MultiRenderable {
-        let color = self.colour.into();
+        let (x, y) = (layout.location.x, -layout.location.y);
+        let (width, height) = (layout.size.width, layout.size.height);
         MultiRenderable::Image {
             texture: self.texture.clone(),
-            renderables: vec![Renderable::Quadrilateral(
-                Vertex {
-                    position: [layout.location.x, -layout.location.y, 0.0],
-                    color,
-                    tex_coords: [0.0, 0.0],
-                },
-                Vertex {
-                    position: [
-                        layout.location.x + layout.size.width,
-                        -layout.location.y,
-                        0.0,
-                    ],
-                    color,
-                    tex_coords: [1.0, 0.0],
-                },
-                Vertex {
-                    position: [
-                        layout.location.x + layout.size.width,
-                        -layout.location.y - layout.size.height,
-                        0.0,
-                    ],
-                    color,
-                    tex_coords: [1.0, 1.0],
-                },
-                Vertex {
-                    position: [
-                        layout.location.x,
-                        -layout.location.y - layout.size.height,
-                        0.0,
-                    ],
-                    color,
-                    tex_coords: [0.0, 1.0],
-                },
+            renderables: vec![Renderable::textured_quad(
+                [
+                    [x, y],
+                    [x + width, y],
+                    [x + width, y - height],
+                    [x, y - height],
+                ],
+                UvRect::FULL.corners(),
+                self.colour.into(),
             )],
         }
     }
@@ -521,7 +521,7 @@ impl UI {
     /// Lays out this UI according to flexbox rules.
     /// This is called when we want to render this UI but the layout has been invalidated by
     /// changing some content in a child widget or UI element.
-    fn layout(&self, size: geometry::Size<Number>) {
+    pub fn layout(&self, size: geometry::Size<Number>) {
         let styles: WidgetStyle = self.root.generate_styles();
 
         let layouts: Vec<_> = {
@@ -3,6 +3,8 @@ use std::sync::{Arc, Weak};
 use std::{fmt::Debug, hash::Hash};
 use tokio::sync::RwLock;
 
+use crate::profile::SharedProfiler;
+
 /// Represents a globally unique asset ID.
 /// These can be generated by calling `new_asset_id`.
 type AssetID = u64;
@@ -27,6 +29,8 @@ where
     /// Weak references to these `Arc`s are stored in each asset.
     stored_assets: HashMap<AssetID, Arc<RwLock<LoadStatus<T>>>>,
     asset_loader: Arc<L>,
+    /// If set, the time taken to load each asset is recorded in the `assets` lane of this profiler.
+    profiler: Option<SharedProfiler>,
 }
 
 impl<K, T, L> AssetManager<K, T, L>
@@ -40,9 +44,15 @@ where
             assets: HashMap::new(),
             stored_assets: HashMap::new(),
             asset_loader: Arc::new(loader),
+            profiler: None,
         }
     }
 
+    /// Records the time taken to load each asset from now on in the given profiler.
+    pub fn set_profiler(&mut self, profiler: SharedProfiler) {
+        self.profiler = Some(profiler);
+    }
+
     fn on_load(key: K, data: &mut LoadStatus<T>, loaded: Result<T, LoadError>) -> LoadStatus<T> {
         if let LoadStatus::Loading(load, fail) = data {
             // This if-let should never fail, because any asset should only be loaded once.
@@ -76,6 +86,7 @@ where
         let entry = self.assets.entry(k1);
         let stored_assets = &mut self.stored_assets;
         let loader = Arc::clone(&self.asset_loader);
+        let profiler = self.profiler.clone();
         entry
             .or_insert_with(|| {
                 let id = new_asset_id();
@@ -88,6 +99,9 @@ where
 
                 stored_assets.insert(id, Arc::clone(&data));
                 tokio::spawn(async move {
+                    let _guard = profiler
+                        .as_ref()
+                        .map(|profiler| profiler.time("assets", std::any::type_name::<T>()));
                     let k_clone = k.clone();
                     let loaded = loader.load(k).await;
                     let mut data = data.write().await;
@@ -182,6 +196,23 @@ impl<T> Asset<T> {
         }
     }
 
+    /// If the asset is loaded, run this function on it, allowing it to be modified.
+    /// Returns true if the asset was loaded.
+    pub async fn if_loaded_mut(&self, func: impl FnOnce(&mut T)) -> bool {
+        if let Some(data) = self.data.upgrade() {
+            match &mut *data.write().await {
+                LoadStatus::Loading(_, _) => false,
+                LoadStatus::Loaded(value) => {
+                    func(value);
+                    true
+                }
+                LoadStatus::Failed(_) => false,
+            }
+        } else {
+            false
+        }
+    }
+
     /// Waits for the asset to be loaded (or until the load fails).
     pub async fn wait_until_loaded_or_failed(&self) {
         let (tx, mut rx) = tokio::sync::mpsc::channel(1);
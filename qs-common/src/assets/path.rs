@@ -53,6 +53,14 @@ impl AssetPath {
         }
     }
 
+    /// Creates a path to a file in the same directory as this one.
+    pub fn with_file_name(&self, name: &str) -> Self {
+        let mut segments = self.segments.clone();
+        segments.pop();
+        segments.push(name.to_string());
+        Self::new(segments)
+    }
+
     pub fn to_path(&self) -> PathBuf {
         let mut path = ASSET_FOLDER.clone();
         for segment in &self.segments {
@@ -0,0 +1,110 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Counts of allocations made by a single thread.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct AllocationStats {
+    /// The number of allocations (including reallocations) that were made.
+    pub count: u64,
+    /// The total number of bytes requested by those allocations.
+    pub bytes: u64,
+}
+
+impl AllocationStats {
+    pub const ZERO: Self = Self { count: 0, bytes: 0 };
+}
+
+impl std::ops::Add for AllocationStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            count: self.count + other.count,
+            bytes: self.bytes + other.bytes,
+        }
+    }
+}
+
+impl std::ops::AddAssign for AllocationStats {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl std::ops::Sub for AllocationStats {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self {
+            count: self.count.wrapping_sub(other.count),
+            bytes: self.bytes.wrapping_sub(other.bytes),
+        }
+    }
+}
+
+thread_local! {
+    /// The allocations made by this thread since it started.
+    static THREAD_ALLOCATIONS: Cell<AllocationStats> = const { Cell::new(AllocationStats::ZERO) };
+}
+
+/// Set to true the first time the `CountingAllocator` is used, so that we know whether allocation counts are meaningful.
+static COUNTING_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Returns every allocation made by the current thread since it started.
+/// If the `CountingAllocator` is not installed, this is always zero.
+pub fn thread_allocations() -> AllocationStats {
+    THREAD_ALLOCATIONS
+        .try_with(Cell::get)
+        .unwrap_or(AllocationStats::ZERO)
+}
+
+/// Returns true if the `CountingAllocator` is installed as the global allocator.
+pub fn allocation_counting_enabled() -> bool {
+    COUNTING_ENABLED.load(Ordering::Relaxed)
+}
+
+fn record_allocation(bytes: usize) {
+    COUNTING_ENABLED.store(true, Ordering::Relaxed);
+    // If the thread is being torn down, the thread local may no longer be accessible; just don't count these allocations.
+    let _ = THREAD_ALLOCATIONS.try_with(|allocations| {
+        allocations.set(
+            allocations.get()
+                + AllocationStats {
+                    count: 1,
+                    bytes: bytes as u64,
+                },
+        )
+    });
+}
+
+/// A global allocator that counts the allocations made by each thread, then defers to the system allocator.
+/// Profile segments use these counts to report how much allocation each task performs.
+///
+/// This is opt-in, because counting every allocation has a small cost. To enable it, add this to the binary crate:
+/// ```ignore
+/// #[global_allocator]
+/// static ALLOCATOR: qs_common::profile::CountingAllocator = qs_common::profile::CountingAllocator;
+/// ```
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record_allocation(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        record_allocation(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record_allocation(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+use super::FrameBreakdown;
+
+/// Aggregates frame breakdowns over a recording window into folded stacks, the input format
+/// used by flamegraph tools such as `inferno` and `flamegraph.pl`.
+///
+/// Each line of the output has the form `main;render;ui 1234`, where the number is the amount
+/// of microseconds spent in that task itself (not in its sub-tasks) across every recorded frame.
+#[derive(Debug, Default)]
+pub struct FlamegraphRecorder {
+    /// Maps semicolon-separated task stacks to the microseconds spent in the innermost task.
+    stacks: BTreeMap<String, u64>,
+    /// How many frames have been recorded.
+    pub frames: u64,
+}
+
+impl FlamegraphRecorder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds a single frame to the recording. Every stack starts with `root_name`, so the time the
+    /// root spent outside its sub-tasks is attributed to the stack `root_name` itself.
+    pub fn record(&mut self, root_name: &str, breakdown: &FrameBreakdown) {
+        self.frames += 1;
+        self.record_stack(root_name.to_string(), breakdown);
+    }
+
+    fn record_stack(&mut self, stack: String, breakdown: &FrameBreakdown) {
+        for (task_name, task) in &breakdown.sub_tasks {
+            self.record_stack(format!("{};{}", stack, task_name), task);
+        }
+        self.record_self_time(stack, breakdown);
+    }
+
+    fn record_self_time(&mut self, stack: String, breakdown: &FrameBreakdown) {
+        let children_seconds = breakdown
+            .sub_tasks
+            .iter()
+            .map(|(_, task)| task.seconds)
+            .sum::<f64>();
+        let self_micros =
+            ((breakdown.seconds - children_seconds).max(0.0) * 1_000_000.0).round() as u64;
+        if self_micros > 0 {
+            *self.stacks.entry(stack).or_insert(0) += self_micros;
+        }
+    }
+
+    /// Writes the folded stacks, one per line, sorted by stack name.
+    pub fn write_folded(&self, mut writer: impl Write) -> std::io::Result<()> {
+        for (stack, micros) in &self.stacks {
+            writeln!(writer, "{} {}", stack, micros)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the folded stacks to the given file, overwriting it if it exists.
+    pub fn write_folded_to_file(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let file = std::fs::File::create(path)?;
+        self.write_folded(std::io::BufWriter::new(file))
+    }
+}
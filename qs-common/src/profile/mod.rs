@@ -0,0 +1,666 @@
+//! The `profile` module provides utilities for profiling often-called functions.
+
+mod allocation;
+pub use allocation::*;
+mod flamegraph;
+pub use flamegraph::*;
+mod shared;
+pub use shared::*;
+
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+pub type TaskName = &'static str;
+
+pub fn display_time(seconds: f64) -> String {
+    let (time, time_unit) = if seconds >= 1.0 {
+        (seconds, " ")
+    } else if seconds >= 0.001 {
+        (seconds * 1000.0, "m")
+    } else if seconds >= 0.000_001 {
+        (seconds * 1_000_000.0, "\u{03BC}") // micro SI prefix (Greek lowercase mu)
+    } else {
+        (seconds * 1_000_000_000.0, "n")
+    };
+    format!("{:3}{}s", time as u32, time_unit)
+}
+
+pub fn display_bytes(bytes: u64) -> String {
+    let (amount, unit) = if bytes >= 1 << 30 {
+        (bytes >> 30, "Gi")
+    } else if bytes >= 1 << 20 {
+        (bytes >> 20, "Mi")
+    } else if bytes >= 1 << 10 {
+        (bytes >> 10, "Ki")
+    } else {
+        (bytes, "  ")
+    };
+    format!("{:4}{}B", amount, unit)
+}
+
+/// Writes a single line of a profiling report, describing one task.
+/// If allocations are being counted, they are shown after the time.
+fn display_task(
+    f: &mut std::fmt::Formatter<'_>,
+    indent: usize,
+    percentage: f64,
+    seconds: f64,
+    allocations: AllocationStats,
+    task_name: TaskName,
+) -> std::fmt::Result {
+    if allocation_counting_enabled() {
+        // E.g. [indent] 5.32% 132ms   12 allocs    3KiB: some_task
+        writeln!(
+            f,
+            "{:indent$}{:5.2}% {} {:4} allocs {}: {}",
+            "",
+            percentage,
+            display_time(seconds),
+            allocations.count,
+            display_bytes(allocations.bytes),
+            task_name,
+            indent = indent
+        )
+    } else {
+        // E.g. [indent] 5.32% 132ms: some_task
+        writeln!(
+            f,
+            "{:indent$}{:5.2}% {}: {}",
+            "",
+            percentage,
+            display_time(seconds),
+            task_name,
+            indent = indent
+        )
+    }
+}
+
+/// Allows profiling of events that happen repeatedly in a roughly predictable manner.
+/// Profiling using this object allows you to see which parts of a function normally take more
+/// time than others over the course of many invocations of the function.
+pub struct CycleProfiler {
+    /// Represents the entire body of the computation.
+    pub main_segment: ProfileSegment,
+
+    /// Times between iterations of the main segment to
+    /// deduce how much time was spent not actually doing profiled stuff.
+    pub stopwatch: InterpolatedStopwatch,
+
+    /// Checks each completed iteration of the main segment for hitches.
+    pub hitch_detector: HitchDetector,
+
+    /// If we are currently recording a flamegraph, this contains the stacks recorded so far.
+    flamegraph: Option<FlamegraphRecorder>,
+
+    /// Collects timings from tasks on other threads. These are shown in separate lanes after the main segment.
+    pub shared: SharedProfiler,
+}
+
+impl CycleProfiler {
+    pub fn new(interpolation_amount: usize) -> Self {
+        Self {
+            main_segment: ProfileSegment::new(interpolation_amount),
+            stopwatch: InterpolatedStopwatch::new(interpolation_amount),
+            hitch_detector: HitchDetector::new(Default::default()),
+            flamegraph: None,
+            shared: SharedProfiler::new(interpolation_amount),
+        }
+    }
+
+    /// The name given to the main segment in flamegraphs.
+    pub const MAIN_TASK_NAME: &'static str = "main";
+
+    /// Starts recording every subsequent frame into a flamegraph, discarding any recording in progress.
+    pub fn start_recording(&mut self) {
+        self.flamegraph = Some(FlamegraphRecorder::new());
+    }
+
+    /// Is a flamegraph currently being recorded?
+    pub fn is_recording(&self) -> bool {
+        self.flamegraph.is_some()
+    }
+
+    /// Stops recording a flamegraph, returning the frames recorded since `start_recording` was called.
+    pub fn finish_recording(&mut self) -> Option<FlamegraphRecorder> {
+        self.flamegraph.take()
+    }
+
+    /// Call this function after every iteration of the main segment, once its guard has been dropped.
+    /// This collects the breakdown of the iteration that just completed, and checks whether it was a hitch.
+    ///
+    /// Returns the hitch, if this iteration was one.
+    pub fn end_frame(&mut self) -> Option<&Hitch> {
+        let breakdown = self.main_segment.take_frame_breakdown();
+        if let Some(flamegraph) = &mut self.flamegraph {
+            flamegraph.record(Self::MAIN_TASK_NAME, &breakdown);
+        }
+        let average = if self.main_segment.ticks > self.main_segment.interpolation_amount as u64 {
+            Some(self.main_segment.average_time_excluding_latest())
+        } else {
+            None
+        };
+        self.hitch_detector
+            .check(self.main_segment.ticks, breakdown, average)
+    }
+}
+
+impl std::fmt::Display for CycleProfiler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.main_segment.ticks < self.main_segment.interpolation_amount as u64 {
+            return writeln!(f, "Insufficient data");
+        }
+        let total_time = self.stopwatch.average_time().as_secs_f64();
+        let calculation_time = self.main_segment.average_time();
+        writeln!(
+            f,
+            "Total time elapsed: {} / {}, {:5.2}% of total CPU time",
+            display_time(calculation_time),
+            display_time(total_time),
+            100.0 * calculation_time / total_time
+        )?;
+        self.main_segment.display(f, 0)?;
+        write!(f, "{}", self.shared)
+    }
+}
+
+/// Represents the time it took to complete a particular block of code.
+/// This counts the durations of intervals of time, and calculates the average
+/// duration, by storing the durations of the last `n` intervals, where `n` is some arbitrary
+/// constant specified in the stopwatch constructor.
+pub struct ProfileSegment {
+    interpolation_amount: usize,
+    sub_tasks: HashMap<TaskName, ProfileSegment>,
+    durations_seconds: Vec<f64>,
+    /// The allocations made during each of the last `n` intervals, stored alongside `durations_seconds`.
+    allocations: Vec<AllocationStats>,
+    offset: usize,
+    pub ticks: u64,
+    /// The total time spent in this segment since the last call to `take_frame_breakdown`.
+    frame_seconds: f64,
+    /// The total allocations made in this segment since the last call to `take_frame_breakdown`.
+    frame_allocations: AllocationStats,
+}
+
+impl ProfileSegment {
+    fn new(interpolation_amount: usize) -> Self {
+        Self {
+            interpolation_amount,
+            sub_tasks: HashMap::new(),
+            durations_seconds: vec![1.0; interpolation_amount],
+            allocations: vec![AllocationStats::ZERO; interpolation_amount],
+            offset: 0,
+            ticks: 0,
+            frame_seconds: 0.0,
+            frame_allocations: AllocationStats::ZERO,
+        }
+    }
+
+    fn display(&self, f: &mut std::fmt::Formatter<'_>, indent: usize) -> std::fmt::Result {
+        let total_duration = self.average_time();
+        for (task_name, task) in &self.sub_tasks {
+            let time_seconds = task.average_time();
+            display_task(
+                f,
+                indent,
+                100.0 * time_seconds / total_duration,
+                time_seconds,
+                task.average_allocations(),
+                task_name,
+            )?;
+            task.display(f, indent + 4)?;
+        }
+
+        Ok(())
+    }
+
+    /// Call this function every time the given event happens, supplying the duration of the interval
+    /// and the allocations made during it.
+    fn tick(&mut self, duration: f64, allocations: AllocationStats) {
+        self.durations_seconds[self.offset] = duration;
+        self.allocations[self.offset] = allocations;
+        self.offset = (self.offset + 1) % self.durations_seconds.len();
+        self.ticks += 1;
+        self.frame_seconds += duration;
+        self.frame_allocations += allocations;
+    }
+
+    /// Collects how long this segment and each of its sub-tasks took since the last call to this function,
+    /// and resets those counters ready for the next frame. Sub-tasks that did not run are omitted.
+    fn take_frame_breakdown(&mut self) -> FrameBreakdown {
+        let mut sub_tasks = self
+            .sub_tasks
+            .iter_mut()
+            .map(|(task_name, task)| (*task_name, task.take_frame_breakdown()))
+            .filter(|(_, breakdown)| breakdown.seconds > 0.0)
+            .collect::<Vec<_>>();
+        sub_tasks.sort_by(|(_, a), (_, b)| b.seconds.partial_cmp(&a.seconds).unwrap());
+        FrameBreakdown {
+            seconds: std::mem::take(&mut self.frame_seconds),
+            allocations: std::mem::take(&mut self.frame_allocations),
+            sub_tasks,
+        }
+    }
+
+    pub fn time(&mut self) -> ProfileSegmentGuard<'_> {
+        ProfileSegmentGuard {
+            start_instant: Instant::now(),
+            start_allocations: thread_allocations(),
+            segment: self,
+        }
+    }
+
+    /// Returns the sub-task with the given name, creating it if it does not exist.
+    fn sub_task(&mut self, name: TaskName) -> &mut ProfileSegment {
+        let interpolation_amount = self.interpolation_amount;
+        self.sub_tasks
+            .entry(name)
+            .or_insert_with(|| ProfileSegment::new(interpolation_amount))
+    }
+
+    /// Returns an amount of seconds.
+    pub fn average_time(&self) -> f64 {
+        self.durations_seconds.iter().copied().sum::<f64>() / self.durations_seconds.len() as f64
+    }
+
+    /// Returns the average amount of seconds, ignoring the most recent duration.
+    /// This is useful for comparing the most recent duration against the ones before it.
+    fn average_time_excluding_latest(&self) -> f64 {
+        let len = self.durations_seconds.len();
+        if len <= 1 {
+            return self.average_time();
+        }
+        let latest = self.durations_seconds[(self.offset + len - 1) % len];
+        (self.durations_seconds.iter().copied().sum::<f64>() - latest) / (len - 1) as f64
+    }
+
+    /// Returns the average allocations made in each interval.
+    /// This is always zero unless the `CountingAllocator` is installed.
+    pub fn average_allocations(&self) -> AllocationStats {
+        let total = self
+            .allocations
+            .iter()
+            .fold(AllocationStats::ZERO, |l, r| l + *r);
+        let len = self.allocations.len().max(1) as u64;
+        AllocationStats {
+            count: total.count / len,
+            bytes: total.bytes / len,
+        }
+    }
+
+    /// How many of the stored intervals have actually been recorded, rather than being placeholder values?
+    fn recorded_samples(&self) -> usize {
+        (self.ticks as usize).min(self.durations_seconds.len())
+    }
+
+    /// Like `average_time`, but ignores placeholder values if fewer than `n` intervals have been recorded.
+    /// This is useful for tasks that happen rarely, such as loading an asset.
+    fn recorded_average_time(&self) -> f64 {
+        let samples = self.recorded_samples().max(1);
+        self.durations_seconds[..samples]
+            .iter()
+            .copied()
+            .sum::<f64>()
+            / samples as f64
+    }
+
+    /// Like `average_allocations`, but ignores placeholder values if fewer than `n` intervals have been recorded.
+    fn recorded_average_allocations(&self) -> AllocationStats {
+        let samples = self.recorded_samples().max(1);
+        let total = self.allocations[..samples]
+            .iter()
+            .fold(AllocationStats::ZERO, |l, r| l + *r);
+        AllocationStats {
+            count: total.count / samples as u64,
+            bytes: total.bytes / samples as u64,
+        }
+    }
+}
+
+impl std::fmt::Display for ProfileSegment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.display(f, 0)
+    }
+}
+
+/// Times the duration of an event. When dropped, the duration of this struct's life
+/// will be sent to the segment.
+pub struct ProfileSegmentGuard<'a> {
+    start_instant: Instant,
+    /// The allocations this thread had made when the guard was created.
+    start_allocations: AllocationStats,
+    segment: &'a mut ProfileSegment,
+}
+
+impl Drop for ProfileSegmentGuard<'_> {
+    fn drop(&mut self) {
+        self.segment.tick(
+            Instant::now()
+                .duration_since(self.start_instant)
+                .as_secs_f64(),
+            thread_allocations() - self.start_allocations,
+        );
+    }
+}
+
+impl<'a> ProfileSegmentGuard<'a> {
+    /// TODO what happens if a subtask is timed multiple times?
+    /// Currently they're treated as separate iterations but that'll mess up the percentage counts.
+    pub fn task(&mut self, name: TaskName) -> &mut ProfileSegment {
+        self.segment.sub_task(name)
+    }
+}
+
+/// The time taken by a segment and its sub-tasks over a single frame.
+#[derive(Debug, Clone)]
+pub struct FrameBreakdown {
+    /// The amount of seconds spent in this segment during the frame.
+    pub seconds: f64,
+    /// The allocations made in this segment during the frame.
+    pub allocations: AllocationStats,
+    /// The sub-tasks that ran during the frame, with the slowest first.
+    pub sub_tasks: Vec<(TaskName, FrameBreakdown)>,
+}
+
+impl FrameBreakdown {
+    fn display(&self, f: &mut std::fmt::Formatter<'_>, indent: usize) -> std::fmt::Result {
+        for (task_name, task) in &self.sub_tasks {
+            display_task(
+                f,
+                indent,
+                100.0 * task.seconds / self.seconds,
+                task.seconds,
+                task.allocations,
+                task_name,
+            )?;
+            task.display(f, indent + 4)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for FrameBreakdown {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.display(f, 0)
+    }
+}
+
+/// Configures when a frame is considered to be a hitch.
+/// A frame is a hitch if it exceeds any of the enabled thresholds.
+#[derive(Debug, Copy, Clone)]
+pub struct HitchConfig {
+    /// If set, any frame that takes longer than this amount of seconds is a hitch.
+    pub budget_seconds: Option<f64>,
+    /// If set, any frame that takes longer than this multiple of the rolling average frame time is a hitch.
+    pub average_multiplier: Option<f64>,
+    /// How many hitches to remember. When more hitches occur, the oldest ones are forgotten.
+    pub history_size: usize,
+}
+
+impl Default for HitchConfig {
+    fn default() -> Self {
+        Self {
+            budget_seconds: Some(1.0 / 30.0),
+            average_multiplier: Some(3.0),
+            history_size: 16,
+        }
+    }
+}
+
+/// A frame that took longer than it should have.
+#[derive(Debug, Clone)]
+pub struct Hitch {
+    /// The number of iterations of the main segment that had completed, including this one.
+    pub frame: u64,
+    /// When the hitch was detected.
+    pub time: SystemTime,
+    /// The threshold in seconds that this frame exceeded.
+    pub threshold_seconds: f64,
+    /// What the frame spent its time doing.
+    pub breakdown: FrameBreakdown,
+}
+
+impl std::fmt::Display for Hitch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let since_epoch = self
+            .time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        writeln!(
+            f,
+            "Hitch on frame {} at {:.3}s since epoch: {} / {} threshold",
+            self.frame,
+            since_epoch.as_secs_f64(),
+            display_time(self.breakdown.seconds),
+            display_time(self.threshold_seconds)
+        )?;
+        self.breakdown.display(f, 4)
+    }
+}
+
+/// A function to be called whenever a hitch is detected.
+type OnHitchFunc = Box<dyn FnMut(&Hitch) + Send + 'static>;
+
+/// Detects frames that take too long, and remembers the most recent ones so that they can be included in bug reports.
+pub struct HitchDetector {
+    pub config: HitchConfig,
+    history: VecDeque<Hitch>,
+    callbacks: Vec<OnHitchFunc>,
+}
+
+impl HitchDetector {
+    pub fn new(config: HitchConfig) -> Self {
+        Self {
+            config,
+            history: VecDeque::new(),
+            callbacks: Vec::new(),
+        }
+    }
+
+    /// When a hitch is detected, call this function on it.
+    /// The function should be lightweight, since it is called on the thread that is running the profiled code.
+    pub fn on_hitch(&mut self, func: impl FnMut(&Hitch) + Send + 'static) {
+        self.callbacks.push(Box::new(func));
+    }
+
+    /// The most recent hitches, from oldest to newest.
+    pub fn history(&self) -> impl Iterator<Item = &Hitch> {
+        self.history.iter()
+    }
+
+    /// Writes every hitch in the history to the given file, overwriting it if it exists.
+    pub fn dump_to_file(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut file = std::fs::File::create(path)?;
+        for hitch in &self.history {
+            writeln!(file, "{}", hitch)?;
+        }
+        Ok(())
+    }
+
+    /// Checks whether the given frame was a hitch.
+    /// If `average_seconds` is `None`, there is not yet enough data to compare against the rolling average.
+    fn check(
+        &mut self,
+        frame: u64,
+        breakdown: FrameBreakdown,
+        average_seconds: Option<f64>,
+    ) -> Option<&Hitch> {
+        let average_threshold = self
+            .config
+            .average_multiplier
+            .and_then(|multiplier| Some(multiplier * average_seconds?));
+        let threshold_seconds = match (self.config.budget_seconds, average_threshold) {
+            (Some(budget), Some(average)) => budget.min(average),
+            (Some(threshold), None) | (None, Some(threshold)) => threshold,
+            (None, None) => return None,
+        };
+        if breakdown.seconds <= threshold_seconds {
+            return None;
+        }
+
+        let hitch = Hitch {
+            frame,
+            time: SystemTime::now(),
+            threshold_seconds,
+            breakdown,
+        };
+        tracing::warn!("{}", hitch);
+        for callback in &mut self.callbacks {
+            callback(&hitch);
+        }
+
+        if self.history.len() >= self.config.history_size {
+            self.history.pop_front();
+        }
+        self.history.push_back(hitch);
+        self.history.back()
+    }
+}
+
+/// An interpolated stopwatch counts the time between successive events, and calculates the average
+/// time between those events, by storing the times of the last `n` events, where `n` is some arbitrary
+/// constant specified in the stopwatch constructor.
+pub struct InterpolatedStopwatch {
+    times: Vec<Instant>,
+    offset: usize,
+    pub ticks: u64,
+}
+
+impl InterpolatedStopwatch {
+    pub fn new(interpolation_amount: usize) -> InterpolatedStopwatch {
+        InterpolatedStopwatch {
+            times: vec![Instant::now(); interpolation_amount],
+            offset: 0,
+            ticks: 0,
+        }
+    }
+
+    /// Call this function every time the given event happens.
+    /// You will be able to retrieve the average time between calls to `tick`
+    /// using the `average_time` function.
+    ///
+    /// Returns the time between the previous tick and this tick.
+    pub fn tick(&mut self) -> Duration {
+        let prev_offset = match self.offset {
+            0 => self.times.len() - 1,
+            _ => self.offset - 1,
+        };
+
+        self.times[self.offset] = Instant::now();
+        let old_time = self.times[prev_offset];
+        let time = self.times[self.offset].duration_since(old_time);
+        self.offset = (self.offset + 1) % self.times.len();
+        self.ticks += 1;
+        time
+    }
+
+    pub fn average_time(&self) -> Duration {
+        let prev_offset = match self.offset {
+            0 => self.times.len() - 1,
+            _ => self.offset - 1,
+        };
+        self.times[prev_offset]
+            .duration_since(self.times[self.offset])
+            .div_f64(self.times.len() as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    #[test]
+    fn detects_frames_over_budget() {
+        let mut profiler = CycleProfiler::new(4);
+        profiler.hitch_detector.config = HitchConfig {
+            budget_seconds: Some(0.010),
+            average_multiplier: None,
+            history_size: 2,
+        };
+
+        for frame_seconds in &[0.005, 0.020, 0.030, 0.040] {
+            profiler
+                .main_segment
+                .tick(*frame_seconds, AllocationStats::ZERO);
+            profiler.end_frame();
+        }
+
+        // Only the two most recent hitches should be remembered.
+        let frames = profiler
+            .hitch_detector
+            .history()
+            .map(|hitch| hitch.frame)
+            .collect::<Vec<_>>();
+        assert_eq!(frames, vec![3, 4]);
+    }
+
+    #[test]
+    fn hitch_breakdown_only_contains_tasks_from_that_frame() {
+        let mut profiler = CycleProfiler::new(4);
+        profiler.hitch_detector.config = HitchConfig {
+            budget_seconds: Some(0.010),
+            average_multiplier: None,
+            history_size: 4,
+        };
+
+        profiler
+            .main_segment
+            .sub_task("layout")
+            .tick(0.001, AllocationStats::ZERO);
+        profiler.main_segment.tick(0.002, AllocationStats::ZERO);
+        profiler.end_frame();
+
+        profiler
+            .main_segment
+            .sub_task("render")
+            .tick(0.015, AllocationStats::ZERO);
+        profiler.main_segment.tick(0.016, AllocationStats::ZERO);
+        let hitch = profiler.end_frame().expect("frame should be a hitch");
+        let names = hitch
+            .breakdown
+            .sub_tasks
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["render"]);
+    }
+
+    #[test]
+    fn guard_attributes_allocations_to_segment() {
+        let mut segment = ProfileSegment::new(1);
+        {
+            let _guard = segment.time();
+            drop(std::hint::black_box(vec![0u8; 100]));
+        }
+        let allocations = segment.average_allocations();
+        assert!(allocations.count >= 1);
+        assert!(allocations.bytes >= 100);
+    }
+
+    #[test]
+    fn flamegraph_records_self_time_of_nested_tasks() {
+        let mut profiler = CycleProfiler::new(4);
+        profiler.start_recording();
+        for _ in 0..2 {
+            let render = profiler.main_segment.sub_task("render");
+            render.sub_task("ui").tick(0.002, AllocationStats::ZERO);
+            render.tick(0.005, AllocationStats::ZERO);
+            profiler.main_segment.tick(0.006, AllocationStats::ZERO);
+            profiler.end_frame();
+        }
+
+        let mut output = Vec::new();
+        let recording = profiler.finish_recording().unwrap();
+        recording.write_folded(&mut output).unwrap();
+        assert_eq!(recording.frames, 2);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "main 2000\nmain;render 6000\nmain;render;ui 4000\n"
+        );
+    }
+}
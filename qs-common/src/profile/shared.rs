@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread::ThreadId;
+use std::time::Instant;
+
+use super::{
+    display_task, display_time, thread_allocations, AllocationStats, ProfileSegment, TaskName,
+};
+
+/// The name of a lane, which groups together related tasks that run off the main thread, such as asset loading.
+pub type LaneName = &'static str;
+
+/// A thread-safe profiler that tasks on any thread can time into. Cloning this creates another handle to the same data.
+///
+/// Unlike a `ProfileSegment`, tasks here don't happen once per iteration of some main loop, so each task is reported
+/// with the average time of its recent runs rather than a percentage of a frame.
+/// Tasks are grouped into named lanes, which are shown separately in the report.
+#[derive(Clone)]
+pub struct SharedProfiler {
+    interpolation_amount: usize,
+    lanes: Arc<Mutex<HashMap<LaneName, ProfileSegment>>>,
+}
+
+impl SharedProfiler {
+    pub fn new(interpolation_amount: usize) -> Self {
+        Self {
+            interpolation_amount,
+            lanes: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Starts timing a task in the given lane. When the returned guard is dropped, the duration is recorded.
+    /// The guard may be held across `.await` points and sent between threads.
+    pub fn time(&self, lane: LaneName, task: TaskName) -> SharedProfileGuard {
+        SharedProfileGuard {
+            profiler: self.clone(),
+            lane,
+            path: vec![task],
+            start_instant: Instant::now(),
+            start_thread: std::thread::current().id(),
+            start_allocations: thread_allocations(),
+        }
+    }
+
+    fn record(
+        &self,
+        lane: LaneName,
+        path: &[TaskName],
+        duration: f64,
+        allocations: AllocationStats,
+    ) {
+        let interpolation_amount = self.interpolation_amount;
+        let mut lanes = self.lanes.lock().unwrap();
+        let mut segment = lanes
+            .entry(lane)
+            .or_insert_with(|| ProfileSegment::new(interpolation_amount));
+        for task in path {
+            segment = segment.sub_task(task);
+        }
+        segment.tick(duration, allocations);
+    }
+}
+
+impl std::fmt::Display for SharedProfiler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let lanes = self.lanes.lock().unwrap();
+        let mut lane_names = lanes.keys().collect::<Vec<_>>();
+        lane_names.sort();
+        for lane_name in lane_names {
+            writeln!(f, "Lane {}:", lane_name)?;
+            for (task_name, task) in &lanes[lane_name].sub_tasks {
+                // E.g. [indent] 132ms x12: some_task
+                writeln!(
+                    f,
+                    "    {} x{}: {}",
+                    display_time(task.recorded_average_time()),
+                    task.ticks,
+                    task_name
+                )?;
+                display_lane_sub_tasks(f, task, 8)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Displays the sub-tasks of a task in a lane, as percentages of the time spent in the task.
+fn display_lane_sub_tasks(
+    f: &mut std::fmt::Formatter<'_>,
+    segment: &ProfileSegment,
+    indent: usize,
+) -> std::fmt::Result {
+    let total_duration = segment.recorded_average_time();
+    for (task_name, task) in &segment.sub_tasks {
+        let time_seconds = task.recorded_average_time();
+        display_task(
+            f,
+            indent,
+            100.0 * time_seconds / total_duration,
+            time_seconds,
+            task.recorded_average_allocations(),
+            task_name,
+        )?;
+        display_lane_sub_tasks(f, task, indent + 4)?;
+    }
+    Ok(())
+}
+
+/// Times the duration of a task in a `SharedProfiler`. When dropped, the duration of this struct's life
+/// will be recorded in the profiler.
+pub struct SharedProfileGuard {
+    profiler: SharedProfiler,
+    lane: LaneName,
+    /// The names of the tasks from the root of the lane down to this task.
+    path: Vec<TaskName>,
+    start_instant: Instant,
+    /// Allocations are counted per thread, so they can only be attributed to this task if it finishes on the thread it started on.
+    start_thread: ThreadId,
+    start_allocations: AllocationStats,
+}
+
+impl SharedProfileGuard {
+    /// Starts timing a sub-task of this task.
+    pub fn task(&self, name: TaskName) -> SharedProfileGuard {
+        let mut path = self.path.clone();
+        path.push(name);
+        SharedProfileGuard {
+            profiler: self.profiler.clone(),
+            lane: self.lane,
+            path,
+            start_instant: Instant::now(),
+            start_thread: std::thread::current().id(),
+            start_allocations: thread_allocations(),
+        }
+    }
+}
+
+impl Drop for SharedProfileGuard {
+    fn drop(&mut self) {
+        let allocations = if std::thread::current().id() == self.start_thread {
+            thread_allocations() - self.start_allocations
+        } else {
+            AllocationStats::ZERO
+        };
+        self.profiler.record(
+            self.lane,
+            &self.path,
+            Instant::now()
+                .duration_since(self.start_instant)
+                .as_secs_f64(),
+            allocations,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_profiler_records_tasks_from_other_threads() {
+        let profiler = SharedProfiler::new(4);
+        let handles = (0..3)
+            .map(|_| {
+                let profiler = profiler.clone();
+                std::thread::spawn(move || {
+                    let guard = profiler.time("assets", "load");
+                    let _decode = guard.task("decode");
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let lanes = profiler.lanes.lock().unwrap();
+        let load = &lanes["assets"].sub_tasks["load"];
+        assert_eq!(load.ticks, 3);
+        assert_eq!(load.sub_tasks["decode"].ticks, 3);
+    }
+}
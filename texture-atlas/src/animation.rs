@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How long each frame of an animation lasts if the animation has no sidecar file saying otherwise.
+pub const DEFAULT_FRAME_DURATION: f64 = 0.1;
+
+/// A sequence of texture regions that are displayed one after another, such as a walk cycle.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Animation {
+    /// The frames of the animation, in the order they are displayed.
+    pub frames: Vec<AnimationFrame>,
+    /// What happens when the animation reaches the last frame.
+    pub loop_mode: LoopMode,
+}
+
+/// A single frame of an animation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnimationFrame {
+    /// The name of the texture region to display during this frame.
+    pub region: String,
+    /// How long this frame is displayed for, in seconds.
+    pub duration: f64,
+}
+
+/// What an animation does after its last frame.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoopMode {
+    /// Play the animation once, then keep displaying the last frame.
+    Once,
+    /// Start again from the first frame.
+    Loop,
+    /// Play the animation backwards back to the first frame, then forwards again, and so on.
+    PingPong,
+}
+
+/// The contents of an optional sidecar file `<animation>.anim.json`, stored alongside the frames of an animation
+/// to configure how it is played.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnimationSidecar {
+    /// The duration of each frame in seconds. If there are fewer durations than frames, the last duration is used for the remaining frames.
+    #[serde(default)]
+    pub durations: Vec<f64>,
+    /// What happens when the animation reaches the last frame.
+    #[serde(default = "default_loop_mode")]
+    pub loop_mode: LoopMode,
+}
+
+fn default_loop_mode() -> LoopMode {
+    LoopMode::Loop
+}
+
+impl Animation {
+    /// Creates an animation that displays the given texture regions in order, configured by the sidecar file if there is one.
+    pub fn new(regions: Vec<String>, sidecar: Option<&AnimationSidecar>) -> Self {
+        let durations = sidecar
+            .map(|sidecar| sidecar.durations.as_slice())
+            .unwrap_or(&[]);
+        Self {
+            frames: regions
+                .into_iter()
+                .enumerate()
+                .map(|(i, region)| AnimationFrame {
+                    region,
+                    duration: durations
+                        .get(i)
+                        .or_else(|| durations.last())
+                        .copied()
+                        .unwrap_or(DEFAULT_FRAME_DURATION),
+                })
+                .collect(),
+            loop_mode: sidecar.map_or(LoopMode::Loop, |sidecar| sidecar.loop_mode),
+        }
+    }
+
+    /// The time taken to play every frame once, in seconds.
+    pub fn duration(&self) -> f64 {
+        self.frames.iter().map(|frame| frame.duration).sum()
+    }
+
+    /// Works out which frame should be displayed when the given number of seconds have passed since the animation started.
+    /// Returns the index of the frame, or `None` if the animation has no frames.
+    pub fn frame_index_at(&self, elapsed: f64) -> Option<usize> {
+        let last = self.frames.len().checked_sub(1)?;
+        let duration = self.duration();
+        if duration <= 0.0 {
+            return Some(0);
+        }
+
+        // Work out how far we are through a single forwards play of the animation, and whether we're playing it backwards.
+        let elapsed = elapsed.max(0.0);
+        let (time, backwards) = match self.loop_mode {
+            LoopMode::Once if elapsed >= duration => return Some(last),
+            LoopMode::Once => (elapsed, false),
+            LoopMode::Loop => (elapsed % duration, false),
+            LoopMode::PingPong => {
+                let time = elapsed % (2.0 * duration);
+                if time < duration {
+                    (time, false)
+                } else {
+                    (2.0 * duration - time, true)
+                }
+            }
+        };
+
+        let mut frame_end = 0.0;
+        for (i, frame) in self.frames.iter().enumerate() {
+            frame_end += frame.duration;
+            if time < frame_end || (backwards && time <= frame_end) {
+                return Some(i);
+            }
+        }
+        Some(last)
+    }
+
+    /// Works out which frame should be displayed when the given number of seconds have passed since the animation started.
+    pub fn frame_at(&self, elapsed: f64) -> Option<&AnimationFrame> {
+        self.frame_index_at(elapsed).map(|i| &self.frames[i])
+    }
+}
+
+/// If the region name follows the animation naming convention `<animation>_<index>.<extension>`, for example `walk_0.png`,
+/// returns the name of the animation and the index of this frame.
+pub fn parse_animation_frame_name(name: &str) -> Option<(&str, u32)> {
+    let stem = match name.rfind('.') {
+        Some(dot) if !name[dot..].contains('/') => &name[..dot],
+        _ => name,
+    };
+    let underscore = stem.rfind('_')?;
+    let index = stem[underscore + 1..].parse().ok()?;
+    Some((&stem[..underscore], index))
+}
+
+/// Groups together the region names that follow the animation naming convention `<animation>_<index>.<extension>`.
+/// Returns a map from each animation's name to its frames' region names, sorted by index.
+pub fn group_animation_frames<'a>(
+    names: impl IntoIterator<Item = &'a str>,
+) -> HashMap<String, Vec<String>> {
+    let mut animations = HashMap::<String, Vec<(u32, String)>>::new();
+    for name in names {
+        if let Some((animation, index)) = parse_animation_frame_name(name) {
+            animations
+                .entry(animation.to_string())
+                .or_default()
+                .push((index, name.to_string()));
+        }
+    }
+    animations
+        .into_iter()
+        .map(|(animation, mut frames)| {
+            frames.sort();
+            (
+                animation,
+                frames.into_iter().map(|(_, name)| name).collect(),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_are_grouped_by_naming_convention() {
+        let animations = group_animation_frames(vec![
+            "player/walk_10.png",
+            "player/walk_2.png",
+            "player/walk_0.png",
+            "button_hovered.png",
+            "spinner_0.png",
+        ]);
+        assert_eq!(animations.len(), 2);
+        assert_eq!(
+            animations["player/walk"],
+            vec![
+                "player/walk_0.png",
+                "player/walk_2.png",
+                "player/walk_10.png"
+            ]
+        );
+        assert_eq!(animations["spinner"], vec!["spinner_0.png"]);
+    }
+
+    #[test]
+    fn frame_is_chosen_from_elapsed_time() {
+        let sidecar = AnimationSidecar {
+            durations: vec![1.0, 2.0],
+            loop_mode: LoopMode::Loop,
+        };
+        let regions = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let mut animation = Animation::new(regions, Some(&sidecar));
+        assert_eq!(animation.duration(), 5.0);
+
+        let frames_at = |animation: &Animation, times: &[f64]| {
+            times
+                .iter()
+                .map(|time| animation.frame_index_at(*time).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let times = [0.5, 1.5, 3.5, 5.5, 7.0, 9.5];
+        assert_eq!(frames_at(&animation, &times), vec![0, 1, 2, 0, 1, 2]);
+
+        animation.loop_mode = LoopMode::Once;
+        assert_eq!(frames_at(&animation, &times), vec![0, 1, 2, 2, 2, 2]);
+
+        animation.loop_mode = LoopMode::PingPong;
+        assert_eq!(frames_at(&animation, &times), vec![0, 1, 2, 2, 1, 0]);
+    }
+}
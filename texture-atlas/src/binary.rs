@@ -0,0 +1,143 @@
+use std::fmt::{Display, Formatter};
+
+use crate::TextureAtlas;
+
+/// The first bytes of every binary atlas file, so that the loader can tell them apart from JSON atlases.
+pub const BINARY_ATLAS_MAGIC: &[u8; 4] = b"QSTA";
+/// The version of the binary atlas format written by this crate.
+/// This must be increased whenever the layout of `TextureAtlas` changes, since binary atlases aren't self-describing.
+pub const BINARY_ATLAS_VERSION: u16 = 2;
+
+/// An atlas file could not be read.
+#[derive(Debug)]
+pub enum AtlasDecodeError {
+    /// The atlas was JSON, but it was invalid.
+    Json(serde_json::Error),
+    /// The atlas was binary, but it was invalid.
+    Binary(bincode::Error),
+    /// The atlas was binary, but written in a different version of the format to the one we support.
+    /// It should be packed again.
+    UnsupportedVersion(u16),
+}
+
+impl Display for AtlasDecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AtlasDecodeError::Json(error) => write!(f, "invalid JSON atlas: {}", error),
+            AtlasDecodeError::Binary(error) => write!(f, "invalid binary atlas: {}", error),
+            AtlasDecodeError::UnsupportedVersion(version) => write!(
+                f,
+                "binary atlas has version {}, but only version {} is supported",
+                version, BINARY_ATLAS_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AtlasDecodeError {}
+
+impl TextureAtlas {
+    /// Encodes the atlas in the compact binary format.
+    /// This starts with `BINARY_ATLAS_MAGIC`, followed by `BINARY_ATLAS_VERSION` as a little-endian `u16`.
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut bytes = BINARY_ATLAS_MAGIC.to_vec();
+        bytes.extend_from_slice(&BINARY_ATLAS_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut bytes, self).expect("could not serialise atlas");
+        bytes
+    }
+
+    /// Decodes an atlas in the compact binary format, written by `to_binary`.
+    pub fn from_binary(bytes: &[u8]) -> Result<Self, AtlasDecodeError> {
+        let header_len = BINARY_ATLAS_MAGIC.len() + 2;
+        if bytes.len() < header_len || !bytes.starts_with(BINARY_ATLAS_MAGIC) {
+            return Err(AtlasDecodeError::Binary(Box::new(
+                bincode::ErrorKind::Custom("missing binary atlas header".to_string()),
+            )));
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != BINARY_ATLAS_VERSION {
+            return Err(AtlasDecodeError::UnsupportedVersion(version));
+        }
+        bincode::deserialize(&bytes[header_len..]).map_err(AtlasDecodeError::Binary)
+    }
+
+    /// Decodes an atlas in either the binary or the JSON format, working out which one it is from the first few bytes.
+    pub fn decode(bytes: &[u8]) -> Result<Self, AtlasDecodeError> {
+        if bytes.starts_with(BINARY_ATLAS_MAGIC) {
+            Self::from_binary(bytes)
+        } else {
+            serde_json::from_slice(bytes).map_err(AtlasDecodeError::Json)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Animation, Rect, TextureAtlasPage, TextureOptions, TextureRegionInformation};
+    use std::collections::HashMap;
+
+    fn atlas() -> TextureAtlas {
+        let mut frames = HashMap::new();
+        frames.insert(
+            "spinner_0.png".to_string(),
+            TextureRegionInformation {
+                frame: Rect {
+                    x: 2,
+                    y: 2,
+                    w: 16,
+                    h: 16,
+                },
+                rotated: false,
+                trimmed: false,
+                source: Rect {
+                    x: 0,
+                    y: 0,
+                    w: 16,
+                    h: 16,
+                },
+                nine_patch: None,
+                pivot: None,
+            },
+        );
+        let mut animations = HashMap::new();
+        animations.insert(
+            "spinner".to_string(),
+            Animation::new(vec!["spinner_0.png".to_string()], None),
+        );
+        TextureAtlas {
+            pages: vec![TextureAtlasPage {
+                image: "atlas_0.png".to_string(),
+                width: 32,
+                height: 32,
+                frames,
+            }],
+            animations,
+            texture_options: TextureOptions {
+                mipmaps: true,
+                premultiply_alpha: false,
+            },
+        }
+    }
+
+    #[test]
+    fn decodes_both_formats() {
+        let binary = TextureAtlas::decode(&atlas().to_binary()).unwrap();
+        assert_eq!(binary.pages, atlas().pages);
+        assert_eq!(binary.animations["spinner"].frames.len(), 1);
+        assert_eq!(binary.texture_options, atlas().texture_options);
+
+        let json = serde_json::to_vec(&atlas()).unwrap();
+        assert_eq!(TextureAtlas::decode(&json).unwrap().pages, atlas().pages);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut bytes = atlas().to_binary();
+        bytes[4] = bytes[4].wrapping_add(1);
+        assert!(matches!(
+            TextureAtlas::decode(&bytes),
+            Err(AtlasDecodeError::UnsupportedVersion(_))
+        ));
+    }
+}
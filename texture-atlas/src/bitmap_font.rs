@@ -0,0 +1,524 @@
+//! Reading bitmap fonts in the text and XML formats written by AngelCode's BMFont and compatible tools,
+//! whose glyphs are stored as images on one or more pages.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use crate::{Rect, TextureAtlas, TextureAtlasPage, TextureOptions, TextureRegionInformation};
+
+/// A font whose glyphs are images, along with the metrics needed to lay them out.
+/// All measurements are in pixels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BitmapFont {
+    /// The name of the font.
+    pub face: String,
+    /// The size of the font, normally the height of the em square.
+    pub size: u32,
+    /// The distance between the tops of two consecutive lines of text.
+    pub line_height: u32,
+    /// The distance from the top of a line of text to the baseline.
+    pub base: u32,
+    /// The width and height of every page image.
+    pub page_size: (u32, u32),
+    /// The file names of the page images, relative to the font file.
+    pub pages: Vec<String>,
+    pub glyphs: HashMap<char, BitmapGlyph>,
+    /// How much to move the second character of each pair horizontally, in addition to the first character's advance.
+    pub kernings: HashMap<(char, char), i32>,
+    /// If the pages store a signed distance field instead of the glyphs themselves, the difference in distance,
+    /// in pixels, between a fully transparent and a fully opaque pixel. The glyph's outline is halfway between.
+    /// Such fonts can be drawn crisply at any size.
+    pub distance_range: Option<u32>,
+}
+
+/// Where a single glyph is stored in a bitmap font, and how to position it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BitmapGlyph {
+    /// Where the glyph's image is on its page.
+    pub frame: Rect,
+    /// The index of the page containing the glyph's image, in `BitmapFont::pages`.
+    pub page: usize,
+    /// How far right of the caret the left edge of the image is drawn.
+    pub x_offset: i32,
+    /// How far below the top of the line the top edge of the image is drawn.
+    pub y_offset: i32,
+    /// How far to move the caret after drawing this glyph.
+    pub x_advance: i32,
+}
+
+/// A problem found while reading a bitmap font.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BitmapFontError {
+    /// A required line or element, such as `common`, is missing.
+    MissingTag(&'static str),
+    /// A line or element is missing a required attribute.
+    MissingAttribute {
+        tag: String,
+        attribute: &'static str,
+    },
+    /// An attribute's value couldn't be parsed.
+    InvalidAttribute {
+        tag: String,
+        attribute: &'static str,
+        value: String,
+    },
+}
+
+impl Display for BitmapFontError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BitmapFontError::MissingTag(tag) => write!(f, "font has no {} tag", tag),
+            BitmapFontError::MissingAttribute { tag, attribute } => {
+                write!(f, "{} tag is missing attribute {}", tag, attribute)
+            }
+            BitmapFontError::InvalidAttribute {
+                tag,
+                attribute,
+                value,
+            } => write!(
+                f,
+                "attribute {} of {} tag has invalid value {:?}",
+                attribute, tag, value
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BitmapFontError {}
+
+/// A line of the text format, or an element of the XML format: a name followed by `key=value` attributes.
+struct Tag {
+    name: String,
+    attributes: HashMap<String, String>,
+}
+
+impl Tag {
+    fn parse(source: &str) -> Option<Self> {
+        let mut chars = source.trim().chars().peekable();
+        let name = take_until(&mut chars, |c| c.is_whitespace());
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut attributes = HashMap::new();
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            let key = take_until(&mut chars, |c| c == '=' || c.is_whitespace());
+            if key.is_empty() {
+                break;
+            }
+            let value = if chars.peek() == Some(&'=') {
+                chars.next();
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    let value = take_until(&mut chars, |c| c == '"');
+                    chars.next();
+                    value
+                } else {
+                    take_until(&mut chars, |c| c.is_whitespace())
+                }
+            } else {
+                String::new()
+            };
+            attributes.insert(key, value);
+        }
+        Some(Self { name, attributes })
+    }
+
+    fn get<T: FromStr>(&self, attribute: &'static str) -> Result<T, BitmapFontError> {
+        match self.attributes.get(attribute) {
+            Some(value) => self.parse_value(attribute, value),
+            None => Err(BitmapFontError::MissingAttribute {
+                tag: self.name.clone(),
+                attribute,
+            }),
+        }
+    }
+
+    fn get_or<T: FromStr>(
+        &self,
+        attribute: &'static str,
+        default: T,
+    ) -> Result<T, BitmapFontError> {
+        match self.attributes.get(attribute) {
+            Some(value) => self.parse_value(attribute, value),
+            None => Ok(default),
+        }
+    }
+
+    fn get_char(&self, attribute: &'static str) -> Result<char, BitmapFontError> {
+        let id: u32 = self.get(attribute)?;
+        std::char::from_u32(id).ok_or_else(|| BitmapFontError::InvalidAttribute {
+            tag: self.name.clone(),
+            attribute,
+            value: id.to_string(),
+        })
+    }
+
+    fn parse_value<T: FromStr>(
+        &self,
+        attribute: &'static str,
+        value: &str,
+    ) -> Result<T, BitmapFontError> {
+        value
+            .parse()
+            .map_err(|_| BitmapFontError::InvalidAttribute {
+                tag: self.name.clone(),
+                attribute,
+                value: value.to_string(),
+            })
+    }
+}
+
+fn take_until(
+    chars: &mut std::iter::Peekable<impl Iterator<Item = char>>,
+    end: impl Fn(char) -> bool,
+) -> String {
+    let mut result = String::new();
+    while let Some(c) = chars.peek() {
+        if end(*c) {
+            break;
+        }
+        result.push(*c);
+        chars.next();
+    }
+    result
+}
+
+/// Splits the XML format into its elements, ignoring closing tags, comments and the XML declaration.
+fn xml_tags(source: &str) -> Vec<Tag> {
+    source
+        .split('<')
+        .filter_map(|element| {
+            let element = &element[..element.find('>')?];
+            if element.starts_with(&['?', '!', '/'][..]) {
+                return None;
+            }
+            let mut tag = Tag::parse(element.trim_end_matches('/'))?;
+            for value in tag.attributes.values_mut() {
+                *value = value
+                    .replace("&quot;", "\"")
+                    .replace("&apos;", "'")
+                    .replace("&lt;", "<")
+                    .replace("&gt;", ">")
+                    .replace("&amp;", "&");
+            }
+            Some(tag)
+        })
+        .collect()
+}
+
+impl BitmapFont {
+    /// Reads a font in either the text or XML format. The binary format is not supported.
+    pub fn parse(source: &str) -> Result<Self, BitmapFontError> {
+        let tags = if source.trim_start().starts_with('<') {
+            xml_tags(source)
+        } else {
+            source.lines().filter_map(Tag::parse).collect()
+        };
+        let find = |name: &'static str| {
+            tags.iter()
+                .find(|tag| tag.name == name)
+                .ok_or(BitmapFontError::MissingTag(name))
+        };
+
+        let info = find("info")?;
+        let common = find("common")?;
+        let mut font = Self {
+            face: info.get_or("face", String::new())?,
+            // A negative size means that the size matches the height of the characters rather than the em square.
+            size: info.get::<i32>("size")?.unsigned_abs(),
+            line_height: common.get("lineHeight")?,
+            base: common.get("base")?,
+            page_size: (common.get("scaleW")?, common.get("scaleH")?),
+            pages: Vec::new(),
+            glyphs: HashMap::new(),
+            kernings: HashMap::new(),
+            distance_range: None,
+        };
+
+        // Distance field fonts have an extra tag, which only some tools write.
+        if let Some(tag) = tags.iter().find(|tag| tag.name == "distanceField") {
+            let field_type: String = tag.get("fieldType")?;
+            // Multi-channel distance fields need a different shader, so they aren't supported.
+            if field_type != "sdf" {
+                return Err(BitmapFontError::InvalidAttribute {
+                    tag: tag.name.clone(),
+                    attribute: "fieldType",
+                    value: field_type,
+                });
+            }
+            font.distance_range = Some(tag.get("distanceRange")?);
+        }
+
+        // Page ids needn't start at zero or be consecutive, so pages are numbered in order of their ids instead.
+        let mut pages = Vec::new();
+        for tag in tags.iter().filter(|tag| tag.name == "page") {
+            pages.push((tag.get::<usize>("id")?, tag.get::<String>("file")?));
+        }
+        pages.sort();
+        let page_indices = pages
+            .iter()
+            .enumerate()
+            .map(|(index, (id, _))| (*id, index))
+            .collect::<HashMap<_, _>>();
+
+        for tag in &tags {
+            match tag.name.as_str() {
+                "char" => {
+                    let page = tag.get_or("page", 0)?;
+                    let page = *page_indices.get(&page).ok_or_else(|| {
+                        BitmapFontError::InvalidAttribute {
+                            tag: tag.name.clone(),
+                            attribute: "page",
+                            value: page.to_string(),
+                        }
+                    })?;
+                    font.glyphs.insert(
+                        tag.get_char("id")?,
+                        BitmapGlyph {
+                            frame: Rect {
+                                x: tag.get("x")?,
+                                y: tag.get("y")?,
+                                w: tag.get("width")?,
+                                h: tag.get("height")?,
+                            },
+                            page,
+                            x_offset: tag.get_or("xoffset", 0)?,
+                            y_offset: tag.get_or("yoffset", 0)?,
+                            x_advance: tag.get("xadvance")?,
+                        },
+                    );
+                }
+                "kerning" => {
+                    font.kernings.insert(
+                        (tag.get_char("first")?, tag.get_char("second")?),
+                        tag.get("amount")?,
+                    );
+                }
+                _ => {}
+            }
+        }
+        font.pages = pages.into_iter().map(|(_, file)| file).collect();
+        Ok(font)
+    }
+
+    /// Writes the font in the text format, which `parse` can read back.
+    /// Characters and kerning pairs are sorted, so the same font is always written the same way.
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![
+            format!("info face=\"{}\" size={} unicode=1", self.face, self.size),
+            format!(
+                "common lineHeight={} base={} scaleW={} scaleH={} pages={}",
+                self.line_height,
+                self.base,
+                self.page_size.0,
+                self.page_size.1,
+                self.pages.len()
+            ),
+        ];
+        for (id, file) in self.pages.iter().enumerate() {
+            lines.push(format!("page id={} file=\"{}\"", id, file));
+        }
+        if let Some(distance_range) = self.distance_range {
+            lines.push(format!(
+                "distanceField fieldType=sdf distanceRange={}",
+                distance_range
+            ));
+        }
+
+        let mut glyphs = self.glyphs.iter().collect::<Vec<_>>();
+        glyphs.sort_by_key(|(c, _)| **c);
+        lines.push(format!("chars count={}", glyphs.len()));
+        for (c, glyph) in glyphs {
+            lines.push(format!(
+                "char id={} x={} y={} width={} height={} xoffset={} yoffset={} xadvance={} page={} chnl=15",
+                *c as u32,
+                glyph.frame.x,
+                glyph.frame.y,
+                glyph.frame.w,
+                glyph.frame.h,
+                glyph.x_offset,
+                glyph.y_offset,
+                glyph.x_advance,
+                glyph.page
+            ));
+        }
+
+        let mut kernings = self.kernings.iter().collect::<Vec<_>>();
+        kernings.sort();
+        lines.push(format!("kernings count={}", kernings.len()));
+        for ((first, second), amount) in kernings {
+            lines.push(format!(
+                "kerning first={} second={} amount={}",
+                *first as u32, *second as u32, amount
+            ));
+        }
+
+        lines.push(String::new());
+        lines.join("\n")
+    }
+
+    /// The name of the region containing the given character's glyph in the atlas returned by `to_atlas`.
+    pub fn glyph_region_name(c: char) -> String {
+        format!("U+{:04X}", c as u32)
+    }
+
+    /// How much to move the second character horizontally when it follows the first, in addition to the first character's advance.
+    pub fn kerning(&self, first: char, second: char) -> i32 {
+        self.kernings.get(&(first, second)).copied().unwrap_or(0)
+    }
+
+    /// Describes the font's pages as a texture atlas, with a region for each glyph named by `glyph_region_name`.
+    /// Glyphs with no image, such as spaces, have no region.
+    /// Distance field fonts are drawn at many sizes, so their pages are mipmapped.
+    pub fn to_atlas(&self) -> TextureAtlas {
+        let mut pages = self
+            .pages
+            .iter()
+            .map(|image| TextureAtlasPage {
+                image: image.clone(),
+                width: self.page_size.0,
+                height: self.page_size.1,
+                frames: HashMap::new(),
+            })
+            .collect::<Vec<_>>();
+        for (c, glyph) in &self.glyphs {
+            if let (Some(info), Some(page)) = (glyph.region_info(), pages.get_mut(glyph.page)) {
+                page.frames.insert(Self::glyph_region_name(*c), info);
+            }
+        }
+        TextureAtlas {
+            pages,
+            animations: HashMap::new(),
+            texture_options: TextureOptions {
+                mipmaps: self.distance_range.is_some(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl BitmapGlyph {
+    /// Describes where the glyph's image is on its page, or returns `None` if the glyph has no image.
+    pub fn region_info(&self) -> Option<TextureRegionInformation> {
+        if self.frame.w == 0 || self.frame.h == 0 {
+            return None;
+        }
+        Some(TextureRegionInformation {
+            frame: self.frame,
+            rotated: false,
+            trimmed: false,
+            source: Rect {
+                x: 0,
+                y: 0,
+                w: self.frame.w,
+                h: self.frame.h,
+            },
+            nine_patch: None,
+            pivot: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = r#"info face="Pixel Sans" size=-8 bold=0 italic=0 charset="" unicode=1 padding=0,0,0,0 spacing=1,1
+common lineHeight=10 base=8 scaleW=64 scaleH=32 pages=1 packed=0
+page id=0 file="pixel_sans_0.png"
+chars count=2
+char id=32   x=0     y=0     width=0     height=0     xoffset=0     yoffset=0     xadvance=3     page=0  chnl=15
+char id=65   x=1     y=1     width=5     height=7     xoffset=0     yoffset=1     xadvance=6     page=0  chnl=15
+kernings count=1
+kerning first=65  second=65  amount=-1
+"#;
+
+    const XML: &str = r#"<?xml version="1.0"?>
+<font>
+  <info face="Pixel Sans" size="-8" bold="0" italic="0" charset="" unicode="1" padding="0,0,0,0" spacing="1,1"/>
+  <common lineHeight="10" base="8" scaleW="64" scaleH="32" pages="1" packed="0"/>
+  <pages>
+    <page id="0" file="pixel_sans_0.png" />
+  </pages>
+  <chars count="2">
+    <char id="32" x="0" y="0" width="0" height="0" xoffset="0" yoffset="0" xadvance="3" page="0" chnl="15" />
+    <char id="65" x="1" y="1" width="5" height="7" xoffset="0" yoffset="1" xadvance="6" page="0" chnl="15" />
+  </chars>
+  <!-- Kerning pairs -->
+  <kernings count="1">
+    <kerning first="65" second="65" amount="-1" />
+  </kernings>
+</font>
+"#;
+
+    #[test]
+    fn text_and_xml_formats_are_read() {
+        let font = BitmapFont::parse(TEXT).unwrap();
+        assert_eq!(BitmapFont::parse(XML).unwrap(), font);
+
+        assert_eq!(font.face, "Pixel Sans");
+        assert_eq!(font.size, 8);
+        assert_eq!((font.line_height, font.base), (10, 8));
+        assert_eq!(font.pages, vec!["pixel_sans_0.png"]);
+        assert_eq!(font.glyphs[&'A'].x_advance, 6);
+        assert_eq!(font.kerning('A', 'A'), -1);
+        assert_eq!(font.kerning('A', ' '), 0);
+
+        // The space has no image, so only the `A` becomes a region.
+        let atlas = font.to_atlas();
+        assert_eq!(atlas.validate(), Ok(()));
+        assert_eq!(atlas.pages[0].frames.len(), 1);
+        assert_eq!(
+            atlas.find("U+0041").unwrap().1.frame,
+            font.glyphs[&'A'].frame
+        );
+
+        assert_eq!(
+            BitmapFont::parse("info size=8"),
+            Err(BitmapFontError::MissingTag("common"))
+        );
+    }
+
+    #[test]
+    fn glyph_pages_must_exist() {
+        // Page ids are numbered in order, so the only page is page 0 even if its id is 1.
+        let sparse = TEXT
+            .replace("page id=0", "page id=1")
+            .replace("page=0", "page=1");
+        let font = BitmapFont::parse(&sparse).unwrap();
+        assert_eq!(font.glyphs[&'A'].page, 0);
+        assert_eq!(font.to_atlas().pages[0].frames.len(), 1);
+
+        let missing = TEXT.replacen("page=0", "page=2", 1);
+        assert_eq!(
+            BitmapFont::parse(&missing),
+            Err(BitmapFontError::InvalidAttribute {
+                tag: "char".to_string(),
+                attribute: "page",
+                value: "2".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn written_fonts_are_read_back() {
+        let mut font = BitmapFont::parse(TEXT).unwrap();
+        assert_eq!(font.distance_range, None);
+        assert_eq!(BitmapFont::parse(&font.to_text()).unwrap(), font);
+
+        font.distance_range = Some(8);
+        let text = font.to_text();
+        assert!(text.contains("distanceField fieldType=sdf distanceRange=8"));
+        assert_eq!(BitmapFont::parse(&text).unwrap(), font);
+        assert!(font.to_atlas().texture_options.mipmaps);
+
+        let msdf = text.replace("fieldType=sdf", "fieldType=msdf");
+        assert!(matches!(
+            BitmapFont::parse(&msdf),
+            Err(BitmapFontError::InvalidAttribute { .. })
+        ));
+    }
+}
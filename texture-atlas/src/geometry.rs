@@ -0,0 +1,203 @@
+use crate::{Rect, TextureAtlas, TextureRegionInformation};
+
+/// The four corners of a quad, in the order top-left, top-right, bottom-right, bottom-left.
+/// Positions and texture coordinates are both given in this order, so they can be zipped together.
+pub type Corners = [[f32; 2]; 4];
+
+/// A rectangle of texture coordinates, normalised so that the whole texture spans 0 to 1 in each direction.
+/// `(u0, v0)` is the top-left corner, and `(u1, v1)` is the bottom-right corner.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct UvRect {
+    pub u0: f32,
+    pub v0: f32,
+    pub u1: f32,
+    pub v1: f32,
+}
+
+impl UvRect {
+    /// The whole texture.
+    pub const FULL: UvRect = UvRect {
+        u0: 0.0,
+        v0: 0.0,
+        u1: 1.0,
+        v1: 1.0,
+    };
+
+    /// Linearly interpolates inside this rectangle, where `(0, 0)` is the top-left and `(1, 1)` is the bottom-right.
+    pub fn lerp(&self, x: f32, y: f32) -> [f32; 2] {
+        [
+            self.u0 + x * (self.u1 - self.u0),
+            self.v0 + y * (self.v1 - self.v0),
+        ]
+    }
+
+    /// A smaller rectangle inside this one, where the edges are given as fractions of this rectangle's size.
+    pub fn sub_rect(&self, left: f32, top: f32, right: f32, bottom: f32) -> UvRect {
+        let [u0, v0] = self.lerp(left, top);
+        let [u1, v1] = self.lerp(right, bottom);
+        UvRect { u0, v0, u1, v1 }
+    }
+
+    /// The corners of this rectangle, in the usual order.
+    pub fn corners(&self) -> Corners {
+        [
+            [self.u0, self.v0],
+            [self.u1, self.v0],
+            [self.u1, self.v1],
+            [self.u0, self.v1],
+        ]
+    }
+}
+
+impl Rect {
+    /// The texture coordinates of this rectangle of pixels, inside a texture of the given size.
+    pub fn uv_rect(&self, texture_size: (u32, u32)) -> UvRect {
+        let (w, h) = (texture_size.0 as f32, texture_size.1 as f32);
+        UvRect {
+            u0: self.x as f32 / w,
+            v0: self.y as f32 / h,
+            u1: (self.x + self.w) as f32 / w,
+            v1: (self.y + self.h) as f32 / h,
+        }
+    }
+}
+
+impl TextureRegionInformation {
+    /// The size of the image before it was packed, including any transparent space that was trimmed away.
+    pub fn source_size(&self) -> (u32, u32) {
+        if self.trimmed {
+            (self.source.w, self.source.h)
+        } else {
+            self.unrotated_size()
+        }
+    }
+
+    /// The size of the trimmed image, before it was rotated during packing.
+    pub(crate) fn unrotated_size(&self) -> (u32, u32) {
+        if self.rotated {
+            (self.frame.h, self.frame.w)
+        } else {
+            (self.frame.w, self.frame.h)
+        }
+    }
+
+    /// The part of the original image that was kept after trimming, in pixels from the top-left of the original image.
+    pub fn trimmed_rect(&self) -> Rect {
+        let (x, y) = if self.trimmed {
+            (self.source.x, self.source.y)
+        } else {
+            (0, 0)
+        };
+        let (w, h) = self.unrotated_size();
+        Rect { x, y, w, h }
+    }
+
+    /// Converts a position in the original image, in pixels from its top-left corner, into texture coordinates
+    /// in a texture of the given size. The position should be inside the `trimmed_rect`.
+    pub fn uv_at(&self, x: f32, y: f32, texture_size: (u32, u32)) -> [f32; 2] {
+        let trimmed = self.trimmed_rect();
+        let (x, y) = (x - trimmed.x as f32, y - trimmed.y as f32);
+        let frame = self.frame;
+        // If the image was rotated 90 degrees clockwise, its left edge became the top edge of the frame.
+        let (u, v) = if self.rotated {
+            (frame.x as f32 + frame.w as f32 - y, frame.y as f32 + x)
+        } else {
+            (frame.x as f32 + x, frame.y as f32 + y)
+        };
+        [u / texture_size.0 as f32, v / texture_size.1 as f32]
+    }
+
+    /// The texture coordinates of the corners of a rectangle in the original image, given in pixels from its top-left corner.
+    /// The corners are ordered as they were in the original image, so rotated regions are drawn the right way up.
+    /// The rectangle should be inside the `trimmed_rect`.
+    pub fn sub_region_uvs(&self, rect: Rect, texture_size: (u32, u32)) -> Corners {
+        let (left, top) = (rect.x as f32, rect.y as f32);
+        let (right, bottom) = ((rect.x + rect.w) as f32, (rect.y + rect.h) as f32);
+        [
+            self.uv_at(left, top, texture_size),
+            self.uv_at(right, top, texture_size),
+            self.uv_at(right, bottom, texture_size),
+            self.uv_at(left, bottom, texture_size),
+        ]
+    }
+
+    /// The texture coordinates of the corners of the trimmed image, ordered as they were in the original image.
+    pub fn corner_uvs(&self, texture_size: (u32, u32)) -> Corners {
+        self.sub_region_uvs(self.trimmed_rect(), texture_size)
+    }
+}
+
+impl TextureAtlas {
+    /// The texture coordinates of the corners of the named region, ordered as they were in the original image,
+    /// and the index of the page the region is on.
+    pub fn corner_uvs(&self, name: &str) -> Option<(usize, Corners)> {
+        let (page, info) = self.find(name)?;
+        let page_size = (self.pages[page].width, self.pages[page].height);
+        Some((page, info.corner_uvs(page_size)))
+    }
+}
+
+/// The corners of the rectangle with the given position and size, moved to the nearest pixel boundaries
+/// so that sprites drawn with it aren't blurred. `pixels_per_unit` is the number of screen pixels per unit of position.
+pub fn pixel_snapped_quad(
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    pixels_per_unit: f32,
+) -> Corners {
+    let snap = |value: f32| (value * pixels_per_unit).round() / pixels_per_unit;
+    let (left, top, right, bottom) = (snap(x), snap(y), snap(x + width), snap(y + height));
+    [[left, top], [right, top], [right, bottom], [left, bottom]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotated_trimmed_corners_keep_their_orientation() {
+        // A 10x8 image, trimmed to the 6x4 area at (2, 1), then rotated into a 4x6 frame.
+        let info = TextureRegionInformation {
+            frame: Rect {
+                x: 20,
+                y: 30,
+                w: 4,
+                h: 6,
+            },
+            rotated: true,
+            trimmed: true,
+            source: Rect {
+                x: 2,
+                y: 1,
+                w: 10,
+                h: 8,
+            },
+            nine_patch: None,
+            pivot: None,
+        };
+        assert_eq!(info.source_size(), (10, 8));
+        // The top-left of the image ends up in the top-right of the frame, and so on clockwise.
+        assert_eq!(
+            info.corner_uvs((100, 100)),
+            [[0.24, 0.30], [0.24, 0.36], [0.20, 0.36], [0.20, 0.30]]
+        );
+    }
+
+    #[test]
+    fn quads_are_snapped_to_pixels() {
+        assert_eq!(
+            pixel_snapped_quad(0.2, 1.7, 2.0, 3.0, 2.0),
+            [[0.0, 1.5], [2.0, 1.5], [2.0, 4.5], [0.0, 4.5]]
+        );
+        assert_eq!(
+            UvRect::FULL.sub_rect(0.25, 0.5, 0.75, 1.0),
+            UvRect {
+                u0: 0.25,
+                v0: 0.5,
+                u1: 0.75,
+                v1: 1.0
+            }
+        );
+    }
+}
@@ -1,9 +1,56 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-/// Represents some metadata about sprites packed into a single image, which can be referred to by their (relative) file names.
+mod animation;
+pub use animation::*;
+mod binary;
+pub use binary::*;
+mod bitmap_font;
+pub use bitmap_font::*;
+mod geometry;
+pub use geometry::*;
+mod nine_patch;
+pub use nine_patch::*;
+mod pack_config;
+pub use pack_config::*;
+mod packer;
+pub use packer::*;
+mod texture_options;
+pub use texture_options::*;
+mod texture_packer_format;
+pub use texture_packer_format::*;
+mod validation;
+pub use validation::*;
+
+/// Represents some metadata about sprites packed into one or more images, which can be referred to by their (relative) file names.
+/// If the sprites don't all fit in a single image, they are spread across several pages.
 #[derive(Serialize, Deserialize)]
 pub struct TextureAtlas {
+    /// The pages of the atlas. Each sprite is contained within exactly one page.
+    pub pages: Vec<TextureAtlasPage>,
+    /// Animations made up of sprites in this atlas, addressable by name.
+    #[serde(default)]
+    pub animations: HashMap<String, Animation>,
+    /// How the page images should be processed when they are loaded.
+    #[serde(default)]
+    pub texture_options: TextureOptions,
+}
+
+impl TextureAtlas {
+    /// Finds the region with the given name, and the index of the page it is on.
+    pub fn find(&self, name: &str) -> Option<(usize, &TextureRegionInformation)> {
+        self.pages
+            .iter()
+            .enumerate()
+            .find_map(|(i, page)| page.frames.get(name).map(|info| (i, info)))
+    }
+}
+
+/// A single image in a texture atlas, and the sprites packed into it.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct TextureAtlasPage {
+    /// The file name of the backing texture, relative to the atlas file.
+    pub image: String,
     /// The width of the backing texture.
     pub width: u32,
     /// The height of the backing texture.
@@ -14,7 +61,7 @@ pub struct TextureAtlas {
 }
 
 /// Roughly corresponds to [texture_packer::Frame].
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TextureRegionInformation {
     /// Rectangle describing the texture coordinates and size.
     pub frame: Rect,
@@ -40,11 +87,25 @@ pub struct TextureRegionInformation {
     //     +--------------+
     /// Source texture size before any trimming.
     pub source: Rect,
+    /// If this region is a nine-patch, the sizes of its borders in the source texture.
+    #[serde(default)]
+    pub nine_patch: Option<NinePatchMargins>,
+    /// The point that the sprite is positioned around, if one was given when it was packed.
+    #[serde(default)]
+    pub pivot: Option<Pivot>,
+}
+
+/// A point inside a sprite, given as fractions of the width and height of the source texture from its top-left corner.
+/// For example, the centre of the sprite is `(0.5, 0.5)`.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pivot {
+    pub x: f32,
+    pub y: f32,
 }
 
 /// Copied from the `texture_packer` crate.
 /// Defines a rectangle in pixels with the origin at the top-left of the texture atlas.
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Rect {
     /// Horizontal position the rectangle begins at.
     pub x: u32,
@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+
+/// The sizes of the borders of a nine-patch image, in pixels. The borders keep their size when the image is stretched,
+/// and the rest of the image is stretched to fill the remaining space.
+///
+/// This is either read from the markers in the border of a `.9.png` image, or from a sidecar file `<name>.9.json`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NinePatchMargins {
+    pub left: u32,
+    pub right: u32,
+    pub top: u32,
+    pub bottom: u32,
+}
+
+impl NinePatchMargins {
+    /// Reads the margins from an Android-style `.9.png` image, which has an extra 1-pixel border around the image.
+    /// Marker pixels in the top row show which columns stretch, and marker pixels in the left column show which rows stretch.
+    /// The margins are given in terms of the image without this border.
+    ///
+    /// `width` and `height` are the size of the image including the border, and `is_marker` tells us whether the pixel
+    /// at the given position is a marker (normally opaque black). Returns `None` if either the top row or the left column has no markers.
+    pub fn from_border_markers(
+        width: u32,
+        height: u32,
+        is_marker: impl Fn(u32, u32) -> bool,
+    ) -> Option<Self> {
+        if width < 3 || height < 3 {
+            return None;
+        }
+        let first_and_last = |positions: std::ops::Range<u32>, is_marker: &dyn Fn(u32) -> bool| {
+            let mut markers = positions.filter(|i| is_marker(*i));
+            let first = markers.next()?;
+            Some((first, markers.next_back().unwrap_or(first)))
+        };
+        let (first_x, last_x) = first_and_last(1..width - 1, &|x| is_marker(x, 0))?;
+        let (first_y, last_y) = first_and_last(1..height - 1, &|y| is_marker(0, y))?;
+        Some(Self {
+            left: first_x - 1,
+            right: width - 2 - last_x,
+            top: first_y - 1,
+            bottom: height - 2 - last_y,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn margins_are_read_from_border_markers() {
+        // A 6x5 image with a border, where columns 2..=3 and row 3 of the inner image stretch.
+        let markers = [
+            ". . . X X . . .",
+            ". . . . . . . .",
+            ". . . . . . . .",
+            ". . . . . . . .",
+            "X . . . . . . .",
+            ". . . . . . . .",
+            ". . . . . . . .",
+        ];
+        let is_marker = |x: u32, y: u32| markers[y as usize].as_bytes()[2 * x as usize] == b'X';
+        assert_eq!(
+            NinePatchMargins::from_border_markers(8, 7, is_marker),
+            Some(NinePatchMargins {
+                left: 2,
+                right: 2,
+                top: 3,
+                bottom: 1,
+            })
+        );
+        assert_eq!(
+            NinePatchMargins::from_border_markers(8, 7, |_, _| false),
+            None
+        );
+    }
+}
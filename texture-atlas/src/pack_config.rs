@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+
+use crate::TextureOptions;
+
+/// Settings for packing a directory of images into an atlas. The build script reads these from an optional `pack.json`
+/// file in the directory, and any settings missing from the file keep their default values.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PackConfig {
+    /// The largest width of a page, in pixels.
+    pub max_page_width: u32,
+    /// The largest height of a page, in pixels.
+    pub max_page_height: u32,
+    /// How many transparent pixels to leave between neighbouring images, and between each image and the edge of its page,
+    /// so that they don't bleed into each other when sampled.
+    pub padding: u32,
+    /// How many pixels to copy the edges of each image outwards into its padding, so that filtering at the edge of an image
+    /// samples copies of its own pixels instead of transparency. The padding is increased to fit the copied pixels if necessary.
+    pub extrude: u32,
+    /// Whether images may be rotated by 90 degrees to fit more of them onto a page.
+    pub allow_rotation: bool,
+    /// Whether transparent space around the edges of each image is trimmed away before packing.
+    pub trim: bool,
+    /// How the pages of the atlas should be processed when the game loads them.
+    pub texture_options: TextureOptions,
+}
+
+impl Default for PackConfig {
+    fn default() -> Self {
+        Self {
+            max_page_width: 512,
+            max_page_height: 512,
+            padding: 2,
+            extrude: 0,
+            allow_rotation: true,
+            trim: true,
+            texture_options: TextureOptions::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_settings_are_defaulted() {
+        let config: PackConfig =
+            serde_json::from_str(r#"{ "max_page_width": 1024, "allow_rotation": false }"#).unwrap();
+        assert_eq!(
+            config,
+            PackConfig {
+                max_page_width: 1024,
+                allow_rotation: false,
+                ..Default::default()
+            }
+        );
+    }
+}
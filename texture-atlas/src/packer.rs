@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+
+use crate::{Rect, TextureAtlasPage, TextureRegionInformation};
+
+/// Packs images into a fixed-size texture while the game is running, so that images loaded at runtime
+/// can be rendered from the same texture as other sprites.
+///
+/// Images are placed on horizontal shelves. Each shelf is as tall as the first image placed on it,
+/// and later images go on the shortest shelf that they fit on, so images of similar heights end up together.
+/// Removing an image frees its space on the shelf for later images.
+pub struct DynamicTextureAtlas {
+    /// The width of the backing texture.
+    width: u32,
+    /// The height of the backing texture.
+    height: u32,
+    /// How many pixels of empty space to leave between images, and between images and the edge of the texture.
+    padding: u32,
+    /// Images are never placed above this height, for example because it is already used by a prebuilt atlas.
+    reserved_height: u32,
+
+    /// The shelves, ordered from top to bottom.
+    shelves: Vec<Shelf>,
+    frames: HashMap<String, TextureRegionInformation>,
+}
+
+struct Shelf {
+    /// The vertical position of the top of this shelf.
+    y: u32,
+    /// The height of the shelf, including padding.
+    height: u32,
+    /// The horizontal spans `(x, w)` of this shelf with nothing in them, sorted by `x`.
+    /// Adjacent free spans are always merged.
+    free: Vec<(u32, u32)>,
+}
+
+impl Shelf {
+    fn new(y: u32, height: u32, atlas_width: u32, padding: u32) -> Self {
+        Self {
+            y,
+            height,
+            free: vec![(padding, atlas_width.saturating_sub(padding))],
+        }
+    }
+
+    /// Finds the narrowest free span that can contain `w` pixels, returning its index.
+    fn best_span(&self, w: u32) -> Option<usize> {
+        self.free
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, span_w))| *span_w >= w)
+            .min_by_key(|(_, (_, span_w))| *span_w)
+            .map(|(i, _)| i)
+    }
+
+    /// Takes `w` pixels from the start of the given free span, returning their horizontal position.
+    fn allocate(&mut self, span: usize, w: u32) -> u32 {
+        let (x, span_w) = self.free[span];
+        if span_w == w {
+            self.free.remove(span);
+        } else {
+            self.free[span] = (x + w, span_w - w);
+        }
+        x
+    }
+
+    /// Returns the span `(x, w)` to the list of free spans, merging it with its neighbours.
+    fn deallocate(&mut self, x: u32, w: u32) {
+        let i = self.free.partition_point(|(free_x, _)| *free_x < x);
+        self.free.insert(i, (x, w));
+        if i + 1 < self.free.len() && x + w == self.free[i + 1].0 {
+            self.free[i].1 += self.free.remove(i + 1).1;
+        }
+        if i > 0 && self.free[i - 1].0 + self.free[i - 1].1 == x {
+            self.free[i - 1].1 += self.free.remove(i).1;
+        }
+    }
+
+    fn is_empty(&self, atlas_width: u32, padding: u32) -> bool {
+        self.free == [(padding, atlas_width.saturating_sub(padding))]
+    }
+}
+
+impl DynamicTextureAtlas {
+    /// Creates an empty atlas for a texture of the given size.
+    pub fn new(width: u32, height: u32, padding: u32) -> Self {
+        Self {
+            width,
+            height,
+            padding,
+            reserved_height: 0,
+            shelves: Vec::new(),
+            frames: HashMap::new(),
+        }
+    }
+
+    /// Creates an atlas for a texture of the given size, whose top-left corner contains the texture described by `page`.
+    /// The regions from `page` are kept, and new images are packed underneath them.
+    ///
+    /// Removing a region from `page` will not free up its space.
+    pub fn from_page(page: TextureAtlasPage, width: u32, height: u32, padding: u32) -> Self {
+        Self {
+            width,
+            height,
+            padding,
+            reserved_height: page.height,
+            shelves: Vec::new(),
+            frames: page.frames,
+        }
+    }
+
+    /// The width of the backing texture.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// The height of the backing texture.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Finds space for an image of the given size and adds it to the atlas under the given name.
+    /// If there was already an image with this name, it is removed first.
+    /// Returns `None` if there was not enough space.
+    pub fn insert(&mut self, name: String, w: u32, h: u32) -> Option<TextureRegionInformation> {
+        self.remove(&name);
+
+        let slot_w = w + self.padding;
+        let slot_h = h + self.padding;
+
+        // Prefer shelves that aren't much taller than the image, so that we don't waste space.
+        // If none of those have room, try to make a new shelf, and only then fall back to any shelf that fits.
+        let (shelf, span) = match self.find_shelf(slot_w, slot_h, slot_h * 2) {
+            Some(found) => found,
+            None => match self.push_shelf(slot_h) {
+                Some(shelf) => match self.shelves[shelf].best_span(slot_w) {
+                    Some(span) => (shelf, span),
+                    None => {
+                        // The image is too wide for the atlas, so don't keep the empty shelf made for it.
+                        self.shelves.pop();
+                        return None;
+                    }
+                },
+                None => self.find_shelf(slot_w, slot_h, u32::MAX)?,
+            },
+        };
+
+        let y = self.shelves[shelf].y;
+        let x = self.shelves[shelf].allocate(span, slot_w);
+        let info = TextureRegionInformation {
+            frame: Rect { x, y, w, h },
+            rotated: false,
+            trimmed: false,
+            source: Rect { x: 0, y: 0, w, h },
+            nine_patch: None,
+            pivot: None,
+        };
+        self.frames.insert(name, info);
+        Some(info)
+    }
+
+    /// Finds the shortest shelf no taller than `max_height` with room for a slot of the given size.
+    /// Returns the index of the shelf and the index of the free span on that shelf.
+    fn find_shelf(&self, slot_w: u32, slot_h: u32, max_height: u32) -> Option<(usize, usize)> {
+        self.shelves
+            .iter()
+            .enumerate()
+            .filter(|(_, shelf)| shelf.height >= slot_h && shelf.height <= max_height)
+            .filter_map(|(i, shelf)| shelf.best_span(slot_w).map(|span| (i, span)))
+            .min_by_key(|(i, _)| self.shelves[*i].height)
+    }
+
+    /// Opens a new shelf at the bottom of the atlas, if there is room. Returns its index.
+    fn push_shelf(&mut self, height: u32) -> Option<usize> {
+        let y = match self.shelves.last() {
+            Some(shelf) => shelf.y + shelf.height,
+            None => self.reserved_height + self.padding,
+        };
+        if y + height > self.height {
+            return None;
+        }
+        self.shelves
+            .push(Shelf::new(y, height, self.width, self.padding));
+        Some(self.shelves.len() - 1)
+    }
+
+    /// Removes the image with the given name, freeing its space for later images.
+    /// Returns where the image used to be.
+    pub fn remove(&mut self, name: &str) -> Option<TextureRegionInformation> {
+        let info = self.frames.remove(name)?;
+        let frame = info.frame;
+        if let Some(shelf) = self.shelves.iter_mut().find(|shelf| shelf.y == frame.y) {
+            shelf.deallocate(frame.x, frame.w + self.padding);
+        }
+
+        // Empty shelves at the bottom can be removed entirely, so that their space can be used for a shelf of a different height.
+        while let Some(shelf) = self.shelves.last() {
+            if shelf.is_empty(self.width, self.padding) {
+                self.shelves.pop();
+            } else {
+                break;
+            }
+        }
+        Some(info)
+    }
+
+    /// Retrieves the region of the image with the given name.
+    pub fn get(&self, name: &str) -> Option<&TextureRegionInformation> {
+        self.frames.get(name)
+    }
+
+    /// All of the regions in this atlas, addressable by name.
+    pub fn frames(&self) -> &HashMap<String, TextureRegionInformation> {
+        &self.frames
+    }
+
+    /// Creates a snapshot of the current regions in this atlas, as a page backed by the given image file.
+    pub fn to_page(&self, image: String) -> TextureAtlasPage {
+        TextureAtlasPage {
+            image,
+            width: self.width,
+            height: self.height,
+            frames: self.frames.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlaps(a: Rect, b: Rect) -> bool {
+        a.x < b.x + b.w && b.x < a.x + a.w && a.y < b.y + b.h && b.y < a.y + a.h
+    }
+
+    #[test]
+    fn packed_regions_do_not_overlap() {
+        let mut atlas = DynamicTextureAtlas::new(128, 128, 2);
+        let mut inserted = 0;
+        for i in 0..100 {
+            if atlas
+                .insert(i.to_string(), 5 + i % 7 * 3, 4 + i % 5 * 4)
+                .is_some()
+            {
+                inserted += 1;
+            }
+        }
+        assert!(inserted > 20);
+
+        let frames = atlas
+            .frames()
+            .values()
+            .map(|info| info.frame)
+            .collect::<Vec<_>>();
+        for (i, a) in frames.iter().enumerate() {
+            assert!(a.x >= 2 && a.y >= 2 && a.x + a.w <= 126 && a.y + a.h <= 126);
+            for b in &frames[i + 1..] {
+                assert!(!overlaps(*a, *b), "{:?} overlaps {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn removing_regions_frees_space() {
+        let mut atlas = DynamicTextureAtlas::new(64, 64, 0);
+        for i in 0..4 {
+            assert!(atlas.insert(i.to_string(), 32, 32).is_some());
+        }
+        assert!(atlas.insert("full".to_string(), 32, 32).is_none());
+
+        // Freeing the bottom shelf lets us reuse it for images of a different height.
+        let removed = atlas.remove("2").unwrap();
+        atlas.remove("3");
+        let region = atlas.insert("short".to_string(), 64, 16).unwrap();
+        assert_eq!((region.frame.x, region.frame.y), (0, removed.frame.y));
+        assert!(atlas.get("2").is_none());
+    }
+
+    #[test]
+    fn images_too_wide_for_the_atlas_take_no_space() {
+        let mut atlas = DynamicTextureAtlas::new(64, 64, 0);
+        assert!(atlas.insert("wide".to_string(), 65, 48).is_none());
+        let region = atlas.insert("full".to_string(), 64, 64).unwrap();
+        assert_eq!((region.frame.x, region.frame.y), (0, 0));
+    }
+
+    #[test]
+    fn images_are_packed_below_an_existing_atlas() {
+        let mut prebuilt = DynamicTextureAtlas::new(64, 32, 0);
+        prebuilt.insert("button.png".to_string(), 16, 16);
+
+        let mut atlas = DynamicTextureAtlas::from_page(prebuilt.to_page(String::new()), 64, 64, 0);
+        let region = atlas.insert("avatar".to_string(), 16, 16).unwrap();
+        assert_eq!(region.frame.y, 32);
+        assert!(atlas.get("button.png").is_some());
+    }
+}
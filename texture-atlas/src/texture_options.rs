@@ -0,0 +1,160 @@
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+/// How the image for a texture is processed before it is uploaded to the graphics card.
+/// Images are RGBA with eight bits per channel, and their colours are in the sRGB colour space.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TextureOptions {
+    /// Whether to generate successively smaller copies of the image, which are sampled when the texture is drawn
+    /// smaller than its actual size so that it doesn't shimmer.
+    pub mipmaps: bool,
+    /// Whether to multiply the colour of each pixel by its alpha, so that transparent pixels don't darken
+    /// the edges of sprites when the texture is filtered.
+    pub premultiply_alpha: bool,
+}
+
+/// One level of a mip chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MipLevel {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// Converts an eight-bit sRGB colour channel into a linear intensity between zero and one.
+pub fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts a linear intensity between zero and one into an eight-bit sRGB colour channel.
+pub fn linear_to_srgb(value: f32) -> u8 {
+    let value = if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (value * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+/// Multiplies the colour of each RGBA pixel by its alpha. This is done in linear space, since that's where the
+/// graphics card blends, and the result is converted back to sRGB.
+pub fn premultiply_alpha(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let alpha = pixel[3] as f32 / 255.0;
+        for channel in &mut pixel[0..3] {
+            *channel = linear_to_srgb(srgb_to_linear(*channel) * alpha);
+        }
+    }
+}
+
+/// Generates the mip chain for an RGBA image. Each level is half the size of the level before, rounded down,
+/// until the last level is a single pixel. The image itself is not included.
+///
+/// Each pixel is the average of the two by two block of pixels in the level above, taken in linear space.
+/// When a level has an odd width or height, its last column or row is folded into the last pixel of the next level,
+/// which then averages three pixels along that axis instead of two. If the alpha of the image is not premultiplied, colours of transparent pixels bleed into their neighbours.
+pub fn generate_mipmaps(width: u32, height: u32, pixels: &[u8]) -> Vec<MipLevel> {
+    let mut levels: Vec<MipLevel> = Vec::new();
+    let (mut width, mut height) = (width, height);
+    while width > 1 || height > 1 {
+        let source = levels
+            .last()
+            .map(|level| &level.pixels[..])
+            .unwrap_or(pixels);
+        let (next_width, next_height) = ((width / 2).max(1), (height / 2).max(1));
+        let mut next = Vec::with_capacity((next_width * next_height * 4) as usize);
+        for y in 0..next_height {
+            for x in 0..next_width {
+                let columns = footprint(x, width, next_width);
+                let rows = footprint(y, height, next_height);
+                let count = (columns.len() * rows.len()) as f32;
+                let mut sum = [0.0; 4];
+                for sy in rows {
+                    for sx in columns.clone() {
+                        let index = ((sy * width + sx) * 4) as usize;
+                        let sample = &source[index..index + 4];
+                        for channel in 0..3 {
+                            sum[channel] += srgb_to_linear(sample[channel]);
+                        }
+                        sum[3] += sample[3] as f32 / 255.0;
+                    }
+                }
+                for (channel, sum) in sum.iter().enumerate() {
+                    next.push(if channel < 3 {
+                        linear_to_srgb(sum / count)
+                    } else {
+                        (sum / count * 255.0).round() as u8
+                    });
+                }
+            }
+        }
+        levels.push(MipLevel {
+            width: next_width,
+            height: next_height,
+            pixels: next,
+        });
+        width = next_width;
+        height = next_height;
+    }
+    levels
+}
+
+/// The pixels along one axis of a level that are averaged into the pixel at `index` of the next level.
+/// The last pixel also takes the leftover pixel of an odd size, so that no pixel of the level is skipped.
+fn footprint(index: u32, size: u32, next_size: u32) -> Range<u32> {
+    let start = 2 * index;
+    if index + 1 == next_size {
+        start..size
+    } else {
+        start..start + 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn premultiplying_works_in_linear_space() {
+        let mut pixels = vec![255, 255, 255, 255, 255, 128, 0, 128, 255, 255, 255, 0];
+        premultiply_alpha(&mut pixels);
+        // Half of full brightness in linear space is 188 in sRGB, not 128.
+        assert_eq!(
+            pixels,
+            vec![255, 255, 255, 255, 188, 93, 0, 128, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn mip_chain_halves_down_to_one_pixel() {
+        // A five by three checkerboard of black and white pixels.
+        let pixels = (0..15)
+            .flat_map(|i| {
+                let value = if i % 2 == 0 { 255 } else { 0 };
+                vec![value, value, value, 255]
+            })
+            .collect::<Vec<_>>();
+        let levels = generate_mipmaps(5, 3, &pixels);
+        let sizes = levels
+            .iter()
+            .map(|level| (level.width, level.height))
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![(2, 1), (1, 1)]);
+        assert_eq!(&levels[0].pixels[0..4], &[188, 188, 188, 255]);
+        // The last pixel also covers the fifth column, so five of its nine pixels are white.
+        assert_eq!(&levels[0].pixels[4..8], &[197, 197, 197, 255]);
+
+        // A single white pixel in the last column of an odd width still contributes a third of the result.
+        let pixels = [0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255, 255];
+        let levels = generate_mipmaps(3, 1, &pixels);
+        assert_eq!(levels.len(), 1);
+        assert_eq!(levels[0].pixels, vec![156, 156, 156, 255]);
+    }
+}
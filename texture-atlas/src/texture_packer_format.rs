@@ -0,0 +1,275 @@
+//! Import and export of the JSON formats written by TexturePacker and similar external packing tools,
+//! so that atlases can be packed outside of our build script.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{Pivot, Rect, TextureAtlasPage, TextureRegionInformation};
+
+/// How the frames are laid out in a TexturePacker JSON file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TexturePackerFormat {
+    /// `frames` is an object, mapping each file name to its frame.
+    Hash,
+    /// `frames` is an array, and each frame contains its file name.
+    Array,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TexturePackerFile {
+    frames: TexturePackerFrames,
+    meta: TexturePackerMeta,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum TexturePackerFrames {
+    Hash(BTreeMap<String, TexturePackerFrame>),
+    Array(Vec<NamedTexturePackerFrame>),
+}
+
+#[derive(Serialize, Deserialize)]
+struct NamedTexturePackerFrame {
+    filename: String,
+    #[serde(flatten)]
+    frame: TexturePackerFrame,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TexturePackerFrame {
+    /// Where the sprite is in the image. If the sprite was rotated, the width and height are from before it was rotated.
+    frame: Rect,
+    rotated: bool,
+    trimmed: bool,
+    /// The part of the source texture that was kept after trimming.
+    sprite_source_size: Rect,
+    source_size: Size,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pivot: Option<Pivot>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TexturePackerMeta {
+    image: String,
+    size: Size,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    app: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    format: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Size {
+    w: u32,
+    h: u32,
+}
+
+impl From<TexturePackerFrame> for TextureRegionInformation {
+    fn from(tp: TexturePackerFrame) -> Self {
+        let mut frame = tp.frame;
+        if tp.rotated {
+            // Our frames describe the area of the image that the sprite takes up after rotation.
+            std::mem::swap(&mut frame.w, &mut frame.h);
+        }
+        Self {
+            frame,
+            rotated: tp.rotated,
+            trimmed: tp.trimmed,
+            source: Rect {
+                x: tp.sprite_source_size.x,
+                y: tp.sprite_source_size.y,
+                w: tp.source_size.w,
+                h: tp.source_size.h,
+            },
+            nine_patch: None,
+            pivot: tp.pivot,
+        }
+    }
+}
+
+impl From<&TextureRegionInformation> for TexturePackerFrame {
+    fn from(info: &TextureRegionInformation) -> Self {
+        let mut frame = info.frame;
+        if info.rotated {
+            std::mem::swap(&mut frame.w, &mut frame.h);
+        }
+        Self {
+            frame,
+            rotated: info.rotated,
+            trimmed: info.trimmed,
+            sprite_source_size: Rect {
+                x: info.source.x,
+                y: info.source.y,
+                w: frame.w,
+                h: frame.h,
+            },
+            source_size: Size {
+                w: info.source.w,
+                h: info.source.h,
+            },
+            pivot: info.pivot,
+        }
+    }
+}
+
+impl TextureAtlasPage {
+    /// Reads a page from a JSON file written by TexturePacker, in either the hash or array format.
+    ///
+    /// The format has no way to describe nine-patch margins, so the regions will not have any.
+    pub fn from_texture_packer_json(json: &str) -> serde_json::Result<Self> {
+        let file: TexturePackerFile = serde_json::from_str(json)?;
+        let frames = match file.frames {
+            TexturePackerFrames::Hash(frames) => frames
+                .into_iter()
+                .map(|(name, frame)| (name, frame.into()))
+                .collect(),
+            TexturePackerFrames::Array(frames) => frames
+                .into_iter()
+                .map(|named| (named.filename, named.frame.into()))
+                .collect::<HashMap<_, _>>(),
+        };
+        Ok(Self {
+            image: file.meta.image,
+            width: file.meta.size.w,
+            height: file.meta.size.h,
+            frames,
+        })
+    }
+
+    /// Writes this page as JSON in one of TexturePacker's formats, so that it can be read by other tools.
+    /// The frames are sorted by name.
+    ///
+    /// Nine-patch margins are not written, since the format has no way to describe them.
+    pub fn to_texture_packer_json(&self, format: TexturePackerFormat) -> String {
+        let mut names = self.frames.keys().collect::<Vec<_>>();
+        names.sort();
+        let frames = match format {
+            TexturePackerFormat::Hash => TexturePackerFrames::Hash(
+                names
+                    .into_iter()
+                    .map(|name| (name.clone(), (&self.frames[name]).into()))
+                    .collect(),
+            ),
+            TexturePackerFormat::Array => TexturePackerFrames::Array(
+                names
+                    .into_iter()
+                    .map(|name| NamedTexturePackerFrame {
+                        filename: name.clone(),
+                        frame: (&self.frames[name]).into(),
+                    })
+                    .collect(),
+            ),
+        };
+        let file = TexturePackerFile {
+            frames,
+            meta: TexturePackerMeta {
+                image: self.image.clone(),
+                size: Size {
+                    w: self.width,
+                    h: self.height,
+                },
+                app: Some(env!("CARGO_PKG_NAME").to_string()),
+                format: Some("RGBA8888".to_string()),
+            },
+        };
+        serde_json::to_string_pretty(&file).expect("could not serialise atlas")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page() -> TextureAtlasPage {
+        let mut frames = HashMap::new();
+        frames.insert(
+            "plain.png".to_string(),
+            TextureRegionInformation {
+                frame: Rect {
+                    x: 2,
+                    y: 2,
+                    w: 16,
+                    h: 8,
+                },
+                rotated: false,
+                trimmed: false,
+                source: Rect {
+                    x: 0,
+                    y: 0,
+                    w: 16,
+                    h: 8,
+                },
+                nine_patch: None,
+                pivot: None,
+            },
+        );
+        frames.insert(
+            "rotated_trimmed.png".to_string(),
+            TextureRegionInformation {
+                frame: Rect {
+                    x: 20,
+                    y: 2,
+                    w: 4,
+                    h: 6,
+                },
+                rotated: true,
+                trimmed: true,
+                source: Rect {
+                    x: 2,
+                    y: 1,
+                    w: 10,
+                    h: 8,
+                },
+                nine_patch: None,
+                pivot: Some(Pivot { x: 0.5, y: 1.0 }),
+            },
+        );
+        TextureAtlasPage {
+            image: "sheet.png".to_string(),
+            width: 32,
+            height: 16,
+            frames,
+        }
+    }
+
+    #[test]
+    fn round_trips_through_both_formats() {
+        for format in [TexturePackerFormat::Hash, TexturePackerFormat::Array].iter() {
+            let json = page().to_texture_packer_json(*format);
+            assert_eq!(
+                TextureAtlasPage::from_texture_packer_json(&json).unwrap(),
+                page()
+            );
+        }
+    }
+
+    #[test]
+    fn reads_texture_packer_output() {
+        let json = r#"{
+            "frames": [{
+                "filename": "rotated_trimmed.png",
+                "frame": {"x": 20, "y": 2, "w": 6, "h": 4},
+                "rotated": true,
+                "trimmed": true,
+                "spriteSourceSize": {"x": 2, "y": 1, "w": 6, "h": 4},
+                "sourceSize": {"w": 10, "h": 8},
+                "pivot": {"x": 0.5, "y": 1}
+            }],
+            "meta": {
+                "app": "https://www.codeandweb.com/texturepacker",
+                "version": "1.0",
+                "image": "sheet.png",
+                "format": "RGBA8888",
+                "size": {"w": 32, "h": 16},
+                "scale": "1"
+            }
+        }"#;
+        let page = TextureAtlasPage::from_texture_packer_json(json).unwrap();
+        assert_eq!(page.image, "sheet.png");
+        assert_eq!(
+            page.frames["rotated_trimmed.png"],
+            self::page().frames["rotated_trimmed.png"]
+        );
+    }
+}
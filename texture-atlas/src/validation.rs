@@ -0,0 +1,326 @@
+use std::fmt::{Display, Formatter};
+
+use crate::{Rect, TextureAtlas};
+
+/// A problem with a texture atlas, found by `TextureAtlas::validate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AtlasError {
+    /// The region has no width or no height.
+    ZeroSized { page: usize, region: String },
+    /// The region extends past the edge of its page.
+    OutOfBounds { page: usize, region: String },
+    /// The two regions share some pixels on the same page.
+    Overlapping {
+        page: usize,
+        first: String,
+        second: String,
+    },
+    /// The region's source rectangle doesn't agree with its frame and whether it was trimmed.
+    InconsistentTrim { page: usize, region: String },
+    /// The region's nine-patch margins don't fit inside its source texture.
+    InvalidNinePatch { page: usize, region: String },
+    /// An animation refers to a region that isn't in the atlas.
+    MissingAnimationFrame { animation: String, region: String },
+}
+
+impl Display for AtlasError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AtlasError::ZeroSized { page, region } => {
+                write!(f, "region {} on page {} has no size", region, page)
+            }
+            AtlasError::OutOfBounds { page, region } => {
+                write!(f, "region {} extends outside page {}", region, page)
+            }
+            AtlasError::Overlapping {
+                page,
+                first,
+                second,
+            } => write!(
+                f,
+                "regions {} and {} overlap on page {}",
+                first, second, page
+            ),
+            AtlasError::InconsistentTrim { page, region } => write!(
+                f,
+                "region {} on page {} has inconsistent trim data",
+                region, page
+            ),
+            AtlasError::InvalidNinePatch { page, region } => write!(
+                f,
+                "nine-patch margins of region {} on page {} are larger than the region",
+                region, page
+            ),
+            AtlasError::MissingAnimationFrame { animation, region } => write!(
+                f,
+                "animation {} refers to missing region {}",
+                animation, region
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AtlasError {}
+
+/// Whether `start + length` is no more than `limit`. Corrupt atlases can have values so large that the sum overflows,
+/// which counts as going past the limit.
+fn fits(start: u32, length: u32, limit: u32) -> bool {
+    matches!(start.checked_add(length), Some(end) if end <= limit)
+}
+
+/// The coordinate just past the end of a span, widened so that it can't overflow.
+fn end(start: u32, length: u32) -> u64 {
+    start as u64 + length as u64
+}
+
+fn overlaps(a: Rect, b: Rect) -> bool {
+    (a.x as u64) < end(b.x, b.w)
+        && (b.x as u64) < end(a.x, a.w)
+        && (a.y as u64) < end(b.y, b.h)
+        && (b.y as u64) < end(a.y, a.h)
+}
+
+impl TextureAtlas {
+    /// Checks that the atlas makes sense, returning every problem that was found.
+    pub fn validate(&self) -> Result<(), Vec<AtlasError>> {
+        let mut errors = Vec::new();
+
+        for (page_index, page) in self.pages.iter().enumerate() {
+            // Sort the regions by name, so that the errors come out in a consistent order.
+            let mut regions = page.frames.iter().collect::<Vec<_>>();
+            regions.sort_by_key(|(name, _)| *name);
+
+            for (name, info) in &regions {
+                let region = || name.to_string();
+                let frame = info.frame;
+                if frame.w == 0 || frame.h == 0 {
+                    errors.push(AtlasError::ZeroSized {
+                        page: page_index,
+                        region: region(),
+                    });
+                }
+                if !fits(frame.x, frame.w, page.width) || !fits(frame.y, frame.h, page.height) {
+                    errors.push(AtlasError::OutOfBounds {
+                        page: page_index,
+                        region: region(),
+                    });
+                }
+
+                let (w, h) = info.unrotated_size();
+                let source = info.source;
+                let consistent = if info.trimmed {
+                    fits(source.x, w, source.w) && fits(source.y, h, source.h)
+                } else {
+                    source.x == 0 && source.y == 0 && source.w == w && source.h == h
+                };
+                if !consistent {
+                    errors.push(AtlasError::InconsistentTrim {
+                        page: page_index,
+                        region: region(),
+                    });
+                }
+
+                if let Some(margins) = info.nine_patch {
+                    if !fits(margins.left, margins.right, source.w)
+                        || !fits(margins.top, margins.bottom, source.h)
+                    {
+                        errors.push(AtlasError::InvalidNinePatch {
+                            page: page_index,
+                            region: region(),
+                        });
+                    }
+                }
+            }
+
+            // Sweep from left to right, so we only need to compare regions that share some columns.
+            regions.sort_by_key(|(_, info)| info.frame.x);
+            for (i, (first, a)) in regions.iter().enumerate() {
+                for (second, b) in &regions[i + 1..] {
+                    if b.frame.x as u64 >= end(a.frame.x, a.frame.w) {
+                        break;
+                    }
+                    if overlaps(a.frame, b.frame) {
+                        let (first, second) = if first < second {
+                            (first, second)
+                        } else {
+                            (second, first)
+                        };
+                        errors.push(AtlasError::Overlapping {
+                            page: page_index,
+                            first: first.to_string(),
+                            second: second.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+
+        let mut animations = self.animations.iter().collect::<Vec<_>>();
+        animations.sort_by_key(|(name, _)| *name);
+        for (name, animation) in animations {
+            for frame in &animation.frames {
+                if self.find(&frame.region).is_none() {
+                    errors.push(AtlasError::MissingAnimationFrame {
+                        animation: name.clone(),
+                        region: frame.region.clone(),
+                    });
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// If there is no region with the given name, finds the region with the most similar name, if any are similar enough.
+    /// This is useful for "did you mean" suggestions when a region name is misspelt.
+    pub fn suggest_region(&self, name: &str) -> Option<&str> {
+        suggest_name(
+            name,
+            self.pages
+                .iter()
+                .flat_map(|page| page.frames.keys().map(String::as_str)),
+        )
+    }
+}
+
+/// Finds the candidate most similar to the given name, if any are similar enough to be a likely misspelling.
+pub fn suggest_name<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(2);
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// The Levenshtein distance between two strings: the number of single-character insertions, deletions
+/// and substitutions needed to turn one into the other.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    let mut current = vec![0; b.len() + 1];
+    for (i, a_char) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + if a_char == *b_char { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TextureAtlasPage, TextureOptions, TextureRegionInformation};
+    use std::collections::HashMap;
+
+    fn region(x: u32, y: u32, w: u32, h: u32) -> TextureRegionInformation {
+        TextureRegionInformation {
+            frame: Rect { x, y, w, h },
+            rotated: false,
+            trimmed: false,
+            source: Rect { x: 0, y: 0, w, h },
+            nine_patch: None,
+            pivot: None,
+        }
+    }
+
+    #[test]
+    fn validation_finds_problems() {
+        let mut frames = HashMap::new();
+        frames.insert("a.png".to_string(), region(0, 0, 8, 8));
+        frames.insert("b.png".to_string(), region(4, 4, 8, 8));
+        frames.insert("c.png".to_string(), region(12, 0, 8, 8));
+        frames.insert("d.png".to_string(), region(30, 0, 0, 4));
+        let mut trimmed = region(20, 8, 8, 8);
+        trimmed.trimmed = true;
+        trimmed.source = Rect {
+            x: 4,
+            y: 0,
+            w: 10,
+            h: 8,
+        };
+        frames.insert("e.png".to_string(), trimmed);
+        let atlas = TextureAtlas {
+            pages: vec![TextureAtlasPage {
+                image: "atlas_0.png".to_string(),
+                width: 16,
+                height: 16,
+                frames,
+            }],
+            animations: HashMap::new(),
+            texture_options: TextureOptions::default(),
+        };
+
+        let errors = atlas.validate().unwrap_err();
+        let expected = vec![
+            AtlasError::OutOfBounds {
+                page: 0,
+                region: "c.png".to_string(),
+            },
+            AtlasError::ZeroSized {
+                page: 0,
+                region: "d.png".to_string(),
+            },
+            AtlasError::OutOfBounds {
+                page: 0,
+                region: "d.png".to_string(),
+            },
+            AtlasError::OutOfBounds {
+                page: 0,
+                region: "e.png".to_string(),
+            },
+            AtlasError::InconsistentTrim {
+                page: 0,
+                region: "e.png".to_string(),
+            },
+            AtlasError::Overlapping {
+                page: 0,
+                first: "a.png".to_string(),
+                second: "b.png".to_string(),
+            },
+        ];
+        assert_eq!(errors, expected);
+
+        assert_eq!(atlas.suggest_region("b.pgn"), Some("b.png"));
+
+        // Sizes so large that adding them to the position overflows are out of bounds, rather than panicking.
+        let mut frames = HashMap::new();
+        frames.insert("huge.png".to_string(), region(8, u32::MAX - 4, 8, 8));
+        frames.insert("a.png".to_string(), region(0, 0, u32::MAX, 8));
+        let atlas = TextureAtlas {
+            pages: vec![TextureAtlasPage {
+                image: "atlas_0.png".to_string(),
+                width: 16,
+                height: 16,
+                frames,
+            }],
+            animations: HashMap::new(),
+            texture_options: TextureOptions::default(),
+        };
+        assert_eq!(
+            atlas.validate(),
+            Err(vec![
+                AtlasError::OutOfBounds {
+                    page: 0,
+                    region: "a.png".to_string(),
+                },
+                AtlasError::OutOfBounds {
+                    page: 0,
+                    region: "huge.png".to_string(),
+                },
+            ])
+        );
+        assert_eq!(atlas.suggest_region("button.png"), None);
+    }
+}